@@ -3,16 +3,109 @@
 extern crate clap;
 extern crate env_logger;
 extern crate nom;
+extern crate serde;
 extern crate serde_json;
+extern crate serde_yaml;
 
 extern crate syslinux_conf;
 
-fn main() {
-    env_logger::init().unwrap();
+const STRUCTURED_FORMATS: [&'static str; 4] =
+    ["json", "json-pretty", "yaml", "toml"];
 
-    let matches = clap::App::new("syslinux-tool")
-        .about("Converts syslinux configuration file into JSON")
-        .version(env!("CARGO_PKG_VERSION"))
+fn print_diagnostics(diagnostics: &[syslinux_conf::Diagnostic]) {
+    use std::io::Write;
+    let mut stderr = std::io::stderr();
+    for diagnostic in diagnostics {
+        let _ = writeln!(stderr, "{}", diagnostic);
+    }
+}
+
+fn has_errors(diagnostics: &[syslinux_conf::Diagnostic]) -> bool {
+    diagnostics.iter().any(
+        |diagnostic| diagnostic.severity == syslinux_conf::Severity::Error)
+}
+
+// All formats except "cfg".
+fn serialize<T: serde::Serialize>(data: &T, format: &str) -> String {
+    let result = match format {
+        "json" => serde_json::to_string(data).map_err(|err| format!("{}", err)),
+        "json-pretty" => {
+            serde_json::to_string_pretty(data).map_err(|err| format!("{}", err))
+        },
+        "yaml" => serde_yaml::to_string(data).map_err(|err| format!("{}", err)),
+        "toml" => syslinux_conf::to_toml(data),
+        _ => panic!("This will never happen"),
+    };
+
+    match result {
+        Ok(mut output) => {
+            if !output.ends_with('\n') && format != "json" {
+                output.push('\n');
+            }
+            output
+        },
+        Err(err) => {
+            error!("Unable to serialize output as {}: {}", format, err);
+            std::process::exit(1)
+        },
+    }
+}
+
+fn write_output(output: String) {
+    use std::io::Write;
+    match std::io::stdout().write_all(&output.into_bytes()[..]) {
+        Ok(_) => (),
+        Err(_) => {
+            error!("Unable to write output to stdout");
+            std::process::exit(1)
+        },
+    };
+}
+
+fn read_file(path: &str) -> String {
+    use std::io::Read;
+
+    let mut text = String::new();
+    let result = match path {
+        "-" => std::io::stdin().read_to_string(&mut text),
+        _ => std::fs::File::open(path).and_then(
+            |mut file| file.read_to_string(&mut text)),
+    };
+    match result {
+        Ok(_) => text,
+        Err(err) => {
+            error!("Unable to read {}: {}", path, err);
+            std::process::exit(1)
+        },
+    }
+}
+
+// Reads configuration in the same JSON format as produced by this tool.
+fn read_json(json_path: &str) -> syslinux_conf::SyslinuxConf {
+    let conf: syslinux_conf::SyslinuxConf =
+        match serde_json::from_str(&read_file(json_path)) {
+            Ok(conf) => conf,
+            Err(err) => {
+                error!("Unable to deserialize syslinux configuration: {}",
+                       err);
+                std::process::exit(1)
+            },
+        };
+
+    let diagnostics = syslinux_conf::validate(&conf);
+    print_diagnostics(&diagnostics);
+    if has_errors(&diagnostics) {
+        error!("Invalid syslinux configuration");
+        std::process::exit(1)
+    }
+
+    conf
+}
+
+// Arguments of all subcommands which read configuration from the boot
+// device.
+fn conf_args<'a, 'b>(app: clap::App<'a, 'b>) -> clap::App<'a, 'b> {
+    app
         .arg(clap::Arg::with_name("type")
             .help("Type of syslinux configuration. Only for autodetect.")
             .short("t")
@@ -28,13 +121,84 @@ fn main() {
             .help("Path to the configuration file. Will be autodetected if \
                    omitted.")
             .index(2))
+        .arg(clap::Arg::with_name("dialect")
+            .help("Dialect of configuration file.")
+            .short("d")
+            .long("dialect")
+            .value_name("DIALECT")
+            .takes_value(true)
+            .default_value("syslinux")
+            .possible_values(&["syslinux", "uboot"]))
+        .arg(clap::Arg::with_name("duplicate-labels")
+            .help("Which of labels with the same name is used.")
+            .long("duplicate-labels")
+            .value_name("POLICY")
+            .takes_value(true)
+            .default_value("first")
+            .possible_values(&["first", "last", "error"]))
+        .arg(clap::Arg::with_name("encoding")
+            .help("Encoding of configuration file.")
+            .long("encoding")
+            .value_name("ENCODING")
+            .takes_value(true)
+            .default_value("utf8")
+            .possible_values(&["utf8", "latin1", "cp437"]))
+        .arg(clap::Arg::with_name("strict")
+            .help("Report unknown tags, tags in wrong scope and invalid \
+                   values as errors.")
+            .long("strict"))
+        .arg(clap::Arg::with_name("detect")
+            .help("Detect type of kernel files by their contents.")
+            .long("detect"))
         .group(clap::ArgGroup::with_name("detection")
             .arg("type")
             .arg("CONF FILE PATH"))
-        .get_matches();
+}
 
-    let root_dir = matches.value_of("ROOT DIR").unwrap();
-    let root_dir = std::path::PathBuf::from(root_dir);
+fn format_arg<'a, 'b>(default: &'a str) -> clap::Arg<'a, 'b> {
+    clap::Arg::with_name("format")
+        .help("Output format.")
+        .short("f")
+        .long("format")
+        .value_name("FORMAT")
+        .takes_value(true)
+        .default_value(default)
+}
+
+fn get_dialect(matches: &clap::ArgMatches) -> syslinux_conf::Dialect {
+    match matches.value_of("dialect") {
+        Some("uboot") => syslinux_conf::Dialect::UBoot,
+        _ => syslinux_conf::Dialect::Syslinux,
+    }
+}
+
+fn get_parser_options(matches: &clap::ArgMatches)
+        -> syslinux_conf::ParserOptions {
+    let duplicate_labels = match matches.value_of("duplicate-labels") {
+        Some("last") => syslinux_conf::DuplicateLabels::LastWins,
+        Some("error") => syslinux_conf::DuplicateLabels::Error,
+        _ => syslinux_conf::DuplicateLabels::FirstWins,
+    };
+    let encoding = match matches.value_of("encoding") {
+        Some("latin1") => syslinux_conf::Encoding::Latin1,
+        Some("cp437") => syslinux_conf::Encoding::CP437,
+        _ => syslinux_conf::Encoding::UTF8,
+    };
+    syslinux_conf::ParserOptions{
+        dialect: get_dialect(matches),
+        duplicate_labels: duplicate_labels,
+        strict: matches.is_present("strict"),
+        encoding: encoding,
+    }
+}
+
+fn get_root_dir(matches: &clap::ArgMatches) -> std::path::PathBuf {
+    std::path::PathBuf::from(matches.value_of("ROOT DIR").unwrap())
+}
+
+fn create_reader(matches: &clap::ArgMatches)
+        -> Result<syslinux_conf::Reader, syslinux_conf::ReaderError> {
+    let root_dir = get_root_dir(matches);
 
     let reader = match matches.value_of("CONF FILE PATH") {
         Some(conf_path) => {
@@ -62,38 +226,874 @@ fn main() {
         }
     };
 
-    let reader = match reader {
+    reader.map(|reader| {
+        reader
+            .with_inspect_options(syslinux_conf::InspectOptions{
+                detect_kernel_files: matches.is_present("detect"),
+                ..syslinux_conf::InspectOptions::default()
+            })
+            .with_parser_options(get_parser_options(matches))
+    })
+}
+
+fn open_reader(matches: &clap::ArgMatches) -> syslinux_conf::Reader {
+    match create_reader(matches) {
         Ok(reader) => reader,
-        Err(_) => {
-            // TODO: Log actual reason.
-            error!("Unable to create syslinux configuration reader");
+        Err(err) => {
+            error!("Unable to create syslinux configuration reader: {}", err);
             std::process::exit(1)
         },
+    }
+}
+
+fn log_diagnostics(diagnostics: &[syslinux_conf::Diagnostic]) {
+    for diagnostic in diagnostics {
+        match diagnostic.severity {
+            syslinux_conf::Severity::Info => info!("{}", diagnostic),
+            _ => warn!("{}", diagnostic),
+        }
+    }
+}
+
+// Raw configuration has paths as they are written in the file.
+fn read_conf_with_diagnostics(reader: &syslinux_conf::Reader, raw: bool)
+        -> (syslinux_conf::SyslinuxConf, Vec<syslinux_conf::Diagnostic>) {
+    let result = match raw {
+        true => reader.read_raw_with_diagnostics(),
+        false => reader.read_with_diagnostics(),
     };
 
-    let data = match reader.read() {
+    match result {
+        Ok(result) => result,
+        Err(err) => exit_on_reader_error(err),
+    }
+}
+
+// Diagnostics reported before parser stopped, e.g. the duplicate label it
+// stopped at, are printed too.
+fn exit_on_reader_error(err: syslinux_conf::ReaderError) -> ! {
+    print_diagnostics(err.diagnostics());
+    error!("Unable to read syslinux configuration: {}", err);
+    std::process::exit(1)
+}
+
+fn read_conf(reader: &syslinux_conf::Reader, raw: bool)
+        -> syslinux_conf::SyslinuxConf {
+    let (conf, diagnostics) = read_conf_with_diagnostics(reader, raw);
+    log_diagnostics(&diagnostics);
+    conf
+}
+
+fn device_path(root_dir: &std::path::Path, path: &std::path::Path) -> String {
+    let path = syslinux_conf::get_device_path(root_dir, path)
+        .unwrap_or_else(|| path.to_path_buf());
+    format!("{}", path.display())
+}
+
+fn parse_command(matches: &clap::ArgMatches) {
+    let format = matches.value_of("format").unwrap();
+    let envelope = matches.is_present("envelope");
+    if envelope && format == "cfg" {
+        error!("Envelope can not be written in cfg format");
+        std::process::exit(1)
+    }
+
+    let reader = match create_reader(matches) {
+        Ok(reader) => reader,
+        Err(err) => {
+            if envelope {
+                write_output(serialize(
+                    &syslinux_conf::Envelope::from_error(
+                        &get_root_dir(matches), &err),
+                    format));
+            }
+            error!("Unable to create syslinux configuration reader: {}", err);
+            std::process::exit(1)
+        },
+    };
+
+    let reader = reader.with_inspect_options(syslinux_conf::InspectOptions{
+        detect_kernel_files: matches.is_present("detect"),
+        linux_kernels: matches.is_present("inspect"),
+        initrds: matches.is_present("inspect"),
+    });
+
+    let read_raw = format == "cfg" && !matches.is_present("manifest") &&
+                   !matches.is_present("resolve-dtb");
+    let data = match read_raw {
+        true => reader.read_raw_with_diagnostics(),
+        false => reader.read_with_diagnostics(),
+    };
+
+    let (data, diagnostics) = match data {
         Ok(data) => data,
-        Err(_) => {
-            // TODO: Log actual reason.
-            error!("Unable to read syslinux configuration");
+        Err(err) => {
+            error!("Unable to read syslinux configuration: {}", err);
+            if envelope {
+                write_output(serialize(
+                    &syslinux_conf::Envelope::from_reader(&reader, Err(err)),
+                    format));
+            }
             std::process::exit(1)
         },
     };
+    log_diagnostics(&diagnostics);
+    let failed = has_errors(&diagnostics);
 
-    let json = match serde_json::to_string(&data) {
-        Ok(json) => json,
-        Err(_) => {
-            error!("Unable to serialize syslinux configuration as JSON");
+    let output = match (matches.is_present("manifest"),
+                        matches.value_of("resolve-dtb")) {
+        (true, _) => syslinux_conf::build_manifest(
+            &data, reader.root_dir(), &reader.conf_files()).to_text(),
+
+        (false, Some(compatible)) => {
+            use syslinux_conf::ApplyDefaults;
+            data.labels.iter().fold(String::new(), |mut text, (name, label)| {
+                let label = label.clone()
+                    .apply_defaults(&data.global.label_defaults);
+                let dtb = match label.kernel_or_config {
+                    syslinux_conf::KernelOrConfig::Kernel(ref kernel) => {
+                        kernel.resolve_dtb(compatible)
+                    },
+                };
+                let dtb = match dtb {
+                    Some(dtb) => format!("{}", dtb.display()),
+                    None => String::from("-"),
+                };
+                text.push_str(&format!("{}\t{}\n", name, dtb));
+                text
+            })
+        },
+
+        (false, None) => match (envelope, format) {
+            (true, _) => serialize(
+                &syslinux_conf::Envelope::from_reader(
+                    &reader, Ok((data, diagnostics))),
+                format),
+            (false, "cfg") => syslinux_conf::to_syslinux_cfg(&data),
+            (false, _) => serialize(&data, format),
+        },
+    };
+
+    write_output(output);
+    if failed {
+        std::process::exit(1)
+    }
+}
+
+fn check_command(matches: &clap::ArgMatches) {
+    let reader = open_reader(matches);
+    let (conf, mut diagnostics) = read_conf_with_diagnostics(&reader, false);
+
+    diagnostics.extend(syslinux_conf::validate(&conf));
+    diagnostics.extend(syslinux_conf::check_files(&conf));
+    diagnostics.extend(syslinux_conf::check_commands(
+        &conf, |path| reader.resolve_path(path)));
+
+    write_output(diagnostics.iter().fold(String::new(), |mut text, d| {
+        text.push_str(&format!("{}\n", d));
+        text
+    }));
+    if has_errors(&diagnostics) {
+        std::process::exit(1)
+    }
+}
+
+fn boot_command(matches: &clap::ArgMatches) {
+    let conf = read_conf(&open_reader(matches), false);
+    let command = matches.value_of("command").unwrap_or("");
+    let format = matches.value_of("format").unwrap();
+
+    match conf.resolve_boot_command(command, matches.is_present("timeout")) {
+        Some(target) => write_output(serialize(&target, format)),
+        None => {
+            error!("Nothing to boot: empty command and no DEFAULT");
+            std::process::exit(1)
+        },
+    }
+}
+
+fn convert_command(matches: &clap::ArgMatches) {
+    let from = matches.value_of("from").unwrap();
+    let to = matches.value_of("to").unwrap();
+    let root_dir = get_root_dir(matches);
+    // Paths should be resolved for conversion into other boot loaders.
+    let resolve = to == "grub" || to == "bls";
+
+    let conf = match from {
+        "syslinux" => read_conf(&open_reader(matches), !resolve),
+
+        _ => {
+            let conf = match from {
+                "json" => read_json(
+                    matches.value_of("CONF FILE PATH").unwrap_or("-")),
+
+                "grub" => {
+                    let text = read_file(
+                        matches.value_of("CONF FILE PATH").unwrap_or("-"));
+                    let (conf, diagnostics) =
+                        syslinux_conf::from_grub_cfg(&text);
+                    print_diagnostics(&diagnostics);
+                    conf
+                },
+
+                "bls" => match syslinux_conf::from_bls(&root_dir) {
+                    Ok((conf, diagnostics)) => {
+                        print_diagnostics(&diagnostics);
+                        conf
+                    },
+                    Err(err) => {
+                        error!("Unable to read Boot Loader Specification \
+                                entries: {}", err);
+                        std::process::exit(1)
+                    },
+                },
+
+                _ => panic!("This will never happen"),
+            };
+
+            match resolve {
+                true => syslinux_conf::resolve_paths(conf, &root_dir),
+                false => conf,
+            }
+        },
+    };
+
+    let output = match to {
+        "cfg" => syslinux_conf::to_syslinux_cfg(&conf),
+
+        "grub" => {
+            let (text, diagnostics) =
+                syslinux_conf::to_grub_cfg(&conf, &root_dir);
+            print_diagnostics(&diagnostics);
+            text
+        },
+
+        "bls" => {
+            let (bls_conf, diagnostics) =
+                syslinux_conf::to_bls(&conf, &root_dir,
+                                      get_dialect(matches));
+            print_diagnostics(&diagnostics);
+
+            match matches.is_present("write") {
+                true => {
+                    if let Err(err) = syslinux_conf::write_bls(&bls_conf,
+                                                               &root_dir) {
+                        error!("Unable to write Boot Loader Specification \
+                                entries: {}", err);
+                        std::process::exit(1)
+                    }
+                    String::new()
+                },
+
+                false => {
+                    let mut text = String::new();
+                    for entry in &bls_conf.entries {
+                        text.push_str(&format!("# loader/entries/{}\n{}\n",
+                                               entry.file_name,
+                                               entry.contents));
+                    }
+                    text.push_str(&format!("# loader/loader.conf\n{}",
+                                           bls_conf.loader_conf));
+                    text
+                },
+            }
+        },
+
+        _ => serialize(&conf, to),
+    };
+
+    write_output(output);
+}
+
+// Pairs of LABEL and value of option which takes two values.
+fn label_values<'a>(matches: &'a clap::ArgMatches, name: &str)
+        -> Vec<(&'a str, &'a str)> {
+    let values: Vec<&str> = matches.values_of(name)
+        .map_or(Vec::new(), |values| values.collect());
+    values.chunks(2).map(|pair| (pair[0], pair[1])).collect()
+}
+
+// Configuration file of reader together with its source. Invalid UTF-8 is
+// replaced only if it is allowed or encoding is given explicitly.
+fn read_document(reader: &syslinux_conf::Reader, matches: &clap::ArgMatches,
+                 allow_lossy: bool) -> syslinux_conf::Document {
+    let conf_file_path = reader.conf_file_path();
+    let options = get_parser_options(matches);
+    let allow_lossy = allow_lossy || matches.occurrences_of("encoding") > 0;
+
+    let bytes = {
+        use std::io::Read;
+        let mut bytes = Vec::new();
+        match std::fs::File::open(conf_file_path).and_then(
+                |mut file| file.read_to_end(&mut bytes)) {
+            Ok(_) => bytes,
+            Err(err) => {
+                error!("Unable to read {:?}: {}", conf_file_path, err);
+                std::process::exit(1)
+            },
+        }
+    };
+
+    match syslinux_conf::Document::decode(&bytes, &options, allow_lossy) {
+        Ok(document) => document,
+        Err(err) => {
+            error!("Unable to read syslinux configuration: {}", err);
             std::process::exit(1)
         },
+    }
+}
+
+fn lint_command(matches: &clap::ArgMatches) {
+    let reader = open_reader(matches);
+    // Document can not be read past parse error, so the error is reported
+    // the way check does.
+    if let Err(err) = reader.read_raw_with_diagnostics() {
+        exit_on_reader_error(err);
+    }
+    // Lint only reads, so replaced bytes do not matter.
+    let document = read_document(&reader, matches, true);
+
+    let mut linter = syslinux_conf::Linter::default();
+    if let Some(ids) = matches.values_of("disable") {
+        for id in ids {
+            linter.set_enabled(id, false);
+        }
+    }
+    if let Some(ids) = matches.values_of("deny") {
+        for id in ids {
+            linter.set_severity(id, syslinux_conf::Severity::Error);
+        }
+    }
+
+    let diagnostics = linter.lint(&document);
+    print_diagnostics(&diagnostics);
+    if has_errors(&diagnostics) {
+        std::process::exit(1)
+    }
+}
+
+// Every edit is checked by parsing the edited configuration again, so the
+// file is never replaced by something syslinux can not read.
+fn edit_command(matches: &clap::ArgMatches) {
+    let reader = open_reader(matches);
+    let conf_file_path = reader.conf_file_path();
+    let mut document = read_document(&reader, matches, false);
+
+    let exit_on_error = |result: Result<(), syslinux_conf::EditError>| {
+        if let Err(err) = result {
+            error!("Unable to edit syslinux configuration: {}", err);
+            std::process::exit(1)
+        }
     };
 
-    use std::io::Write;
-    match std::io::stdout().write_all(&json.into_bytes()[..]) {
-        Ok(_) => (),
-        Err(_) => {
-            error!("Unable to write JSON to stdout");
+    // New default first, so the old one can be removed.
+    if let Some(default) = matches.value_of("set-default") {
+        exit_on_error(document.set_default(Some(default)));
+    }
+    for (label_name, append) in label_values(matches, "set-append") {
+        exit_on_error(document.set_kernel_field(
+            label_name, syslinux_conf::KernelField::Append, Some(append)));
+    }
+    for (label_name, param) in label_values(matches, "add-param") {
+        exit_on_error(document.add_param(label_name, param));
+    }
+    for (label_name, param) in label_values(matches, "remove-param") {
+        exit_on_error(document.remove_param(label_name, param));
+    }
+    if let Some(label_names) = matches.values_of("remove-label") {
+        for label_name in label_names {
+            exit_on_error(document.remove_label(label_name));
+        }
+    }
+
+    match matches.is_present("print") {
+        true => write_output(document.to_string()),
+        false => if let Err(err) = document.write_to(conf_file_path) {
+            error!("Unable to write {:?}: {}", conf_file_path, err);
             std::process::exit(1)
         },
     };
 }
+
+fn read_conf_file(path: &str, dialect: syslinux_conf::Dialect)
+        -> syslinux_conf::SyslinuxConf {
+    let path = std::path::PathBuf::from(path);
+    // Paths are not resolved, so the root does not matter.
+    let root_dir = match path.parent() {
+        Some(parent) if parent != std::path::Path::new("") => {
+            parent.to_path_buf()
+        },
+        _ => std::path::PathBuf::from("."),
+    };
+
+    let reader = match syslinux_conf::Reader::from_local_conf_file_path(
+            root_dir, path) {
+        Ok(reader) => reader.with_parser_options(syslinux_conf::ParserOptions{
+            dialect: dialect,
+            ..syslinux_conf::ParserOptions::default()
+        }),
+        Err(err) => {
+            error!("Unable to create syslinux configuration reader: {}", err);
+            std::process::exit(1)
+        },
+    };
+    read_conf(&reader, true)
+}
+
+fn diff_command(matches: &clap::ArgMatches) {
+    let dialect = get_dialect(matches);
+    let old = read_conf_file(matches.value_of("OLD").unwrap(), dialect);
+    let new = read_conf_file(matches.value_of("NEW").unwrap(), dialect);
+
+    let conf_diff = syslinux_conf::diff(&old, &new);
+    let output = match matches.value_of("format").unwrap() {
+        "text" => conf_diff.to_text(),
+        format => serialize(&conf_diff, format),
+    };
+    write_output(output);
+
+    // Like diff(1).
+    if !conf_diff.is_empty() {
+        std::process::exit(1)
+    }
+}
+
+fn merge_command(matches: &clap::ArgMatches) {
+    let dialect = get_dialect(matches);
+    let base = read_conf_file(matches.value_of("BASE").unwrap(), dialect);
+    let ours = read_conf_file(matches.value_of("OURS").unwrap(), dialect);
+    let theirs = read_conf_file(matches.value_of("THEIRS").unwrap(), dialect);
+
+    let result = syslinux_conf::merge(&base, &ours, &theirs);
+    let diagnostics: Vec<syslinux_conf::Diagnostic> = result.conflicts.iter()
+        .map(|conflict| conflict.to_diagnostic())
+        .collect();
+    print_diagnostics(&diagnostics);
+
+    let output = match matches.value_of("format").unwrap() {
+        "cfg" => syslinux_conf::to_syslinux_cfg(&result.conf),
+        format => serialize(&result, format),
+    };
+
+    match matches.value_of("output") {
+        // Configuration with conflicts is not written over the real one.
+        Some(output_path) => {
+            if !result.conflicts.is_empty() {
+                error!("Not writing {}: merge has conflicts", output_path);
+                std::process::exit(1)
+            }
+            let written = syslinux_conf::write_file(
+                std::path::Path::new(output_path), output.as_bytes());
+            if let Err(err) = written {
+                error!("Unable to write {}: {}", output_path, err);
+                std::process::exit(1)
+            }
+        },
+        None => write_output(output),
+    };
+
+    if !result.conflicts.is_empty() {
+        std::process::exit(1)
+    }
+}
+
+fn list_command(matches: &clap::ArgMatches) {
+    use syslinux_conf::ApplyDefaults;
+
+    let reader = open_reader(matches);
+    let conf = read_conf(&reader, false);
+
+    let mut rows = vec![[String::from(""), String::from("LABEL"),
+                         String::from("TYPE"), String::from("KERNEL"),
+                         String::from("APPEND")]];
+    for (label_name, label) in conf.labels.iter() {
+        let label = label.clone().apply_defaults(&conf.global.label_defaults);
+        let kernel = match label.kernel_or_config {
+            syslinux_conf::KernelOrConfig::Kernel(kernel) => kernel,
+        };
+        let (kernel_type, kernel_path) = match kernel.kernel_file {
+            Some(ref kernel_file) => (
+                String::from(kernel_file.tag()),
+                device_path(reader.root_dir(), kernel_file.path())),
+            None => (String::from("-"), String::from("-")),
+        };
+
+        rows.push([
+            match conf.global.default.as_ref() == Some(label_name) {
+                true => String::from("*"),
+                false => String::from(""),
+            },
+            label_name.clone(),
+            kernel_type,
+            kernel_path,
+            kernel.append.unwrap_or(String::from("-")),
+        ]);
+    }
+
+    let mut widths = [0; 4];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = std::cmp::max(*width, cell.chars().count());
+        }
+    }
+
+    let output = rows.iter().fold(String::new(), |mut text, row| {
+        for (width, cell) in widths.iter().zip(row.iter()) {
+            text.push_str(&format!("{:1$}  ", cell, width));
+        }
+        text.push_str(&row[4]);
+        text.push('\n');
+        text
+    });
+
+    write_output(output);
+}
+
+// Same as "convert --from json --to cfg".
+fn from_json_command(json_path: &str) {
+    write_output(syslinux_conf::to_syslinux_cfg(&read_json(json_path)))
+}
+
+fn schema_command(matches: &clap::ArgMatches) {
+    write_output(match matches.is_present("envelope") {
+        true => syslinux_conf::envelope_json_schema(),
+        false => String::from(syslinux_conf::json_schema()),
+    })
+}
+
+// Invocation from before subcommands, "syslinux-conf ROOT [CONF]", is parse
+// which detects type of kernel files unless --no-detect is given.
+fn legacy_args(args: Vec<std::ffi::OsString>) -> Vec<std::ffi::OsString> {
+    const NOT_LEGACY: [&'static str; 15] = [
+        "parse", "check", "lint", "boot", "convert", "edit", "diff", "merge",
+        "list", "schema", "help", "-h", "--help", "-V", "--version"];
+
+    let legacy = match args.get(1) {
+        Some(arg) => !NOT_LEGACY.iter().any(|not_legacy| arg == *not_legacy),
+        None => false,
+    };
+    // Options which were replaced by subcommands are handled by main().
+    let replaced = args.iter().any(|arg| {
+        arg == "--schema" ||
+            arg.to_str().map_or(false, |arg| arg.starts_with("--from-json"))
+    });
+    if !legacy || replaced {
+        return args;
+    }
+
+    let no_detect = args.iter().any(|arg| arg == "--no-detect");
+    let mut parse_args = args.into_iter();
+    let mut result: Vec<std::ffi::OsString> = parse_args.next().into_iter()
+        .collect();
+    result.push(std::ffi::OsString::from("parse"));
+    if !no_detect {
+        result.push(std::ffi::OsString::from("--detect"));
+    }
+    result.extend(parse_args.filter(|arg| arg != "--no-detect"));
+    result
+}
+
+fn build_app<'a>(lint_rules: &'a [&'a str]) -> clap::App<'a, 'a> {
+    clap::App::new("syslinux-conf")
+        .about("Reads, checks, converts and edits syslinux configuration \
+                files")
+        .version(env!("CARGO_PKG_VERSION"))
+        .setting(clap::AppSettings::ArgRequiredElseHelp)
+        // Options which were replaced by subcommands, kept for scripts.
+        .arg(clap::Arg::with_name("from-json")
+            .help("Same as \"convert --from json --to cfg\".")
+            .long("from-json")
+            .value_name("JSON FILE")
+            .takes_value(true)
+            .hidden(true))
+        .arg(clap::Arg::with_name("schema")
+            .help("Same as \"schema\" subcommand.")
+            .long("schema")
+            .hidden(true)
+            .conflicts_with("from-json"))
+        .subcommand(conf_args(clap::SubCommand::with_name("parse")
+            .about("Prints configuration as JSON or other format")
+            .arg(format_arg("json")
+                .help("Output format. \"cfg\" writes configuration in \
+                       syslinux syntax with paths as they are in the \
+                       original file.")
+                .possible_values(&["json", "json-pretty", "yaml", "toml",
+                                   "cfg"]))
+            .arg(clap::Arg::with_name("inspect")
+                .help("Inspect Linux kernels and initrds and include their \
+                       metadata.")
+                .short("i")
+                .long("inspect"))
+            .arg(clap::Arg::with_name("manifest")
+                .help("Print SHA-256 manifest of configuration and all boot \
+                       files instead of configuration.")
+                .short("m")
+                .long("manifest"))
+            .arg(clap::Arg::with_name("resolve-dtb")
+                .help("Print device tree U-Boot would load for every label \
+                       on the board with given compatible string instead of \
+                       configuration.")
+                .long("resolve-dtb")
+                .value_name("COMPATIBLE")
+                .takes_value(true)
+                .conflicts_with("manifest"))
+            .arg(clap::Arg::with_name("envelope")
+                .help("Wrap configuration into versioned envelope with \
+                       format version, paths and diagnostics. Envelope is \
+                       printed even if configuration can not be read.")
+                .short("e")
+                .long("envelope")
+                .conflicts_with_all(&["manifest", "resolve-dtb"]))))
+        .subcommand(conf_args(clap::SubCommand::with_name("check")
+            .about("Validates configuration and checks that all referenced \
+                    files exist")))
+        .subcommand(conf_args(clap::SubCommand::with_name("boot")
+            .about("Shows what would be booted by the command typed at the \
+                    boot prompt")
+            .arg(clap::Arg::with_name("command")
+                .help("Command line as typed by user. DEFAULT is used if \
+                       omitted.")
+                .short("c")
+                .long("command")
+                .value_name("COMMAND")
+                .takes_value(true))
+            .arg(clap::Arg::with_name("timeout")
+                .help("Simulate timeout instead of typed command.")
+                .long("timeout")
+                .conflicts_with("command"))
+            .arg(format_arg("json-pretty")
+                .possible_values(&STRUCTURED_FORMATS))))
+        .subcommand(conf_args(clap::SubCommand::with_name("lint")
+            .about("Warns about configuration which is valid, but probably \
+                    does not do what was meant. Exits with 1 if any denied \
+                    rule is violated.")
+            .arg(clap::Arg::with_name("disable")
+                .help("Do not check rule.")
+                .long("disable")
+                .value_name("RULE")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .possible_values(lint_rules))
+            .arg(clap::Arg::with_name("deny")
+                .help("Report violations of rule as errors.")
+                .long("deny")
+                .value_name("RULE")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .possible_values(lint_rules))))
+        .subcommand(conf_args(clap::SubCommand::with_name("convert")
+            .about("Converts configuration from and into other boot loader \
+                    formats. ROOT DIR is the ESP for Boot Loader \
+                    Specification.")
+            .arg(clap::Arg::with_name("from")
+                .help("Source format. CONF FILE PATH is JSON or grub.cfg \
+                       file, stdin if omitted.")
+                .long("from")
+                .value_name("FORMAT")
+                .takes_value(true)
+                .default_value("syslinux")
+                .possible_values(&["syslinux", "json", "grub", "bls"]))
+            .arg(clap::Arg::with_name("to")
+                .help("Target format.")
+                .long("to")
+                .value_name("FORMAT")
+                .takes_value(true)
+                .required(true)
+                .possible_values(&["cfg", "grub", "bls", "json",
+                                   "json-pretty", "yaml", "toml"]))
+            .arg(clap::Arg::with_name("write")
+                .help("Write Boot Loader Specification entries into the ESP \
+                       instead of printing them.")
+                .long("write"))))
+        .subcommand(conf_args(clap::SubCommand::with_name("edit")
+            .about("Modifies configuration in place. Only affected lines \
+                    are changed, the file is replaced atomically.")
+            .arg(clap::Arg::with_name("set-default")
+                .help("Make label the default one.")
+                .long("set-default")
+                .value_name("LABEL")
+                .takes_value(true))
+            .arg(clap::Arg::with_name("set-append")
+                .help("Replace kernel parameters of label.")
+                .long("set-append")
+                .value_names(&["LABEL", "APPEND"])
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(2))
+            .arg(clap::Arg::with_name("add-param")
+                .help("Add kernel parameter to label unless it is already \
+                       there.")
+                .long("add-param")
+                .value_names(&["LABEL", "PARAM"])
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(2))
+            .arg(clap::Arg::with_name("remove-param")
+                .help("Remove kernel parameter from label. Parameter without \
+                       \"=\" removes all its values.")
+                .long("remove-param")
+                .value_names(&["LABEL", "PARAM"])
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(2))
+            .arg(clap::Arg::with_name("remove-label")
+                .help("Remove label.")
+                .long("remove-label")
+                .value_name("LABEL")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1))
+            .arg(clap::Arg::with_name("print")
+                .help("Print the result instead of writing it.")
+                .short("p")
+                .long("print"))))
+        .subcommand(clap::SubCommand::with_name("diff")
+            .about("Compares two configuration files: labels are matched \
+                    by name, fields and kernel arguments are compared. \
+                    Exits with 1 if they differ.")
+            .arg(clap::Arg::with_name("OLD")
+                .help("Path to the old configuration file.")
+                .required(true)
+                .index(1))
+            .arg(clap::Arg::with_name("NEW")
+                .help("Path to the new configuration file.")
+                .required(true)
+                .index(2))
+            .arg(clap::Arg::with_name("dialect")
+                .help("Dialect of configuration files.")
+                .short("d")
+                .long("dialect")
+                .value_name("DIALECT")
+                .takes_value(true)
+                .default_value("syslinux")
+                .possible_values(&["syslinux", "uboot"]))
+            .arg(format_arg("text")
+                .possible_values(&["text", "json", "json-pretty", "yaml",
+                                   "toml"])))
+        .subcommand(clap::SubCommand::with_name("merge")
+            .about("Merges changes of two configuration files made \
+                    independently from the common base. Conflicts are \
+                    resolved in favour of OURS and reported, exits with 1 if \
+                    there are any.")
+            .arg(clap::Arg::with_name("BASE")
+                .help("Path to the common base configuration file.")
+                .required(true)
+                .index(1))
+            .arg(clap::Arg::with_name("OURS")
+                .help("Path to our changed configuration file.")
+                .required(true)
+                .index(2))
+            .arg(clap::Arg::with_name("THEIRS")
+                .help("Path to their changed configuration file.")
+                .required(true)
+                .index(3))
+            .arg(clap::Arg::with_name("dialect")
+                .help("Dialect of configuration files.")
+                .short("d")
+                .long("dialect")
+                .value_name("DIALECT")
+                .takes_value(true)
+                .default_value("syslinux")
+                .possible_values(&["syslinux", "uboot"]))
+            .arg(format_arg("cfg")
+                .help("Output format. Structured formats include conflicts.")
+                .possible_values(&["cfg", "json", "json-pretty", "yaml",
+                                   "toml"]))
+            .arg(clap::Arg::with_name("output")
+                .help("Write the result into file instead of stdout. Nothing \
+                       is written if there are conflicts.")
+                .short("o")
+                .long("output")
+                .value_name("FILE")
+                .takes_value(true)))
+        .subcommand(conf_args(clap::SubCommand::with_name("list")
+            .about("Prints table of labels, the default one is marked with \
+                    \"*\"")))
+        .subcommand(clap::SubCommand::with_name("schema")
+            .about("Prints JSON Schema of the output format")
+            .arg(clap::Arg::with_name("envelope")
+                .help("Prints schema of output with --envelope")
+                .long("envelope")))
+}
+
+fn main() {
+    env_logger::init().unwrap();
+
+    let lint_rules: Vec<&str> = syslinux_conf::Linter::default().rules()
+        .iter()
+        .map(|rule| rule.id)
+        .collect();
+    let matches = build_app(&lint_rules)
+        .get_matches_from(legacy_args(std::env::args_os().collect()));
+
+    match matches.subcommand() {
+        ("parse", Some(matches)) => parse_command(matches),
+        ("check", Some(matches)) => check_command(matches),
+        ("lint", Some(matches)) => lint_command(matches),
+        ("boot", Some(matches)) => boot_command(matches),
+        ("convert", Some(matches)) => convert_command(matches),
+        ("edit", Some(matches)) => edit_command(matches),
+        ("diff", Some(matches)) => diff_command(matches),
+        ("merge", Some(matches)) => merge_command(matches),
+        ("list", Some(matches)) => list_command(matches),
+        ("schema", Some(matches)) => schema_command(matches),
+        _ => match matches.value_of("from-json") {
+            Some(json_path) => {
+                warn!("--from-json is deprecated, use \"convert --from json \
+                       --to cfg\"");
+                from_json_command(json_path)
+            },
+            None => {
+                warn!("--schema is deprecated, use \"schema\" subcommand");
+                schema_command(&matches)
+            },
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std;
+
+    use super::{build_app, legacy_args};
+
+    fn args(args: &[&str]) -> Vec<std::ffi::OsString> {
+        args.iter().map(std::ffi::OsString::from).collect()
+    }
+
+    #[test]
+    fn runs_parse_without_subcommand() {
+        assert_eq!(legacy_args(args(&["syslinux-conf", "/boot", "x.cfg"])),
+                   args(&["syslinux-conf", "parse", "--detect", "/boot",
+                          "x.cfg"]));
+        assert_eq!(legacy_args(args(&["syslinux-conf", "--no-detect", "-f",
+                                      "yaml", "/boot"])),
+                   args(&["syslinux-conf", "parse", "-f", "yaml", "/boot"]));
+
+        let matches = build_app(&[])
+            .get_matches_from_safe(legacy_args(
+                args(&["syslinux-conf", "-t", "extlinux", "/boot"])))
+            .unwrap();
+        match matches.subcommand() {
+            ("parse", Some(matches)) => {
+                assert_eq!(matches.value_of("ROOT DIR"), Some("/boot"));
+                assert_eq!(matches.value_of("type"), Some("extlinux"));
+                assert!(matches.is_present("detect"));
+            },
+            (name, _) => panic!("Unexpected subcommand {:?}", name),
+        };
+    }
+
+    #[test]
+    fn keeps_subcommands_and_replaced_options() {
+        for command in &[&["syslinux-conf", "check", "/boot"][..],
+                         &["syslinux-conf", "--schema"][..],
+                         &["syslinux-conf", "--from-json", "conf.json"][..],
+                         &["syslinux-conf", "--help"][..],
+                         &["syslinux-conf"][..]] {
+            assert_eq!(legacy_args(args(command)), args(command));
+        }
+    }
+}
@@ -0,0 +1,558 @@
+use std;
+
+use parser::Dialect;
+use reader::get_device_path;
+use types::{ApplyDefaults, Diagnostic, Global, KernelFile, KernelFileSource,
+            KernelOrConfig, Label, Labels, Severity, SyslinuxConf};
+
+#[derive(Debug, Clone)]
+pub struct BlsEntry {
+    // Name of file inside of "loader/entries".
+    pub file_name: String,
+    pub contents: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct BlsConf {
+    pub entries: Vec<BlsEntry>,
+    // Contents of "loader/loader.conf".
+    pub loader_conf: String,
+}
+
+// Characters outside of this set are replaced in entry file names.
+fn sanitize_file_name(label_name: &str) -> String {
+    label_name.chars().map(|c| match c {
+        'a'...'z' | 'A'...'Z' | '0'...'9' | '.' | '_' | '-' => c,
+        _ => '_',
+    }).collect()
+}
+
+struct BlsWriter<'a> {
+    esp_root: &'a std::path::Path,
+    entries: Vec<BlsEntry>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> BlsWriter<'a> {
+    fn diagnostic(&mut self, severity: Severity, label_name: &str, code: &str,
+                  message: String) {
+        self.diagnostics.push(
+            Diagnostic::new(severity, code, message).for_label(label_name));
+    }
+
+    fn path(&mut self, label_name: &str, path: &std::path::Path)
+            -> Option<String> {
+        match get_device_path(self.esp_root, path) {
+            Some(device_path) => {
+                Some(String::from(device_path.to_string_lossy()))
+            },
+
+            None => {
+                self.diagnostic(
+                    Severity::Error, label_name, "bls-outside-esp",
+                    format!("File {:?} is outside of the ESP root, label \
+                             skipped", path));
+                None
+            },
+        }
+    }
+
+    fn file_name(&self, label_name: &str) -> String {
+        let base = sanitize_file_name(label_name);
+        let is_free = |name: &str| {
+            !self.entries.iter().any(|entry| entry.file_name == name)
+        };
+
+        let mut file_name = format!("{}.conf", base);
+        let mut suffix = 2;
+        while !is_free(&file_name) {
+            file_name = format!("{}-{}.conf", base, suffix);
+            suffix += 1;
+        }
+        file_name
+    }
+
+    // Returns name of the entry file.
+    fn label(&mut self, label_name: &str, label: &Label) -> Option<String> {
+        let kernel = match label.kernel_or_config {
+            KernelOrConfig::Kernel(ref kernel) => kernel,
+        };
+
+        let path = match kernel.kernel_file {
+            Some(KernelFile::Linux(ref path)) => path,
+
+            Some(ref kernel_file) => {
+                self.diagnostic(
+                    Severity::Warning, label_name, "bls-unsupported-kernel",
+                    format!("{:?} is not supported by Boot Loader \
+                             Specification, label skipped", kernel_file));
+                return None;
+            },
+
+            None => {
+                self.diagnostic(
+                    Severity::Warning, label_name, "bls-no-kernel",
+                    String::from("Label has no kernel, skipped"));
+                return None;
+            },
+        };
+
+        let mut lines = vec![format!("title {}", label_name)];
+
+        match self.path(label_name, path) {
+            Some(path) => lines.push(format!("linux {}", path)),
+            None => return None,
+        };
+
+        if let Some(ref initrd) = kernel.initrd {
+            match self.path(label_name, initrd) {
+                Some(initrd) => lines.push(format!("initrd {}", initrd)),
+                None => return None,
+            };
+        }
+
+        match kernel.append {
+            // "APPEND -" means no arguments at all.
+            Some(ref append) if append != "-" && !append.is_empty() => {
+                lines.push(format!("options {}", append))
+            },
+            _ => (),
+        };
+
+        match (&kernel.fdt, &kernel.fdt_dir) {
+            (&Some(ref fdt), _) => match self.path(label_name, fdt) {
+                Some(fdt) => lines.push(format!("devicetree {}", fdt)),
+                None => return None,
+            },
+            (&None, &Some(_)) => self.diagnostic(
+                Severity::Warning, label_name, "bls-fdt-dir",
+                String::from("FDTDIR has no Boot Loader Specification \
+                              equivalent, devicetree is not loaded")),
+            (&None, &None) => (),
+        };
+
+        if !kernel.fdt_overlays.is_empty() {
+            let mut fdt_overlays = Vec::new();
+            for fdt_overlay in &kernel.fdt_overlays {
+                match self.path(label_name, fdt_overlay) {
+                    Some(fdt_overlay) => fdt_overlays.push(fdt_overlay),
+                    None => return None,
+                };
+            }
+            lines.push(format!("devicetree-overlay {}",
+                               fdt_overlays.join(" ")));
+        }
+
+        let file_name = self.file_name(label_name);
+        let mut contents = lines.join("\n");
+        contents.push('\n');
+        self.entries.push(BlsEntry{
+            file_name: file_name.clone(),
+            contents: contents,
+        });
+        Some(file_name)
+    }
+}
+
+// Paths in configuration should be already resolved. Kernels, initrds, etc.
+// should be located inside of the ESP root. Dialect tells what missing
+// timeout means.
+pub fn to_bls(conf: &SyslinuxConf, esp_root: &std::path::Path,
+              dialect: Dialect)
+        -> (BlsConf, Vec<Diagnostic>) {
+    let mut writer = BlsWriter{
+        esp_root: esp_root,
+        entries: Vec::new(),
+        diagnostics: Vec::new(),
+    };
+
+    let mut default_entry = None;
+    for (label_name, label) in conf.labels.iter() {
+        let label = label.clone().apply_defaults(&conf.global.label_defaults);
+        let file_name = writer.label(label_name, &label);
+        if conf.global.default.as_ref() == Some(label_name) {
+            default_entry = file_name;
+        }
+    }
+
+    let mut loader_conf = String::new();
+    match (&conf.global.default, default_entry) {
+        (&Some(_), Some(file_name)) => {
+            loader_conf.push_str(&format!("default {}\n", file_name));
+        },
+
+        (&Some(ref default), None) => writer.diagnostics.push(Diagnostic::new(
+            Severity::Warning, "bls-default-not-label", format!(
+                "DEFAULT \"{}\" is not a converted label, first entry will \
+                 be booted", default))),
+
+        (&None, _) => (),
+    };
+
+    match (conf.global.timeout, dialect) {
+        (Some(timeout), _) => loader_conf.push_str(
+            &format!("timeout {}\n", timeout.ceil() as u64)),
+        // No timeout means waiting forever in syslinux, while systemd-boot
+        // boots the default entry immediately.
+        (None, Dialect::Syslinux) => {
+            loader_conf.push_str("timeout menu-force\n")
+        },
+        (None, Dialect::UBoot) => (),
+    };
+
+    (BlsConf{
+        entries: writer.entries,
+        loader_conf: loader_conf,
+    }, writer.diagnostics)
+}
+
+// Writes "loader/loader.conf" and "loader/entries/*.conf" into the ESP root.
+pub fn write_bls(bls_conf: &BlsConf, esp_root: &std::path::Path)
+        -> std::io::Result<()> {
+    use std::io::Write;
+
+    let loader_dir = esp_root.join("loader");
+    let entries_dir = loader_dir.join("entries");
+    try!(std::fs::create_dir_all(&entries_dir));
+
+    for entry in &bls_conf.entries {
+        let mut file = try!(std::fs::File::create(
+            entries_dir.join(&entry.file_name)));
+        try!(file.write_all(entry.contents.as_bytes()));
+    }
+
+    let mut file = try!(std::fs::File::create(loader_dir.join("loader.conf")));
+    file.write_all(bls_conf.loader_conf.as_bytes())
+}
+
+// Splits "key value" line, returns None for empty and comment lines.
+fn split_bls_line(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    match line.find(|c: char| c.is_whitespace()) {
+        Some(pos) => Some((&line[..pos], line[pos..].trim())),
+        None => Some((line, "")),
+    }
+}
+
+// Glob pattern with "*" and "?", as used by "default" in loader.conf.
+fn glob_match(pattern: &[char], value: &[char]) -> bool {
+    match (pattern.first(), value.first()) {
+        (None, None) => true,
+        (Some(&'*'), _) => {
+            glob_match(&pattern[1..], value) ||
+                (!value.is_empty() && glob_match(pattern, &value[1..]))
+        },
+        (Some(&'?'), Some(_)) => glob_match(&pattern[1..], &value[1..]),
+        (Some(p), Some(v)) if p == v => glob_match(&pattern[1..], &value[1..]),
+        _ => false,
+    }
+}
+
+fn parse_bls_entry(text: &str, label_name: &str,
+                   diagnostics: &mut Vec<Diagnostic>) -> Label {
+    let mut label = Label::default();
+    let mut initrds = Vec::new();
+    let mut options = Vec::new();
+
+    for (line_index, line) in text.lines().enumerate() {
+        let (key, value) = match split_bls_line(line) {
+            Some(key_value) => key_value,
+            None => continue,
+        };
+
+        let mut diagnostic = |severity, code, message: String| {
+            diagnostics.push(Diagnostic::new(
+                severity, code,
+                format!("line {}: {}", line_index + 1, message))
+                .for_label(label_name));
+        };
+
+        match label.kernel_or_config {
+            KernelOrConfig::Kernel(ref mut kernel) => match key {
+                "linux" => {
+                    kernel.kernel_file = Some(KernelFile::Linux(
+                        std::path::PathBuf::from(value)));
+                    kernel.kernel_file_source = Some(KernelFileSource::Tag);
+                },
+                "initrd" => initrds.push(String::from(value)),
+                "options" => options.push(String::from(value)),
+
+                "title" | "version" | "machine-id" | "sort-key" |
+                        "architecture" => {
+                    diagnostic(Severity::Info, "bls-ignored", format!(
+                        "Key \"{}\" has no syslinux equivalent", key));
+                },
+
+                "devicetree" => {
+                    kernel.fdt = Some(std::path::PathBuf::from(value));
+                },
+                "devicetree-overlay" => {
+                    kernel.fdt_overlays.extend(
+                        value.split_whitespace()
+                            .map(|path| std::path::PathBuf::from(path)));
+                },
+
+                "efi" => {
+                    diagnostic(Severity::Warning, "bls-efi", String::from(
+                        "EFI programs are not supported by syslinux"));
+                },
+
+                _ => {
+                    diagnostic(Severity::Warning, "bls-unsupported", format!(
+                        "Unknown key \"{}\"", key));
+                },
+            },
+        };
+    }
+
+    match label.kernel_or_config {
+        KernelOrConfig::Kernel(ref mut kernel) => {
+            // Syslinux accepts comma-separated list of initrds.
+            if !initrds.is_empty() {
+                kernel.initrd = Some(std::path::PathBuf::from(
+                    initrds.join(",")));
+            }
+            if !options.is_empty() {
+                kernel.append = Some(options.join(" "));
+            }
+        },
+    };
+
+    label
+}
+
+fn read_file(path: &std::path::Path) -> std::io::Result<String> {
+    use std::io::Read;
+
+    let mut text = String::new();
+    try!(try!(std::fs::File::open(path)).read_to_string(&mut text));
+    Ok(text)
+}
+
+// Imports "loader/entries/*.conf" and "loader/loader.conf" from the ESP root.
+// Entries are ordered by file name, label names are file names without
+// ".conf".
+pub fn from_bls(esp_root: &std::path::Path)
+        -> std::io::Result<(SyslinuxConf, Vec<Diagnostic>)> {
+    let loader_dir = esp_root.join("loader");
+    let mut diagnostics = Vec::new();
+
+    let mut entry_paths = Vec::new();
+    for dir_entry in try!(std::fs::read_dir(loader_dir.join("entries"))) {
+        let path = try!(dir_entry).path();
+        if path.extension().map_or(false, |extension| extension == "conf") {
+            entry_paths.push(path);
+        }
+    }
+    entry_paths.sort();
+
+    let mut labels = Labels::new();
+    for path in entry_paths {
+        let label_name = match path.file_stem() {
+            Some(file_stem) => String::from(file_stem.to_string_lossy()),
+            None => continue,
+        };
+        let text = try!(read_file(&path));
+        let label = parse_bls_entry(&text, &label_name, &mut diagnostics);
+        labels.insert(label_name, label);
+    }
+
+    let mut global = Global::default();
+    let loader_conf_path = loader_dir.join("loader.conf");
+    if loader_conf_path.exists() {
+        let text = try!(read_file(&loader_conf_path));
+        for (line_index, line) in text.lines().enumerate() {
+            let (key, value) = match split_bls_line(line) {
+                Some(key_value) => key_value,
+                None => continue,
+            };
+            let line = line_index + 1;
+
+            match key {
+                "default" => {
+                    let pattern: Vec<char> = value.trim_right_matches(".conf")
+                        .chars().collect();
+                    global.default = labels.keys()
+                        .find(|label_name| {
+                            let label_name: Vec<char> =
+                                label_name.chars().collect();
+                            glob_match(&pattern, &label_name)
+                        })
+                        .cloned();
+                    if global.default.is_none() {
+                        diagnostics.push(Diagnostic::new(
+                            Severity::Warning, "bls-default-not-found",
+                            format!("loader.conf line {}: no entry matches \
+                                     default \"{}\"", line, value)));
+                    }
+                },
+
+                "timeout" => match value {
+                    "menu-force" => global.timeout = None,
+                    _ => match value.parse::<f64>() {
+                        Ok(timeout) if timeout > 0.0 => {
+                            global.timeout = Some(timeout)
+                        },
+                        _ => diagnostics.push(Diagnostic::new(
+                            Severity::Warning, "bls-timeout", format!(
+                                "loader.conf line {}: timeout \"{}\" can not \
+                                 be expressed in syslinux", line, value))),
+                    },
+                },
+
+                _ => diagnostics.push(Diagnostic::new(
+                    Severity::Info, "bls-ignored", format!(
+                        "loader.conf line {}: key \"{}\" is ignored",
+                        line, key))),
+            }
+        }
+    }
+
+    Ok((SyslinuxConf{
+        global: global,
+        labels: labels,
+    }, diagnostics))
+}
+
+#[cfg(test)]
+mod tests {
+    use std;
+
+    use super::{from_bls, to_bls, write_bls};
+    use parser::Dialect;
+    use test_util::{TempDir, parse};
+    use types::{Kernel, KernelFile, KernelOrConfig, SyslinuxConf};
+
+    const CONF: &'static str = "DEFAULT linux\n\
+                                LABEL linux\n\
+                                \x20 LINUX /vmlinuz\n\
+                                \x20 INITRD /initrd.img\n\
+                                \x20 APPEND root=/dev/sda1\n";
+
+    fn loader_conf(text: &str, dialect: Dialect) -> String {
+        let conf = parse(text);
+        to_bls(&conf, std::path::Path::new("/"), dialect).0.loader_conf
+    }
+
+    #[test]
+    fn writes_entry() {
+        let (bls_conf, diagnostics) = to_bls(
+            &parse(CONF), std::path::Path::new("/"), Dialect::Syslinux);
+
+        assert!(diagnostics.is_empty());
+        assert_eq!(bls_conf.entries.len(), 1);
+        assert_eq!(bls_conf.entries[0].file_name, "linux.conf");
+        assert_eq!(bls_conf.entries[0].contents,
+                   "title linux\n\
+                    linux /vmlinuz\n\
+                    initrd /initrd.img\n\
+                    options root=/dev/sda1\n");
+    }
+
+    #[test]
+    fn writes_timeout() {
+        assert_eq!(loader_conf(&format!("TIMEOUT 50\n{}", CONF),
+                               Dialect::Syslinux),
+                   "default linux.conf\ntimeout 5\n");
+    }
+
+    #[test]
+    fn zero_timeout_waits_forever() {
+        assert_eq!(loader_conf(&format!("TIMEOUT 0\n{}", CONF),
+                               Dialect::Syslinux),
+                   "default linux.conf\ntimeout menu-force\n");
+        assert_eq!(loader_conf(CONF, Dialect::Syslinux),
+                   "default linux.conf\ntimeout menu-force\n");
+    }
+
+    #[test]
+    fn no_timeout_boots_immediately_in_uboot() {
+        assert_eq!(loader_conf(CONF, Dialect::UBoot),
+                   "default linux.conf\n");
+    }
+
+    fn kernel<'a>(conf: &'a SyslinuxConf, label_name: &str) -> &'a Kernel {
+        match conf.labels.get(label_name).unwrap().kernel_or_config {
+            KernelOrConfig::Kernel(ref kernel) => kernel,
+        }
+    }
+
+    #[test]
+    fn imports_entries() {
+        let dir = TempDir::new();
+        dir.write("loader/entries/10-linux.conf",
+                  b"title Linux\n\
+                    version 6.1\n\
+                    # Comment\n\
+                    linux /vmlinuz\n\
+                    initrd /intel-ucode.img\n\
+                    initrd /initramfs.img\n\
+                    options root=/dev/sda1\n\
+                    options  quiet\n");
+        dir.write("loader/entries/20-rescue.conf",
+                  b"linux /vmlinuz-rescue\n\
+                    efi /shell.efi\n\
+                    foo bar\n");
+        dir.write("loader/entries/README", b"Not an entry\n");
+        dir.write("loader/loader.conf",
+                  b"default 20-*\n\
+                    timeout menu-force\n\
+                    console-mode max\n");
+
+        let (conf, diagnostics) = from_bls(dir.path()).unwrap();
+
+        let label_names: Vec<&String> = conf.labels.keys().collect();
+        assert_eq!(label_names, vec!["10-linux", "20-rescue"]);
+        assert_eq!(conf.global.default, Some(String::from("20-rescue")));
+        assert_eq!(conf.global.timeout, None);
+
+        let linux = kernel(&conf, "10-linux");
+        assert_eq!(linux.kernel_file, Some(KernelFile::Linux(
+            std::path::PathBuf::from("/vmlinuz"))));
+        assert_eq!(linux.initrd, Some(std::path::PathBuf::from(
+            "/intel-ucode.img,/initramfs.img")));
+        assert_eq!(linux.append, Some(String::from("root=/dev/sda1 quiet")));
+
+        let codes: Vec<&str> = diagnostics.iter()
+            .map(|diagnostic| &diagnostic.code[..])
+            .collect();
+        assert_eq!(codes, vec!["bls-ignored", "bls-ignored", "bls-efi",
+                               "bls-unsupported", "bls-ignored"]);
+    }
+
+    #[test]
+    fn imports_timeout() {
+        let dir = TempDir::new();
+        dir.write("loader/entries/linux.conf", b"linux /vmlinuz\n");
+        dir.write("loader/loader.conf", b"timeout 3\n");
+        assert_eq!(from_bls(dir.path()).unwrap().0.global.timeout, Some(3.0));
+
+        // Immediate boot can not be expressed.
+        dir.write("loader/loader.conf", b"timeout 0\n");
+        let (conf, diagnostics) = from_bls(dir.path()).unwrap();
+        assert_eq!(conf.global.timeout, None);
+        assert_eq!(diagnostics[0].code, "bls-timeout");
+    }
+
+    #[test]
+    fn imports_what_it_exports() {
+        let dir = TempDir::new();
+        let (bls_conf, _) = to_bls(&parse(CONF), std::path::Path::new("/"),
+                                   Dialect::Syslinux);
+        write_bls(&bls_conf, dir.path()).unwrap();
+
+        let (conf, _) = from_bls(dir.path()).unwrap();
+        assert_eq!(conf.global.default, Some(String::from("linux")));
+        assert_eq!(conf.global.timeout, None);
+        let linux = kernel(&conf, "linux");
+        assert_eq!(linux.kernel_file, Some(KernelFile::Linux(
+            std::path::PathBuf::from("/vmlinuz"))));
+        assert_eq!(linux.initrd,
+                   Some(std::path::PathBuf::from("/initrd.img")));
+        assert_eq!(linux.append, Some(String::from("root=/dev/sda1")));
+    }
+}
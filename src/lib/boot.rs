@@ -0,0 +1,100 @@
+use std;
+
+use types::{ApplyDefaults, BootTarget, KernelFile, KernelOrConfig,
+            SyslinuxConf};
+
+// Command line without "APPEND -", which means "no arguments".
+fn join_append(append: &Option<String>, args: &str) -> String {
+    let append = match *append {
+        Some(ref append) if append != "-" => append.trim(),
+        _ => "",
+    };
+    match (append.is_empty(), args.is_empty()) {
+        (true, _) => String::from(args),
+        (false, true) => String::from(append),
+        (false, false) => format!("{} {}", append, args),
+    }
+}
+
+// Last "initrd=" argument wins, like in syslinux.
+fn initrd_from_append(append: &str) -> Option<std::path::PathBuf> {
+    append.split_whitespace()
+        .filter(|arg| arg.starts_with("initrd="))
+        .last()
+        .map(|arg| std::path::PathBuf::from(&arg["initrd=".len()..]))
+}
+
+fn split_command(command: &str) -> (&str, &str) {
+    let command = command.trim();
+    match command.find(|c: char| c.is_whitespace()) {
+        Some(pos) => (&command[..pos], command[pos..].trim()),
+        None => (command, ""),
+    }
+}
+
+impl SyslinuxConf {
+    // Simulates syslinux command line interface: first word is a label or,
+    // if there is no such label, a kernel file name; the rest is appended to
+    // the kernel command line. Empty command boots DEFAULT, or ONTIMEOUT if
+    // timeout is true. Kernel file names typed by user are not resolved.
+    pub fn resolve_boot_command(&self, command: &str, timeout: bool)
+            -> Option<BootTarget> {
+        let command = match (command.trim().is_empty(), timeout) {
+            (false, _) => String::from(command.trim()),
+            (true, true) => match self.global.ontimeout {
+                Some(ref ontimeout) => ontimeout.clone(),
+                None => match self.global.default {
+                    Some(ref default) => default.clone(),
+                    None => return None,
+                },
+            },
+            (true, false) => match self.global.default {
+                Some(ref default) => default.clone(),
+                None => return None,
+            },
+        };
+
+        let (name, args) = split_command(&command);
+        if name.is_empty() {
+            return None;
+        }
+
+        let defaults = &self.global.label_defaults;
+        let target = match self.labels.get(name) {
+            Some(label) => {
+                let label = label.clone().apply_defaults(defaults);
+                match label.kernel_or_config {
+                    KernelOrConfig::Kernel(kernel) => {
+                        let append = join_append(&kernel.append, args);
+                        BootTarget{
+                            command: command.clone(),
+                            label: Some(String::from(name)),
+                            kernel_file: kernel.kernel_file,
+                            initrd: kernel.initrd.or(
+                                initrd_from_append(&append)),
+                            append: append,
+                        }
+                    },
+                }
+            },
+
+            None => {
+                let append = match defaults.kernel_or_config {
+                    KernelOrConfig::Kernel(ref kernel) => {
+                        join_append(&kernel.append, args)
+                    },
+                };
+                BootTarget{
+                    command: command.clone(),
+                    label: None,
+                    kernel_file: Some(KernelFile::guess(
+                        std::path::PathBuf::from(name))),
+                    initrd: initrd_from_append(&append),
+                    append: append,
+                }
+            },
+        };
+
+        Some(target)
+    }
+}
@@ -0,0 +1,378 @@
+use std;
+
+extern crate linked_hash_map;
+
+use edit::KernelField;
+use types::{AppendChange, ConfDiff, FieldChange, Kernel, KernelFile,
+            KernelFileSource, Label, LabelDiff, Labels, SyslinuxConf,
+            UnknownDirective};
+
+// Inspection results are not part of configuration and are not compared.
+
+pub fn path_value(path: &Option<std::path::PathBuf>) -> Option<String> {
+    path.as_ref().map(|path| String::from(path.to_string_lossy()))
+}
+
+pub fn timeout_value(timeout: Option<f64>) -> Option<String> {
+    timeout.map(|timeout| format!("{}", timeout))
+}
+
+pub fn prompt_value(prompt: Option<bool>) -> Option<String> {
+    prompt.map(|prompt| String::from(if prompt { "1" } else { "0" }))
+}
+
+// Directives as they are written, one per line.
+pub fn directives_value(directives: &Vec<UnknownDirective>) -> Option<String> {
+    match directives.is_empty() {
+        true => None,
+        false => Some(directives.iter()
+            .map(|directive| format!("{} {}", directive.keyword,
+                                     directive.argument).trim().to_string())
+            .collect::<Vec<String>>()
+            .join("\n")),
+    }
+}
+
+// KERNEL and LINUX of the same file are different: type of the former is
+// guessed.
+pub fn kernel_file_value(kernel_file: &Option<KernelFile>,
+                         source: Option<KernelFileSource>) -> Option<String> {
+    kernel_file.as_ref().map(|kernel_file| {
+        let tag = match source {
+            Some(KernelFileSource::Extension) => "KERNEL",
+            _ => kernel_file.tag(),
+        };
+        format!("{} {}", tag, kernel_file.path().to_string_lossy())
+    })
+}
+
+// "APPEND -" means no arguments.
+fn append_args(append: &Option<String>) -> Vec<&str> {
+    match *append {
+        Some(ref append) if append.trim() != "-" => {
+            append.split_whitespace().collect()
+        },
+        _ => Vec::new(),
+    }
+}
+
+// Arguments are counted, so the second "quiet" is a change too.
+fn subtract(args: &[&str], other: &[&str]) -> Vec<String> {
+    let mut other = other.to_vec();
+    let mut result = Vec::new();
+    for arg in args {
+        match other.iter().position(|other_arg| other_arg == arg) {
+            Some(index) => {
+                other.remove(index);
+            },
+            None => result.push(String::from(*arg)),
+        }
+    }
+    result
+}
+
+fn diff_append(old: &Option<String>, new: &Option<String>)
+               -> Option<AppendChange> {
+    if old == new {
+        return None;
+    }
+
+    let old_args = append_args(old);
+    let new_args = append_args(new);
+    Some(AppendChange{
+        added: subtract(&new_args, &old_args),
+        removed: subtract(&old_args, &new_args),
+    })
+}
+
+struct FieldDiffer {
+    changes: Vec<FieldChange>,
+}
+
+impl FieldDiffer {
+    fn field(&mut self, field: &str, old: Option<String>, new: Option<String>) {
+        if old != new {
+            self.changes.push(FieldChange{
+                field: String::from(field),
+                old: old,
+                new: new,
+            });
+        }
+    }
+
+    fn kernel_field(&mut self, name: &str, field: KernelField, old: &Kernel,
+                    new: &Kernel) {
+        self.field(name, old.get_field(field), new.get_field(field));
+    }
+}
+
+// Field names are the same as in JSON.
+pub fn diff_label(old: &Label, new: &Label) -> Option<LabelDiff> {
+    let mut differ = FieldDiffer{
+        changes: Vec::new(),
+    };
+
+    let old_kernel = old.kernel();
+    let new_kernel = new.kernel();
+    differ.field("kernel_file",
+                 kernel_file_value(&old_kernel.kernel_file,
+                                   old_kernel.kernel_file_source),
+                 kernel_file_value(&new_kernel.kernel_file,
+                                   new_kernel.kernel_file_source));
+    differ.kernel_field("initrd", KernelField::InitRD, old_kernel,
+                        new_kernel);
+    differ.kernel_field("fdt", KernelField::FDT, old_kernel, new_kernel);
+    differ.kernel_field("fdt_dir", KernelField::FDTDir, old_kernel,
+                        new_kernel);
+    differ.kernel_field("fdt_overlays", KernelField::FDTOverlays, old_kernel,
+                        new_kernel);
+    differ.kernel_field("append", KernelField::Append, old_kernel,
+                        new_kernel);
+
+    differ.field("text_help", old.text_help.clone(), new.text_help.clone());
+    differ.field("say", old.say.clone(), new.say.clone());
+    differ.field("display", path_value(&old.display), path_value(&new.display));
+    differ.field("unknown_directives",
+                 directives_value(&old.unknown_directives),
+                 directives_value(&new.unknown_directives));
+
+    match differ.changes.is_empty() {
+        true => None,
+        false => Some(LabelDiff{
+            changes: differ.changes,
+            append: diff_append(&old_kernel.append, &new_kernel.append),
+        }),
+    }
+}
+
+// Common labels which are not in the longest common subsequence of both
+// orders, i.e. the fewest labels which have to be moved.
+fn reordered(old: &[&str], new: &[&str]) -> Vec<String> {
+    let (n, m) = (old.len(), new.len());
+    let mut lengths = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i][j] = match old[i] == new[j] {
+                true => lengths[i + 1][j + 1] + 1,
+                false => std::cmp::max(lengths[i + 1][j], lengths[i][j + 1]),
+            };
+        }
+    }
+
+    let mut in_place = std::collections::HashSet::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if old[i] == new[j] {
+            in_place.insert(old[i]);
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+
+    new.iter()
+        .filter(|name| !in_place.contains(*name))
+        .map(|name| String::from(*name))
+        .collect()
+}
+
+fn only_in(labels: &Labels, other: &Labels) -> Vec<String> {
+    labels.keys()
+        .filter(|name| !other.contains_key(*name))
+        .cloned()
+        .collect()
+}
+
+// Names in order of labels.
+fn common<'a>(labels: &'a Labels, other: &Labels) -> Vec<&'a str> {
+    labels.keys()
+        .filter(|name| other.contains_key(*name))
+        .map(|name| name.as_str())
+        .collect()
+}
+
+pub fn diff(old: &SyslinuxConf, new: &SyslinuxConf) -> ConfDiff {
+    let mut differ = FieldDiffer{
+        changes: Vec::new(),
+    };
+    differ.field("default", old.global.default.clone(),
+                 new.global.default.clone());
+    differ.field("ontimeout", old.global.ontimeout.clone(),
+                 new.global.ontimeout.clone());
+    differ.field("onerror", old.global.onerror.clone(),
+                 new.global.onerror.clone());
+    differ.field("timeout", timeout_value(old.global.timeout),
+                 timeout_value(new.global.timeout));
+    differ.field("total_timeout", timeout_value(old.global.total_timeout),
+                 timeout_value(new.global.total_timeout));
+    differ.field("menu_title", old.global.menu_title.clone(),
+                 new.global.menu_title.clone());
+    differ.field("prompt", prompt_value(old.global.prompt),
+                 prompt_value(new.global.prompt));
+    differ.field("ui", old.global.ui.clone(), new.global.ui.clone());
+    differ.field("unknown_directives",
+                 directives_value(&old.global.unknown_directives),
+                 directives_value(&new.global.unknown_directives));
+
+    let mut changed_labels = linked_hash_map::LinkedHashMap::new();
+    for (name, new_label) in new.labels.iter() {
+        if let Some(old_label) = old.labels.get(name) {
+            if let Some(label_diff) = diff_label(old_label, new_label) {
+                changed_labels.insert(name.clone(), label_diff);
+            }
+        }
+    }
+
+    ConfDiff{
+        global: differ.changes,
+        label_defaults: diff_label(&old.global.label_defaults,
+                                   &new.global.label_defaults),
+        removed_labels: only_in(&old.labels, &new.labels),
+        added_labels: only_in(&new.labels, &old.labels),
+        reordered_labels: reordered(&common(&old.labels, &new.labels),
+                                    &common(&new.labels, &old.labels)),
+        changed_labels: changed_labels,
+    }
+}
+
+fn change_text(prefix: &str, change: &FieldChange) -> String {
+    let value = |value: &Option<String>| match *value {
+        Some(ref value) => format!("{:?}", value),
+        None => String::from("none"),
+    };
+    format!("~ {}{}: {} -> {}\n", prefix, change.field, value(&change.old),
+            value(&change.new))
+}
+
+fn label_diff_text(prefix: &str, label_diff: &LabelDiff) -> String {
+    let mut text = String::new();
+    for change in &label_diff.changes {
+        text.push_str(&change_text(prefix, change));
+    }
+    if let Some(ref append) = label_diff.append {
+        let args: Vec<String> = append.added.iter()
+            .map(|arg| format!("+{}", arg))
+            .chain(append.removed.iter().map(|arg| format!("-{}", arg)))
+            .collect();
+        if !args.is_empty() {
+            text.push_str(&format!("~ {}append arguments: {}\n", prefix,
+                                   args.join(" ")));
+        }
+    }
+    text
+}
+
+impl ConfDiff {
+    pub fn is_empty(&self) -> bool {
+        self.global.is_empty() && self.label_defaults.is_none() &&
+            self.removed_labels.is_empty() && self.added_labels.is_empty() &&
+            self.reordered_labels.is_empty() && self.changed_labels.is_empty()
+    }
+
+    // One change per line, prefixed with "+", "-" or "~".
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for change in &self.global {
+            text.push_str(&change_text("global ", change));
+        }
+        if let Some(ref label_diff) = self.label_defaults {
+            text.push_str(&label_diff_text("defaults ", label_diff));
+        }
+        for name in &self.removed_labels {
+            text.push_str(&format!("- label {}\n", name));
+        }
+        for name in &self.added_labels {
+            text.push_str(&format!("+ label {}\n", name));
+        }
+        for name in &self.reordered_labels {
+            text.push_str(&format!("~ label {} moved\n", name));
+        }
+        for (name, label_diff) in self.changed_labels.iter() {
+            text.push_str(&label_diff_text(&format!("label {} ", name),
+                                           label_diff));
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::diff;
+    use test_util::parse;
+
+    const OLD: &'static str = "DEFAULT linux\n\
+                               TIMEOUT 50\n\
+                               LABEL linux\n\
+                               \x20 KERNEL vmlinuz\n\
+                               \x20 APPEND root=/dev/sda1 quiet quiet\n\
+                               LABEL rescue\n\
+                               \x20 KERNEL vmlinuz\n\
+                               LABEL memtest\n\
+                               \x20 LINUX memtest.bin\n\
+                               LABEL old\n\
+                               \x20 KERNEL vmlinuz.old\n";
+
+    #[test]
+    fn finds_no_changes() {
+        let diff = diff(&parse(OLD), &parse(OLD));
+        assert!(diff.is_empty());
+        assert_eq!(diff.to_text(), "");
+    }
+
+    #[test]
+    fn finds_global_changes() {
+        let new = OLD.replace("TIMEOUT 50", "TIMEOUT 100\nPROMPT 1");
+        let diff = diff(&parse(OLD), &parse(&new));
+
+        assert_eq!(diff.to_text(),
+                   "~ global timeout: \"5\" -> \"10\"\n\
+                    ~ global prompt: none -> \"1\"\n");
+    }
+
+    #[test]
+    fn finds_label_changes() {
+        let new = "DEFAULT linux\n\
+                   TIMEOUT 50\n\
+                   LABEL memtest\n\
+                   \x20 LINUX memtest.bin\n\
+                   LABEL linux\n\
+                   \x20 LINUX vmlinuz\n\
+                   \x20 APPEND root=/dev/sda2 quiet\n\
+                   LABEL rescue\n\
+                   \x20 KERNEL vmlinuz\n\
+                   LABEL new\n\
+                   \x20 KERNEL vmlinuz.new\n";
+        let diff = diff(&parse(OLD), &parse(new));
+
+        assert_eq!(diff.removed_labels, vec!["old"]);
+        assert_eq!(diff.added_labels, vec!["new"]);
+        assert_eq!(diff.reordered_labels, vec!["memtest"]);
+        let append = diff.changed_labels["linux"].append.clone().unwrap();
+        assert_eq!(append.added, vec!["root=/dev/sda2"]);
+        assert_eq!(append.removed, vec!["root=/dev/sda1", "quiet"]);
+        assert_eq!(diff.to_text(),
+                   "- label old\n\
+                    + label new\n\
+                    ~ label memtest moved\n\
+                    ~ label linux kernel_file: \"KERNEL vmlinuz\" -> \
+                    \"LINUX vmlinuz\"\n\
+                    ~ label linux append: \"root=/dev/sda1 quiet quiet\" -> \
+                    \"root=/dev/sda2 quiet\"\n\
+                    ~ label linux append arguments: +root=/dev/sda2 \
+                    -root=/dev/sda1 -quiet\n");
+    }
+
+    #[test]
+    fn finds_label_defaults_changes() {
+        let new = format!("APPEND quiet\n{}", OLD);
+        let diff = diff(&parse(OLD), &parse(&new));
+
+        assert!(diff.global.is_empty());
+        assert_eq!(diff.to_text(),
+                   "~ defaults append: none -> \"quiet\"\n\
+                    ~ defaults append arguments: +quiet\n");
+    }
+}
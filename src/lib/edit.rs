@@ -0,0 +1,587 @@
+use std;
+
+use types::{Kernel, KernelFile, KernelFileSource, KernelOrConfig, Label,
+            Labels, SyslinuxConf};
+
+#[derive(Debug)]
+pub enum EditError {
+    NoSuchLabel(String),
+    LabelExists(String),
+    InvalidLabelName(String),
+    // Label is the target of DEFAULT, ONTIMEOUT or ONERROR.
+    LabelReferenced(String, &'static str),
+    // Value which can not be written in syslinux syntax.
+    InvalidValue(String),
+    // Edited document can not be parsed back.
+    Reparse(Option<usize>),
+    // Line is not valid UTF-8, writing it back would replace invalid bytes.
+    InvalidEncoding(usize),
+}
+
+impl std::fmt::Display for EditError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            EditError::NoSuchLabel(ref name) => {
+                write!(f, "No such label: \"{}\"", name)
+            },
+            EditError::LabelExists(ref name) => {
+                write!(f, "Label \"{}\" already exists", name)
+            },
+            EditError::InvalidLabelName(ref name) => {
+                write!(f, "Invalid label name: \"{}\"", name)
+            },
+            EditError::LabelReferenced(ref name, tag) => {
+                write!(f, "Label \"{}\" is referenced by {}", name, tag)
+            },
+            EditError::InvalidValue(ref value) => {
+                write!(f, "Invalid value: \"{}\"", value)
+            },
+            EditError::Reparse(Some(line)) => {
+                write!(f, "Edited configuration can not be parsed at line {}",
+                       line)
+            },
+            EditError::Reparse(None) => {
+                write!(f, "Edited configuration can not be parsed")
+            },
+            EditError::InvalidEncoding(line) => {
+                write!(f, "Line {} is not valid UTF-8, encoding of \
+                           configuration has to be given", line)
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KernelField {
+    // Tag of label is kept, new one is KERNEL and its type is guessed from
+    // extension.
+    KernelFile,
+    InitRD,
+    FDT,
+    FDTDir,
+    FDTOverlays,
+    Append,
+}
+
+impl KernelField {
+    // Tags which set this field, the first one is used for new lines.
+    pub fn tags(&self) -> &'static [&'static str] {
+        const KERNEL_FILE_TAGS: [&'static str; 8] = [
+            "KERNEL", "LINUX", "BOOT", "BSS", "PXE", "FDIMAGE", "COMBOOT",
+            "COM32"];
+        const INITRD_TAGS: [&'static str; 1] = ["INITRD"];
+        const FDT_TAGS: [&'static str; 2] = ["FDT", "DEVICETREE"];
+        const FDTDIR_TAGS: [&'static str; 2] = ["FDTDIR", "DEVICETREEDIR"];
+        const FDTOVERLAYS_TAGS: [&'static str; 1] = ["FDTOVERLAYS"];
+        const APPEND_TAGS: [&'static str; 1] = ["APPEND"];
+
+        match *self {
+            KernelField::KernelFile  => &KERNEL_FILE_TAGS,
+            KernelField::InitRD      => &INITRD_TAGS,
+            KernelField::FDT         => &FDT_TAGS,
+            KernelField::FDTDir      => &FDTDIR_TAGS,
+            KernelField::FDTOverlays => &FDTOVERLAYS_TAGS,
+            KernelField::Append      => &APPEND_TAGS,
+        }
+    }
+}
+
+fn check_value(value: &str) -> Result<(), EditError> {
+    match value.contains('\n') || value.contains('\r') {
+        true => Err(EditError::InvalidValue(String::from(value))),
+        false => Ok(()),
+    }
+}
+
+// Label names are typed at the boot prompt as the first word of command.
+fn check_label_name(name: &str) -> Result<(), EditError> {
+    match name.is_empty() || name.contains(char::is_whitespace) {
+        true => Err(EditError::InvalidLabelName(String::from(name))),
+        false => Ok(()),
+    }
+}
+
+fn path_to_string(path: &std::path::PathBuf) -> String {
+    String::from(path.to_string_lossy())
+}
+
+fn string_to_path(value: &str) -> std::path::PathBuf {
+    std::path::PathBuf::from(value.trim())
+}
+
+// Label referenced by DEFAULT, ONTIMEOUT or ONERROR command.
+pub fn command_label(command: &Option<String>) -> Option<&str> {
+    command.as_ref().and_then(|command| command.split_whitespace().next())
+}
+
+pub fn rename_command(command: &mut Option<String>, old_name: &str,
+                      new_name: &str) {
+    let renamed = match command_label(command) {
+        Some(label_name) if label_name == old_name => {
+            let command = command.as_ref().unwrap().trim();
+            Some(format!("{}{}", new_name, &command[old_name.len()..]))
+        },
+        _ => None,
+    };
+    if renamed.is_some() {
+        *command = renamed;
+    }
+}
+
+impl KernelFile {
+    // The same type of kernel file.
+    pub fn with_path(&self, path: std::path::PathBuf) -> KernelFile {
+        match *self {
+            KernelFile::Linux(_)   => KernelFile::Linux(path),
+            KernelFile::Boot(_)    => KernelFile::Boot(path),
+            KernelFile::BSS(_)     => KernelFile::BSS(path),
+            KernelFile::PXE(_)     => KernelFile::PXE(path),
+            KernelFile::FDImage(_) => KernelFile::FDImage(path),
+            KernelFile::ComBoot(_) => KernelFile::ComBoot(path),
+            KernelFile::Com32(_)   => KernelFile::Com32(path),
+        }
+    }
+}
+
+impl Label {
+    pub fn kernel(&self) -> &Kernel {
+        match self.kernel_or_config {
+            KernelOrConfig::Kernel(ref kernel) => kernel,
+        }
+    }
+
+    pub fn kernel_mut(&mut self) -> &mut Kernel {
+        match self.kernel_or_config {
+            KernelOrConfig::Kernel(ref mut kernel) => kernel,
+        }
+    }
+}
+
+impl Kernel {
+    pub fn get_field(&self, field: KernelField) -> Option<String> {
+        match field {
+            KernelField::KernelFile => {
+                self.kernel_file.as_ref().map(
+                    |kernel_file| path_to_string(kernel_file.path()))
+            },
+            KernelField::InitRD => self.initrd.as_ref().map(path_to_string),
+            KernelField::FDT => self.fdt.as_ref().map(path_to_string),
+            KernelField::FDTDir => self.fdt_dir.as_ref().map(path_to_string),
+            KernelField::FDTOverlays => match self.fdt_overlays.is_empty() {
+                true => None,
+                false => Some(self.fdt_overlays.iter()
+                    .map(path_to_string)
+                    .collect::<Vec<String>>()
+                    .join(" ")),
+            },
+            KernelField::Append => self.append.clone(),
+        }
+    }
+
+    // Sets field as if it was read from the tag with given value, None
+    // clears the field.
+    pub fn set_field(&mut self, field: KernelField, value: Option<&str>)
+            -> Result<(), EditError> {
+        if let Some(value) = value {
+            try!(check_value(value));
+        }
+
+        match field {
+            KernelField::KernelFile => {
+                // Type given by tag is kept, KERNEL guesses it from
+                // extension.
+                let keep_type = value.is_some() &&
+                    self.kernel_file.is_some() &&
+                    self.kernel_file_source == Some(KernelFileSource::Tag);
+                match keep_type {
+                    true => {
+                        let path = string_to_path(value.unwrap());
+                        self.kernel_file = self.kernel_file.as_ref().map(
+                            |kernel_file| kernel_file.with_path(path));
+                    },
+                    false => {
+                        self.kernel_file = value.map(
+                            |value| KernelFile::guess(string_to_path(value)));
+                        self.kernel_file_source = value.map(
+                            |_| KernelFileSource::Extension);
+                    },
+                };
+                self.linux_info = None;
+            },
+            KernelField::InitRD => {
+                self.initrd = value.map(string_to_path);
+                self.initrd_info = None;
+            },
+            KernelField::FDT => self.fdt = value.map(string_to_path),
+            KernelField::FDTDir => self.fdt_dir = value.map(string_to_path),
+            KernelField::FDTOverlays => {
+                self.fdt_overlays = match value {
+                    Some(value) => {
+                        value.split_whitespace().map(string_to_path).collect()
+                    },
+                    None => Vec::new(),
+                };
+            },
+            KernelField::Append => {
+                self.append = value.map(|value| String::from(value.trim()));
+            },
+        };
+        Ok(())
+    }
+
+    // Adds kernel parameter to APPEND unless it is already there.
+    pub fn add_param(&mut self, param: &str) -> Result<(), EditError> {
+        let param = param.trim();
+        if param.is_empty() || param.contains(char::is_whitespace) {
+            return Err(EditError::InvalidValue(String::from(param)));
+        }
+
+        let append = match self.append {
+            // "APPEND -" means no arguments.
+            Some(ref append) if append.trim() != "-" => {
+                match append.split_whitespace().any(|arg| arg == param) {
+                    true => return Ok(()),
+                    false => format!("{} {}", append.trim(), param),
+                }
+            },
+            _ => String::from(param),
+        };
+        self.append = Some(append);
+        Ok(())
+    }
+
+    // Removes kernel parameters which are equal to param or, if it has no
+    // "=", have the same name. "APPEND -" is kept when nothing is left, so
+    // that label does not inherit APPEND of global scope.
+    pub fn remove_param(&mut self, param: &str) {
+        let matches = |arg: &str| match param.contains('=') {
+            true => arg == param,
+            false => arg == param || arg.starts_with(&format!("{}=", param)),
+        };
+
+        if let Some(append) = self.append.take() {
+            let args: Vec<&str> = append.split_whitespace()
+                .filter(|arg| !matches(arg))
+                .collect();
+            self.append = match args.is_empty() {
+                true => Some(String::from("-")),
+                false => Some(args.join(" ")),
+            };
+        }
+    }
+}
+
+impl SyslinuxConf {
+    pub fn label_mut(&mut self, name: &str) -> Result<&mut Label, EditError> {
+        match self.labels.get_mut(name) {
+            Some(label) => Ok(label),
+            None => Err(EditError::NoSuchLabel(String::from(name))),
+        }
+    }
+
+    // APPEND in effect for label, the one of global scope is inherited by
+    // labels without their own.
+    fn effective_kernel(&mut self, name: &str)
+            -> Result<&mut Kernel, EditError> {
+        let default_append = self.global.label_defaults.kernel().append
+            .clone();
+        let kernel = try!(self.label_mut(name)).kernel_mut();
+        if kernel.append.is_none() {
+            kernel.append = default_append;
+        }
+        Ok(kernel)
+    }
+
+    pub fn add_param(&mut self, label_name: &str, param: &str)
+            -> Result<(), EditError> {
+        try!(self.effective_kernel(label_name)).add_param(param)
+    }
+
+    pub fn remove_param(&mut self, label_name: &str, param: &str)
+            -> Result<(), EditError> {
+        try!(self.effective_kernel(label_name)).remove_param(param);
+        Ok(())
+    }
+
+    // Adds label to the end.
+    pub fn add_label(&mut self, name: &str, label: Label)
+            -> Result<(), EditError> {
+        try!(check_label_name(name));
+        if self.labels.contains_key(name) {
+            return Err(EditError::LabelExists(String::from(name)));
+        }
+        self.labels.insert(String::from(name), label);
+        Ok(())
+    }
+
+    // Fails if label is still referenced from global scope.
+    pub fn remove_label(&mut self, name: &str) -> Result<Label, EditError> {
+        let references = [("DEFAULT", &self.global.default),
+                          ("ONTIMEOUT", &self.global.ontimeout),
+                          ("ONERROR", &self.global.onerror)];
+        for &(tag, command) in references.iter() {
+            if command_label(command) == Some(name) {
+                return Err(EditError::LabelReferenced(String::from(name),
+                                                      tag));
+            }
+        }
+
+        match self.labels.remove(name) {
+            Some(label) => Ok(label),
+            None => Err(EditError::NoSuchLabel(String::from(name))),
+        }
+    }
+
+    // Keeps position of label and updates references to it.
+    pub fn rename_label(&mut self, old_name: &str, new_name: &str)
+            -> Result<(), EditError> {
+        try!(check_label_name(new_name));
+        if !self.labels.contains_key(old_name) {
+            return Err(EditError::NoSuchLabel(String::from(old_name)));
+        }
+        if old_name != new_name && self.labels.contains_key(new_name) {
+            return Err(EditError::LabelExists(String::from(new_name)));
+        }
+
+        let mut labels = Labels::new();
+        for (name, label) in std::mem::replace(&mut self.labels,
+                                               Labels::new()) {
+            match name == old_name {
+                true => labels.insert(String::from(new_name), label),
+                false => labels.insert(name, label),
+            };
+        }
+        self.labels = labels;
+
+        rename_command(&mut self.global.default, old_name, new_name);
+        rename_command(&mut self.global.ontimeout, old_name, new_name);
+        rename_command(&mut self.global.onerror, old_name, new_name);
+        Ok(())
+    }
+
+    // Moves label to given position, positions past the end mean the end.
+    pub fn move_label(&mut self, name: &str, index: usize)
+            -> Result<(), EditError> {
+        let label = match self.labels.remove(name) {
+            Some(label) => label,
+            None => return Err(EditError::NoSuchLabel(String::from(name))),
+        };
+
+        let mut pairs: Vec<(String, Label)> =
+            std::mem::replace(&mut self.labels, Labels::new())
+                .into_iter()
+                .collect();
+        let index = std::cmp::min(index, pairs.len());
+        pairs.insert(index, (String::from(name), label));
+
+        use std::iter::FromIterator;
+        self.labels = Labels::from_iter(pairs);
+        Ok(())
+    }
+
+    // None removes DEFAULT.
+    pub fn set_default(&mut self, name: Option<&str>) -> Result<(), EditError> {
+        if let Some(name) = name {
+            if !self.labels.contains_key(name) {
+                return Err(EditError::NoSuchLabel(String::from(name)));
+            }
+        }
+        self.global.default = name.map(String::from);
+        Ok(())
+    }
+
+    pub fn set_kernel_field(&mut self, label_name: &str, field: KernelField,
+                            value: Option<&str>) -> Result<(), EditError> {
+        try!(self.label_mut(label_name)).kernel_mut().set_field(field, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std;
+
+    use super::{EditError, KernelField, rename_command};
+    use test_util::parse;
+    use types::{KernelFile, KernelFileSource, Label};
+
+    const CONF: &'static str = "DEFAULT linux quiet\n\
+                                ONERROR rescue\n\
+                                LABEL linux\n\
+                                \x20 KERNEL vmlinuz\n\
+                                \x20 APPEND root=/dev/sda1 console=tty0\n\
+                                LABEL rescue\n\
+                                \x20 KERNEL vmlinuz\n\
+                                LABEL memtest\n\
+                                \x20 LINUX memtest.bin\n";
+
+    #[test]
+    fn renames_commands() {
+        let mut command = Some(String::from("linux single"));
+        rename_command(&mut command, "linux", "default");
+        assert_eq!(command, Some(String::from("default single")));
+
+        let mut command = Some(String::from("linux2"));
+        rename_command(&mut command, "linux", "default");
+        assert_eq!(command, Some(String::from("linux2")));
+    }
+
+    #[test]
+    fn adds_label() {
+        let mut conf = parse(CONF);
+
+        conf.add_label("new", Label::default()).unwrap();
+        assert_eq!(conf.labels.keys().last().unwrap(), "new");
+        match conf.add_label("new", Label::default()) {
+            Err(EditError::LabelExists(_)) => (),
+            result => panic!("Unexpected result {:?}", result),
+        };
+        match conf.add_label("two words", Label::default()) {
+            Err(EditError::InvalidLabelName(_)) => (),
+            result => panic!("Unexpected result {:?}", result),
+        };
+    }
+
+    #[test]
+    fn removes_only_unreferenced_label() {
+        let mut conf = parse(CONF);
+
+        match conf.remove_label("linux") {
+            Err(EditError::LabelReferenced(_, "DEFAULT")) => (),
+            result => panic!("Unexpected result {:?}", result),
+        };
+        match conf.remove_label("rescue") {
+            Err(EditError::LabelReferenced(_, "ONERROR")) => (),
+            result => panic!("Unexpected result {:?}", result),
+        };
+        conf.remove_label("memtest").unwrap();
+        assert!(!conf.labels.contains_key("memtest"));
+        match conf.remove_label("memtest") {
+            Err(EditError::NoSuchLabel(_)) => (),
+            result => panic!("Unexpected result {:?}", result),
+        };
+    }
+
+    #[test]
+    fn renames_label_in_place() {
+        let mut conf = parse(CONF);
+
+        conf.rename_label("linux", "default").unwrap();
+        let names: Vec<&str> = conf.labels.keys().map(|k| k.as_str())
+            .collect();
+        assert_eq!(names, vec!["default", "rescue", "memtest"]);
+        assert_eq!(conf.global.default, Some(String::from("default quiet")));
+        match conf.rename_label("default", "rescue") {
+            Err(EditError::LabelExists(_)) => (),
+            result => panic!("Unexpected result {:?}", result),
+        };
+    }
+
+    #[test]
+    fn moves_label() {
+        let mut conf = parse(CONF);
+
+        conf.move_label("memtest", 0).unwrap();
+        let names: Vec<&str> = conf.labels.keys().map(|k| k.as_str())
+            .collect();
+        assert_eq!(names, vec!["memtest", "linux", "rescue"]);
+
+        conf.move_label("memtest", 100).unwrap();
+        assert_eq!(conf.labels.keys().last().unwrap(), "memtest");
+        assert_eq!(conf.labels.len(), 3);
+    }
+
+    #[test]
+    fn sets_default() {
+        let mut conf = parse(CONF);
+
+        conf.set_default(Some("rescue")).unwrap();
+        assert_eq!(conf.global.default, Some(String::from("rescue")));
+        conf.set_default(None).unwrap();
+        assert_eq!(conf.global.default, None);
+        match conf.set_default(Some("missing")) {
+            Err(EditError::NoSuchLabel(_)) => (),
+            result => panic!("Unexpected result {:?}", result),
+        };
+    }
+
+    #[test]
+    fn sets_kernel_fields() {
+        let mut conf = parse(CONF);
+
+        conf.set_kernel_field("linux", KernelField::KernelFile,
+                              Some("menu.c32")).unwrap();
+        conf.set_kernel_field("linux", KernelField::FDTOverlays,
+                              Some("a.dtbo b.dtbo")).unwrap();
+        conf.set_kernel_field("linux", KernelField::Append, None).unwrap();
+
+        let kernel = conf.labels["linux"].kernel();
+        assert_eq!(kernel.kernel_file, Some(KernelFile::Com32(
+            std::path::PathBuf::from("menu.c32"))));
+        assert_eq!(kernel.kernel_file_source,
+                   Some(KernelFileSource::Extension));
+        assert_eq!(kernel.get_field(KernelField::FDTOverlays),
+                   Some(String::from("a.dtbo b.dtbo")));
+        assert_eq!(kernel.append, None);
+        match conf.set_kernel_field("linux", KernelField::InitRD,
+                                    Some("initrd\nAPPEND x")) {
+            Err(EditError::InvalidValue(_)) => (),
+            result => panic!("Unexpected result {:?}", result),
+        };
+    }
+
+    #[test]
+    fn keeps_type_given_by_tag() {
+        let mut conf = parse(CONF);
+
+        conf.set_kernel_field("memtest", KernelField::KernelFile,
+                              Some("memtest.c32")).unwrap();
+        let kernel = conf.labels["memtest"].kernel();
+        assert_eq!(kernel.kernel_file, Some(KernelFile::Linux(
+            std::path::PathBuf::from("memtest.c32"))));
+        assert_eq!(kernel.kernel_file_source, Some(KernelFileSource::Tag));
+    }
+
+    #[test]
+    fn adds_and_removes_params() {
+        let mut conf = parse(CONF);
+        let kernel = conf.label_mut("linux").unwrap().kernel_mut();
+
+        kernel.add_param("quiet").unwrap();
+        kernel.add_param("quiet").unwrap();
+        assert_eq!(kernel.append,
+                   Some(String::from("root=/dev/sda1 console=tty0 quiet")));
+        assert!(kernel.add_param("two words").is_err());
+
+        kernel.remove_param("console");
+        kernel.remove_param("root=/dev/sda2");
+        assert_eq!(kernel.append, Some(String::from("root=/dev/sda1 quiet")));
+        kernel.remove_param("root");
+        kernel.remove_param("quiet");
+        assert_eq!(kernel.append, Some(String::from("-")));
+
+        kernel.append = Some(String::from("-"));
+        kernel.add_param("quiet").unwrap();
+        assert_eq!(kernel.append, Some(String::from("quiet")));
+    }
+
+    #[test]
+    fn changes_inherited_params() {
+        let mut conf = parse("APPEND root=/dev/sda1 quiet\n\
+                              LABEL linux\n\
+                              \x20 KERNEL vmlinuz\n\
+                              LABEL rescue\n\
+                              \x20 KERNEL vmlinuz\n");
+
+        conf.add_param("linux", "single").unwrap();
+        assert_eq!(conf.labels["linux"].kernel().append,
+                   Some(String::from("root=/dev/sda1 quiet single")));
+        conf.remove_param("rescue", "quiet").unwrap();
+        assert_eq!(conf.labels["rescue"].kernel().append,
+                   Some(String::from("root=/dev/sda1")));
+        conf.remove_param("rescue", "root").unwrap();
+        assert_eq!(conf.labels["rescue"].kernel().append,
+                   Some(String::from("-")));
+        assert_eq!(conf.global.label_defaults.kernel().append,
+                   Some(String::from("root=/dev/sda1 quiet")));
+    }
+}
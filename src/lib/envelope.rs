@@ -0,0 +1,117 @@
+use std;
+
+use reader::{Reader, ReaderError};
+use schema::SCHEMA_VERSION;
+use types::{Diagnostic, Envelope, SyslinuxConf};
+
+fn absolute(path: &std::path::Path) -> std::path::PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+impl Envelope {
+    // Reader could not be created: configuration file was not found, etc.
+    pub fn from_error(root_dir: &std::path::Path, err: &ReaderError)
+            -> Envelope {
+        Envelope{
+            format_version: String::from(SCHEMA_VERSION),
+            conf_type: None,
+            root_dir: Some(absolute(root_dir)),
+            conf_file_path: None,
+            included_files: Vec::new(),
+            diagnostics: vec![err.to_diagnostic()],
+            conf: None,
+        }
+    }
+
+    pub fn from_reader(reader: &Reader,
+                       result: Result<(SyslinuxConf, Vec<Diagnostic>),
+                                      ReaderError>) -> Envelope {
+        let (conf, diagnostics) = match result {
+            Ok((conf, diagnostics)) => (Some(conf), diagnostics),
+            Err(err) => {
+                let mut diagnostics = err.diagnostics().to_vec();
+                diagnostics.push(err.to_diagnostic());
+                (None, diagnostics)
+            },
+        };
+
+        Envelope{
+            format_version: String::from(SCHEMA_VERSION),
+            conf_type: reader.conf_type().map(
+                |conf_type| format!("{:?}", conf_type)),
+            root_dir: Some(absolute(reader.root_dir())),
+            conf_file_path: Some(absolute(reader.conf_file_path())),
+            included_files: reader.conf_files().iter()
+                .skip(1)
+                .map(|path| absolute(path))
+                .collect(),
+            diagnostics: diagnostics,
+            conf: conf,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std;
+
+    use reader::{Reader, ReaderError};
+    use schema::SCHEMA_VERSION;
+    use test_util::TempDir;
+    use types::Envelope;
+
+    #[test]
+    fn wraps_configuration() {
+        let dir = TempDir::new();
+        let conf_file = dir.write("boot/extlinux/extlinux.conf",
+                                  b"INCLUDE menu.conf\n\
+                                    LABEL linux\n\
+                                    \x20 KERNEL vmlinuz\n");
+        let menu_file = dir.write("boot/extlinux/menu.conf", b"");
+        let reader = Reader::from_local(dir.path().to_path_buf()).unwrap();
+
+        let envelope = Envelope::from_reader(
+            &reader, reader.read_with_diagnostics());
+        assert_eq!(envelope.format_version, SCHEMA_VERSION);
+        assert_eq!(envelope.conf_type, Some(String::from("ExtLinux")));
+        assert_eq!(envelope.root_dir,
+                   Some(std::fs::canonicalize(dir.path()).unwrap()));
+        assert_eq!(envelope.conf_file_path,
+                   Some(std::fs::canonicalize(conf_file).unwrap()));
+        assert_eq!(envelope.included_files,
+                   vec![std::fs::canonicalize(menu_file).unwrap()]);
+        assert!(envelope.diagnostics.is_empty());
+        assert!(envelope.conf.unwrap().labels.contains_key("linux"));
+    }
+
+    #[test]
+    fn reports_read_error() {
+        let dir = TempDir::new();
+        dir.write("syslinux.cfg", b"");
+        let reader = Reader::from_local(dir.path().to_path_buf()).unwrap();
+        let err = ReaderError::Parse(reader.conf_file_path().to_path_buf(),
+                                     Some(3), Vec::new());
+
+        let envelope = Envelope::from_reader(&reader, Err(err));
+        assert_eq!(envelope.conf_type, Some(String::from("SysLinux")));
+        assert!(envelope.conf.is_none());
+        assert_eq!(envelope.diagnostics.len(), 1);
+        assert_eq!(envelope.diagnostics[0].code, "parse");
+        assert!(envelope.diagnostics[0].message.contains("line 3"));
+    }
+
+    #[test]
+    fn reports_missing_configuration() {
+        let dir = TempDir::new();
+        let err = Reader::from_local(dir.path().to_path_buf()).unwrap_err();
+
+        let envelope = Envelope::from_error(dir.path(), &err);
+        assert_eq!(envelope.format_version, SCHEMA_VERSION);
+        assert_eq!(envelope.conf_type, None);
+        assert_eq!(envelope.root_dir,
+                   Some(std::fs::canonicalize(dir.path()).unwrap()));
+        assert_eq!(envelope.conf_file_path, None);
+        assert!(envelope.conf.is_none());
+        assert_eq!(envelope.diagnostics[0].code, "conf-not-found");
+    }
+}
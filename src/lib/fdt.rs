@@ -0,0 +1,272 @@
+use std;
+
+use types::Kernel;
+
+const FDT_MAGIC: u32 = 0xd00dfeed;
+
+// Offsets of fields of flattened device tree header, all fields are
+// big-endian u32.
+const FDT_OFF_DT_STRUCT_OFFSET: usize = 8;
+const FDT_OFF_DT_STRINGS_OFFSET: usize = 12;
+
+// Tokens of structure block.
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+
+macro_rules! try_opt {
+    ($expr: expr) => {
+        match $expr {
+            Some(v) => v,
+            None => return None,
+        }
+    }
+}
+
+fn get_u32_be(data: &[u8], offset: usize) -> Option<u32> {
+    let end = try_opt!(offset.checked_add(4));
+    match data.len() >= end {
+        true => Some(data[offset..end].iter().fold(
+            0, |value, byte| (value << 8) | *byte as u32)),
+        false => None,
+    }
+}
+
+// Length of NUL-terminated string including terminator.
+fn c_string_len(data: &[u8], offset: usize) -> Option<usize> {
+    match offset < data.len() {
+        true => data[offset..].iter().position(|v| *v == 0).map(|len| len + 1),
+        false => None,
+    }
+}
+
+fn align4(offset: usize) -> Option<usize> {
+    offset.checked_add(3).map(|offset| offset & !3)
+}
+
+// Values of "compatible" property of the root node, most specific first.
+// Offsets and lengths come from the file, so all of them are checked.
+pub fn read_compatible(data: &[u8]) -> Option<Vec<String>> {
+    if get_u32_be(data, 0) != Some(FDT_MAGIC) {
+        return None;
+    }
+
+    let strings = try_opt!(get_u32_be(data, FDT_OFF_DT_STRINGS_OFFSET))
+        as usize;
+    let mut offset = try_opt!(get_u32_be(data, FDT_OFF_DT_STRUCT_OFFSET))
+        as usize;
+    let mut depth: usize = 0;
+
+    loop {
+        let token = try_opt!(get_u32_be(data, offset));
+        offset += 4;
+
+        match token {
+            FDT_BEGIN_NODE => {
+                let name_len = try_opt!(c_string_len(data, offset));
+                offset = try_opt!(align4(offset + name_len));
+                depth += 1;
+            },
+
+            FDT_END_NODE => {
+                depth = try_opt!(depth.checked_sub(1));
+                // Root node is over, there is no "compatible" property.
+                if depth == 0 {
+                    return None;
+                }
+            },
+
+            FDT_PROP => {
+                let len = try_opt!(get_u32_be(data, offset)) as usize;
+                let name_offset = try_opt!(get_u32_be(data, offset + 4))
+                    as usize;
+                let value_offset = offset + 8;
+                let value_end = try_opt!(value_offset.checked_add(len));
+                offset = try_opt!(align4(value_end));
+
+                let name_start = try_opt!(strings.checked_add(name_offset));
+                let name_len = try_opt!(c_string_len(data, name_start));
+                let name = &data[name_start..name_start + name_len - 1];
+
+                if depth == 1 && name == b"compatible" {
+                    if data.len() < value_end {
+                        return None;
+                    }
+                    return Some(
+                        data[value_offset..value_end]
+                            .split(|v| *v == 0)
+                            .filter(|value| !value.is_empty())
+                            .map(|value| {
+                                String::from_utf8_lossy(value).into_owned()
+                            })
+                            .collect());
+                }
+            },
+
+            FDT_NOP => (),
+            // FDT_END or broken structure block.
+            _ => return None,
+        }
+    }
+}
+
+fn is_compatible(path: &std::path::Path, compatible: &str) -> bool {
+    use std::io::Read;
+
+    let mut data = Vec::new();
+    let result = std::fs::File::open(path)
+        .and_then(|mut file| file.read_to_end(&mut data));
+    if let Err(err) = result {
+        warn!("Unable to read device tree {:?}: {}", path, err);
+        return false;
+    }
+
+    match read_compatible(&data) {
+        Some(values) => values.iter().any(|value| value == compatible),
+        None => {
+            debug!("No root compatible property in {:?}", path);
+            false
+        },
+    }
+}
+
+// Looks for "*.dtb" inside of directory (recursively, as distributions
+// install device trees into per-vendor subdirectories) with given compatible
+// string of the root node.
+pub fn find_dtb(dir: &std::path::Path, compatible: &str)
+        -> Option<std::path::PathBuf> {
+    let mut dir_entries: Vec<(std::path::PathBuf, std::fs::FileType)> =
+        match std::fs::read_dir(dir) {
+            Ok(dir_entries) => dir_entries
+                .filter_map(|dir_entry| dir_entry.ok())
+                .filter_map(|dir_entry| match dir_entry.file_type() {
+                    Ok(file_type) => Some((dir_entry.path(), file_type)),
+                    Err(_) => None,
+                })
+                .collect(),
+            Err(err) => {
+                warn!("Unable to list directory {:?}: {}", dir, err);
+                return None;
+            },
+        };
+    dir_entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+    for (dir_entry, file_type) in dir_entries {
+        // Symlinks to directories are not followed, they may form a loop.
+        let found = match file_type.is_dir() {
+            true => find_dtb(&dir_entry, compatible),
+            false => match dir_entry.extension() {
+                Some(extension) if extension == "dtb" &&
+                        is_compatible(&dir_entry, compatible) => {
+                    Some(dir_entry.clone())
+                },
+                _ => None,
+            },
+        };
+        if found.is_some() {
+            return found;
+        }
+    }
+
+    None
+}
+
+impl Kernel {
+    // Device tree U-Boot would pass to the kernel on the board with given
+    // compatible string. FDT wins over FDTDIR, like in U-Boot. Paths in
+    // kernel should be already resolved.
+    pub fn resolve_dtb(&self, compatible: &str)
+            -> Option<std::path::PathBuf> {
+        match (&self.fdt, &self.fdt_dir) {
+            (&Some(ref fdt), _) => Some(fdt.clone()),
+            (&None, &Some(ref fdt_dir)) => find_dtb(fdt_dir, compatible),
+            (&None, &None) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std;
+
+    use super::{find_dtb, read_compatible};
+    use test_util::TempDir;
+
+    fn push_u32(data: &mut Vec<u8>, value: u32) {
+        for shift in [24, 16, 8, 0].iter() {
+            data.push((value >> *shift) as u8);
+        }
+    }
+
+    // Root node with single property.
+    fn dtb_with(value: &[u8], value_len: u32, name_offset: u32) -> Vec<u8> {
+        let mut structure = Vec::new();
+        push_u32(&mut structure, 1);
+        push_u32(&mut structure, 0);
+        push_u32(&mut structure, 3);
+        push_u32(&mut structure, value_len);
+        push_u32(&mut structure, name_offset);
+        structure.extend_from_slice(value);
+        while structure.len() % 4 != 0 {
+            structure.push(0);
+        }
+        push_u32(&mut structure, 2);
+        push_u32(&mut structure, 9);
+
+        let strings = b"compatible\0";
+        let header_size = 40;
+        let mut data = Vec::new();
+        push_u32(&mut data, 0xd00dfeed);
+        push_u32(&mut data, (header_size + structure.len() +
+                             strings.len()) as u32);
+        push_u32(&mut data, header_size as u32);
+        push_u32(&mut data, (header_size + structure.len()) as u32);
+        while data.len() < header_size {
+            data.push(0);
+        }
+        data.extend(structure);
+        data.extend_from_slice(strings);
+        data
+    }
+
+    fn dtb(compatible: &[u8]) -> Vec<u8> {
+        dtb_with(compatible, compatible.len() as u32, 0)
+    }
+
+    #[test]
+    fn reads_root_compatible() {
+        assert_eq!(read_compatible(&dtb(b"vendor,board\0vendor,soc\0")),
+                   Some(vec![String::from("vendor,board"),
+                             String::from("vendor,soc")]));
+    }
+
+    #[test]
+    fn rejects_broken_device_trees() {
+        assert_eq!(read_compatible(b""), None);
+        assert_eq!(read_compatible(b"\xd0\x0d\xfe\xed"), None);
+        assert_eq!(read_compatible(&dtb_with(b"a\0", 0xffffffff, 0)), None);
+        assert_eq!(read_compatible(&dtb_with(b"a\0", 2, 0xffffffff)), None);
+
+        let data = dtb(b"vendor,board\0");
+        for len in 0..data.len() {
+            assert_eq!(read_compatible(&data[..len]), None);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn finds_dtb_without_following_directory_symlinks() {
+        let dir = TempDir::new();
+        dir.write("dtbs/a/other.dtb", &dtb(b"vendor,other\0"));
+        dir.write("dtbs/b/board.dtb", &dtb(b"vendor,board\0"));
+        dir.write("dtbs/b/board.txt", b"");
+        std::os::unix::fs::symlink(dir.path().join("dtbs"),
+                                   dir.path().join("dtbs/a/loop")).unwrap();
+
+        let dtbs = dir.path().join("dtbs");
+        assert_eq!(find_dtb(&dtbs, "vendor,board"),
+                   Some(dtbs.join("b/board.dtb")));
+        assert_eq!(find_dtb(&dtbs, "vendor,missing"), None);
+    }
+}
@@ -0,0 +1,955 @@
+use std;
+
+use reader::get_device_path;
+use types::{ApplyDefaults, Diagnostic, Global, KernelFile, KernelFileSource,
+            KernelOrConfig, Label, Labels, Severity, SyslinuxConf};
+
+// Quote string for GRUB script, which follows rules of POSIX shell.
+pub fn quote(value: &str) -> String {
+    format!("'{}'", value.replace("'", "'\\''"))
+}
+
+// Quote word only if it has characters special to GRUB script, to keep
+// kernel command line readable.
+fn quote_word(value: &str) -> String {
+    let plain = !value.is_empty() && value.chars().all(|c| {
+        c.is_ascii_alphanumeric() || "_-+=.,:/@%".contains(c)
+    });
+    match plain {
+        true => String::from(value),
+        false => quote(value),
+    }
+}
+
+struct GrubWriter<'a> {
+    root_dir: &'a std::path::Path,
+    output: String,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> GrubWriter<'a> {
+    fn line(&mut self, indent: usize, line: &str) {
+        for _ in 0..indent {
+            self.output.push_str("    ");
+        }
+        self.output.push_str(line);
+        self.output.push('\n');
+    }
+
+    fn warn(&mut self, label_name: Option<&str>, code: &str, message: String) {
+        let diagnostic = Diagnostic::new(Severity::Warning, code, message);
+        self.diagnostics.push(match label_name {
+            Some(label_name) => diagnostic.for_label(label_name),
+            None => diagnostic,
+        });
+    }
+
+    fn path(&mut self, label_name: &str, path: &std::path::Path)
+            -> Option<String> {
+        match get_device_path(self.root_dir, path) {
+            Some(device_path) => {
+                Some(quote(&device_path.to_string_lossy()))
+            },
+
+            None => {
+                self.warn(Some(label_name), "grub-outside-root", format!(
+                    "File {:?} is outside of the boot device", path));
+                None
+            },
+        }
+    }
+
+    fn global(&mut self, conf: &SyslinuxConf) {
+        let global: &Global = &conf.global;
+
+        if let Some(ref default) = global.default {
+            match conf.labels.contains_key(default) {
+                true => {
+                    let line = format!("set default={}", quote(default));
+                    self.line(0, &line);
+                },
+                false => self.warn(None, "grub-default-not-label", format!(
+                    "DEFAULT \"{}\" is not a label, GRUB will boot the \
+                     first menu entry", default)),
+            }
+        }
+
+        if let Some(timeout) = global.timeout {
+            let line = format!("set timeout={}", timeout.ceil() as u64);
+            self.line(0, &line);
+        }
+
+        if global.ontimeout.is_some() && global.ontimeout != global.default {
+            self.warn(None, "grub-ontimeout", String::from(
+                "ONTIMEOUT has no GRUB equivalent, default entry will be \
+                 booted on timeout"));
+        }
+
+        if global.onerror.is_some() {
+            self.warn(None, "grub-onerror", String::from(
+                "ONERROR has no GRUB equivalent"));
+        }
+
+        if global.total_timeout.is_some() {
+            self.warn(None, "grub-total-timeout", String::from(
+                "TOTALTIMEOUT has no GRUB equivalent"));
+        }
+
+        for directive in &global.unknown_directives {
+            self.warn(None, "grub-unknown-directive", format!(
+                "{} is not understood, it is skipped", directive.keyword));
+        }
+    }
+
+    fn label(&mut self, label_name: &str, label: &Label) {
+        let kernel = match label.kernel_or_config {
+            KernelOrConfig::Kernel(ref kernel) => kernel,
+        };
+
+        let kernel_file = match kernel.kernel_file {
+            Some(ref kernel_file) => kernel_file,
+            None => {
+                self.warn(Some(label_name), "grub-no-kernel", String::from(
+                    "Label has no kernel, skipped"));
+                return;
+            },
+        };
+
+        let mut commands = Vec::new();
+
+        if let Some(ref say) = label.say {
+            commands.push(format!("echo {}", quote(say)));
+        }
+
+        match *kernel_file {
+            KernelFile::Linux(ref path) => {
+                let path = match self.path(label_name, path) {
+                    Some(path) => path,
+                    None => return,
+                };
+
+                // "APPEND -" means no arguments at all.
+                let append = match kernel.append {
+                    Some(ref append) if append != "-" => append.clone(),
+                    _ => String::new(),
+                };
+                if append.split_whitespace().any(
+                        |arg| arg.starts_with("initrd=")) {
+                    self.warn(Some(label_name), "grub-initrd-in-append",
+                              String::from(
+                                  "initrd= in APPEND is handled by syslinux, \
+                                   GRUB will only pass it to the kernel"));
+                }
+
+                // Every argument is a separate word, so that "$", ";" or
+                // quotes in it are passed to the kernel as they are.
+                commands.push(append.split_whitespace().fold(
+                    format!("linux {}", path),
+                    |mut command, arg| {
+                        command.push(' ');
+                        command.push_str(&quote_word(arg));
+                        command
+                    }));
+
+                if let Some(ref initrd) = kernel.initrd {
+                    match self.path(label_name, initrd) {
+                        Some(initrd) => {
+                            commands.push(format!("initrd {}", initrd))
+                        },
+                        None => return,
+                    };
+                }
+
+                match (&kernel.fdt, &kernel.fdt_dir) {
+                    (&Some(ref fdt), _) => match self.path(label_name, fdt) {
+                        Some(fdt) => {
+                            commands.push(format!("devicetree {}", fdt))
+                        },
+                        None => return,
+                    },
+                    (&None, &Some(_)) => {
+                        self.warn(Some(label_name), "grub-fdt-dir",
+                                  String::from(
+                                      "FDTDIR has no GRUB equivalent, \
+                                       devicetree is not loaded"));
+                    },
+                    (&None, &None) => (),
+                };
+
+                if !kernel.fdt_overlays.is_empty() {
+                    self.warn(Some(label_name), "grub-fdt-overlays",
+                              String::from(
+                                  "FDTOVERLAYS have no GRUB equivalent, \
+                                   overlays are not applied"));
+                }
+            },
+
+            KernelFile::Boot(ref path) | KernelFile::BSS(ref path) => {
+                if let KernelFile::BSS(_) = *kernel_file {
+                    self.warn(Some(label_name), "grub-bss", String::from(
+                        "BSS boot sector is chainloaded without patching \
+                         DOS superblock"));
+                }
+
+                match self.path(label_name, path) {
+                    Some(path) => commands.push(
+                        format!("chainloader {}", path)),
+                    None => return,
+                };
+            },
+
+            KernelFile::PXE(_) | KernelFile::FDImage(_) |
+                    KernelFile::ComBoot(_) | KernelFile::Com32(_) => {
+                self.warn(Some(label_name), "grub-unsupported-kernel", format!(
+                    "{:?} has no GRUB equivalent, label skipped",
+                    kernel_file));
+                return;
+            },
+        };
+
+        if label.display.is_some() {
+            self.warn(Some(label_name), "grub-display", String::from(
+                "DISPLAY has no GRUB equivalent"));
+        }
+        for directive in &label.unknown_directives {
+            self.warn(Some(label_name), "grub-unknown-directive", format!(
+                "{} is not understood, it is skipped", directive.keyword));
+        }
+
+        let header = format!("menuentry {} --id {} {{",
+                             quote(label_name), quote(label_name));
+        self.line(0, "");
+        self.line(0, &header);
+        for command in commands {
+            self.line(1, &command);
+        }
+        self.line(0, "}");
+    }
+}
+
+// Paths in configuration should be already resolved, they are converted into
+// paths relative to the root directory.
+pub fn to_grub_cfg(conf: &SyslinuxConf, root_dir: &std::path::Path)
+        -> (String, Vec<Diagnostic>) {
+    let mut writer = GrubWriter{
+        root_dir: root_dir,
+        output: String::new(),
+        diagnostics: Vec::new(),
+    };
+
+    writer.global(conf);
+    for (label_name, label) in conf.labels.iter() {
+        let label = label.clone().apply_defaults(&conf.global.label_defaults);
+        writer.label(label_name, &label);
+    }
+
+    (writer.output, writer.diagnostics)
+}
+
+#[derive(Debug)]
+struct GrubWord {
+    text: String,
+    // Word contains variable expansion, which is not supported.
+    variable: bool,
+}
+
+#[derive(Debug)]
+struct GrubCommand {
+    line: usize,
+    words: Vec<GrubWord>,
+}
+
+struct GrubTokenizer {
+    commands: Vec<GrubCommand>,
+    words: Vec<GrubWord>,
+    word: String,
+    in_word: bool,
+    variable: bool,
+    line: usize,
+    command_line: usize,
+}
+
+impl GrubTokenizer {
+    fn start_word(&mut self) {
+        if self.words.is_empty() && !self.in_word {
+            self.command_line = self.line;
+        }
+        self.in_word = true;
+    }
+
+    fn push(&mut self, c: char) {
+        self.start_word();
+        if c == '\n' {
+            self.line += 1;
+        }
+        self.word.push(c);
+    }
+
+    fn finish_word(&mut self) {
+        if self.in_word {
+            self.words.push(GrubWord{
+                text: std::mem::replace(&mut self.word, String::new()),
+                variable: self.variable,
+            });
+            self.in_word = false;
+            self.variable = false;
+        }
+    }
+
+    fn finish_command(&mut self) {
+        self.finish_word();
+        if !self.words.is_empty() {
+            self.commands.push(GrubCommand{
+                line: self.command_line,
+                words: std::mem::replace(&mut self.words, Vec::new()),
+            });
+        }
+    }
+
+    // Splits GRUB script into commands. Quoting rules are the same as in
+    // POSIX shell.
+    fn tokenize(text: &str) -> Vec<GrubCommand> {
+        let mut tokenizer = GrubTokenizer{
+            commands: Vec::new(),
+            words: Vec::new(),
+            word: String::new(),
+            in_word: false,
+            variable: false,
+            line: 1,
+            command_line: 1,
+        };
+
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\n' | ';' => {
+                    tokenizer.finish_command();
+                    if c == '\n' {
+                        tokenizer.line += 1;
+                    }
+                },
+
+                ' ' | '\t' | '\r' => tokenizer.finish_word(),
+
+                '#' if !tokenizer.in_word => {
+                    while chars.peek().map_or(false, |c| *c != '\n') {
+                        chars.next();
+                    }
+                },
+
+                '\'' => {
+                    tokenizer.start_word();
+                    loop {
+                        match chars.next() {
+                            Some('\'') | None => break,
+                            Some(c) => tokenizer.push(c),
+                        }
+                    }
+                },
+
+                '"' => {
+                    tokenizer.start_word();
+                    loop {
+                        match chars.next() {
+                            Some('"') | None => break,
+                            Some('\\') => match chars.next() {
+                                Some(c) if c == '"' || c == '\\' ||
+                                           c == '$' => tokenizer.push(c),
+                                Some('\n') => tokenizer.line += 1,
+                                Some(c) => {
+                                    tokenizer.push('\\');
+                                    tokenizer.push(c);
+                                },
+                                None => break,
+                            },
+                            Some('$') => {
+                                tokenizer.variable = true;
+                                tokenizer.push('$');
+                            },
+                            Some(c) => tokenizer.push(c),
+                        }
+                    }
+                },
+
+                '\\' => match chars.next() {
+                    // Line continuation.
+                    Some('\n') => tokenizer.line += 1,
+                    Some(c) => tokenizer.push(c),
+                    None => (),
+                },
+
+                '$' => {
+                    tokenizer.variable = true;
+                    tokenizer.push('$');
+                },
+
+                c => tokenizer.push(c),
+            }
+        }
+        tokenizer.finish_command();
+
+        tokenizer.commands
+    }
+}
+
+// GRUB commands which do not affect what is booted.
+const GRUB_IGNORED_COMMANDS: [&'static str; 14] = [
+    "insmod", "load_video", "search", "set", "savedefault", "terminal_input",
+    "terminal_output", "terminal", "serial", "font", "loadfont", "gfxmode",
+    "background_image", "play",
+];
+
+// Commands which are part of GRUB scripting language.
+const GRUB_SCRIPT_COMMANDS: [&'static str; 13] = [
+    "if", "then", "elif", "else", "fi", "for", "while", "until", "do", "done",
+    "function", "source", "export",
+];
+
+struct GrubEntry {
+    line: usize,
+    title: String,
+    id: Option<String>,
+    label: Label,
+}
+
+struct GrubImporter {
+    labels: Labels,
+    // (ID, title, label name) of each imported entry.
+    entries: Vec<(Option<String>, String, String)>,
+    default: Option<String>,
+    timeout: Option<f64>,
+    entry: Option<GrubEntry>,
+    // Stack of open blocks, true for menuentry.
+    blocks: Vec<bool>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+fn strip_grub_device(path: &str) -> &str {
+    match path.starts_with('(') {
+        true => match path.find(')') {
+            Some(end) => &path[end + 1..],
+            None => path,
+        },
+        false => path,
+    }
+}
+
+impl GrubImporter {
+    fn diagnostic(&mut self, severity: Severity, line: usize, code: &str,
+                  message: String) {
+        let diagnostic = Diagnostic::new(
+            severity, code, format!("line {}: {}", line, message));
+        self.diagnostics.push(match self.entry {
+            Some(ref entry) => {
+                let label_name = entry.id.as_ref().unwrap_or(&entry.title);
+                diagnostic.for_label(label_name)
+            },
+            None => diagnostic,
+        });
+    }
+
+    fn path(&mut self, line: usize, path: &str) -> std::path::PathBuf {
+        let stripped = strip_grub_device(path);
+        if stripped.len() != path.len() {
+            self.diagnostic(Severity::Info, line, "grub-device-ignored",
+                            format!("Device in path \"{}\" is ignored", path));
+        }
+        std::path::PathBuf::from(stripped)
+    }
+
+    fn set(&mut self, line: usize, assignment: &str) {
+        let (name, value) = match assignment.find('=') {
+            Some(pos) => (&assignment[..pos], &assignment[pos + 1..]),
+            None => (assignment, ""),
+        };
+
+        match (name, self.entry.is_some()) {
+            ("default", false) => self.default = Some(String::from(value)),
+
+            ("timeout", false) => match value.parse::<f64>() {
+                Ok(timeout) if timeout < 0.0 => self.timeout = None,
+                Ok(timeout) if timeout == 0.0 => {
+                    self.diagnostic(
+                        Severity::Warning, line, "grub-zero-timeout",
+                        String::from("Zero timeout means immediate boot in \
+                                      GRUB, but no timeout in syslinux"));
+                    self.timeout = None;
+                },
+                Ok(timeout) => self.timeout = Some(timeout),
+                Err(_) => self.diagnostic(
+                    Severity::Warning, line, "grub-invalid-timeout",
+                    format!("Invalid timeout \"{}\"", value)),
+            },
+
+            _ => self.diagnostic(
+                Severity::Info, line, "grub-ignored",
+                format!("Variable \"{}\" is ignored", name)),
+        }
+    }
+
+    fn start_entry(&mut self, command: &GrubCommand) {
+        if self.entry.is_some() {
+            self.diagnostic(Severity::Warning, command.line,
+                            "grub-nested-menuentry",
+                            String::from("Nested menuentry is not supported"));
+            self.blocks.push(false);
+            return;
+        }
+
+        let mut title = None;
+        let mut id = None;
+        let mut words = command.words[1..command.words.len() - 1].iter();
+        while let Some(word) = words.next() {
+            let text = &word.text[..];
+            if text == "--id" || text == "$menuentry_id_option" {
+                id = words.next().map(|word| word.text.clone());
+            } else if text.starts_with("--id=") {
+                id = Some(String::from(&text["--id=".len()..]));
+            } else if text.starts_with("--") {
+                // --class, --unrestricted, --users, etc.
+                if text == "--class" || text == "--users" ||
+                        text == "--hotkey" {
+                    words.next();
+                }
+            } else if title.is_none() {
+                title = Some(word.text.clone());
+            }
+        }
+
+        self.entry = Some(GrubEntry{
+            line: command.line,
+            title: title.unwrap_or_else(String::new),
+            id: id,
+            label: Label::default(),
+        });
+        self.blocks.push(true);
+    }
+
+    fn finish_entry(&mut self) {
+        let entry = match self.entry.take() {
+            Some(entry) => entry,
+            None => return,
+        };
+
+        let label_name = entry.id.clone().unwrap_or(entry.title.clone());
+        if label_name.is_empty() {
+            self.diagnostic(Severity::Warning, entry.line, "grub-no-title",
+                            String::from("Menuentry without title, skipped"));
+            return;
+        }
+
+        if self.labels.contains_key(&label_name) {
+            self.diagnostic(Severity::Warning, entry.line, "grub-duplicate",
+                            format!("Duplicate menuentry \"{}\", skipped",
+                                    label_name));
+            return;
+        }
+
+        self.entries.push((entry.id, entry.title, label_name.clone()));
+        self.labels.insert(label_name, entry.label);
+    }
+
+    fn entry_command(&mut self, command: &GrubCommand) {
+        let name = &command.words[0].text[..];
+        let args: Vec<String> = command.words[1..].iter()
+            .map(|word| word.text.clone())
+            .collect();
+
+        match name {
+            "linux" | "linux16" | "linuxefi" => {
+                if args.is_empty() {
+                    return;
+                }
+                let path = self.path(command.line, &args[0]);
+                let entry = self.entry.as_mut().unwrap();
+                match entry.label.kernel_or_config {
+                    KernelOrConfig::Kernel(ref mut kernel) => {
+                        kernel.kernel_file = Some(KernelFile::Linux(path));
+                        kernel.kernel_file_source = Some(
+                            KernelFileSource::Tag);
+                        kernel.append = match args.len() {
+                            1 => None,
+                            _ => Some(args[1..].join(" ")),
+                        };
+                    },
+                };
+            },
+
+            "initrd" | "initrd16" | "initrdefi" => {
+                // Syslinux accepts comma-separated list of initrds.
+                let paths: Vec<String> = args.iter()
+                    .map(|arg| String::from(strip_grub_device(arg)))
+                    .collect();
+                let entry = self.entry.as_mut().unwrap();
+                match entry.label.kernel_or_config {
+                    KernelOrConfig::Kernel(ref mut kernel) => {
+                        kernel.initrd = Some(std::path::PathBuf::from(
+                            paths.join(",")));
+                    },
+                };
+            },
+
+            "chainloader" => {
+                if args.is_empty() {
+                    return;
+                }
+                if args[0].starts_with('+') {
+                    self.diagnostic(Severity::Warning, command.line,
+                                    "grub-blocklist",
+                                    String::from("Block lists are not \
+                                                  supported"));
+                    return;
+                }
+                let path = self.path(command.line, &args[0]);
+                let entry = self.entry.as_mut().unwrap();
+                match entry.label.kernel_or_config {
+                    KernelOrConfig::Kernel(ref mut kernel) => {
+                        kernel.kernel_file = Some(KernelFile::Boot(path));
+                        kernel.kernel_file_source = Some(
+                            KernelFileSource::Tag);
+                    },
+                };
+            },
+
+            "devicetree" => {
+                if args.is_empty() {
+                    return;
+                }
+                let path = self.path(command.line, &args[0]);
+                let entry = self.entry.as_mut().unwrap();
+                match entry.label.kernel_or_config {
+                    KernelOrConfig::Kernel(ref mut kernel) => {
+                        kernel.fdt = Some(path);
+                    },
+                };
+            },
+
+            "echo" => {
+                let entry = self.entry.as_mut().unwrap();
+                entry.label.say = Some(args.join(" "));
+            },
+
+            _ => self.other_command(command),
+        }
+    }
+
+    fn other_command(&mut self, command: &GrubCommand) {
+        let name = &command.words[0].text[..];
+
+        if name == "set" && command.words.len() > 1 {
+            let assignment = command.words[1].text.clone();
+            return self.set(command.line, &assignment);
+        }
+
+        if GRUB_IGNORED_COMMANDS.iter().any(|command| *command == name) {
+            self.diagnostic(Severity::Info, command.line, "grub-ignored",
+                            format!("Command \"{}\" is ignored", name));
+        } else if GRUB_SCRIPT_COMMANDS.iter().any(|command| *command == name) {
+            self.diagnostic(Severity::Warning, command.line, "grub-scripting",
+                            format!("GRUB scripting (\"{}\") is not \
+                                     supported, commands are imported as if \
+                                     there were no conditions", name));
+        } else {
+            self.diagnostic(Severity::Warning, command.line, "grub-unsupported",
+                            format!("Command \"{}\" is not supported", name));
+        }
+    }
+
+    fn command(&mut self, command: &GrubCommand) {
+        if command.words.iter().any(|word| {
+                word.variable && word.text != "$menuentry_id_option"}) {
+            self.diagnostic(Severity::Warning, command.line, "grub-variable",
+                            String::from("Variable expansion is not \
+                                          supported, variables are kept \
+                                          as is"));
+        }
+
+        let opens_block = command.words.len() > 1 &&
+            command.words[command.words.len() - 1].text == "{";
+
+        match &command.words[0].text[..] {
+            "}" => match self.blocks.pop() {
+                Some(true) => self.finish_entry(),
+                Some(false) => (),
+                None => self.diagnostic(Severity::Warning, command.line,
+                                        "grub-unbalanced",
+                                        String::from("Unexpected \"}\"")),
+            },
+
+            "menuentry" if opens_block => self.start_entry(command),
+
+            "submenu" if opens_block => {
+                self.diagnostic(Severity::Warning, command.line,
+                                "grub-submenu",
+                                String::from("Submenu is flattened"));
+                self.blocks.push(false);
+            },
+
+            _ if opens_block => {
+                self.other_command(command);
+                self.blocks.push(false);
+            },
+
+            _ => match self.entry.is_some() {
+                true => self.entry_command(command),
+                false => self.other_command(command),
+            },
+        }
+    }
+
+    fn default_label(&mut self) -> Option<String> {
+        let default = match self.default.take() {
+            Some(default) => default,
+            None => return None,
+        };
+
+        let found = match default.parse::<usize>() {
+            Ok(index) => self.entries.get(index)
+                .map(|&(_, _, ref label_name)| label_name.clone()),
+            Err(_) => self.entries.iter()
+                .find(|&&(ref id, ref title, _)| {
+                    id.as_ref() == Some(&default) || *title == default
+                })
+                .map(|&(_, _, ref label_name)| label_name.clone()),
+        };
+
+        if found.is_none() {
+            self.diagnostics.push(Diagnostic::new(
+                Severity::Warning, "grub-default-not-found",
+                format!("Default entry \"{}\" not found", default)));
+        }
+        found
+    }
+}
+
+// Imports simple grub.cfg: menuentries with linux, initrd and chainloader
+// commands, default and timeout.
+pub fn from_grub_cfg(text: &str) -> (SyslinuxConf, Vec<Diagnostic>) {
+    let mut importer = GrubImporter{
+        labels: Labels::new(),
+        entries: Vec::new(),
+        default: None,
+        timeout: None,
+        entry: None,
+        blocks: Vec::new(),
+        diagnostics: Vec::new(),
+    };
+
+    for command in GrubTokenizer::tokenize(text) {
+        importer.command(&command);
+    }
+
+    if importer.entry.is_some() {
+        importer.diagnostics.push(Diagnostic::new(
+            Severity::Warning, "grub-unbalanced",
+            String::from("Unterminated menuentry")));
+        importer.finish_entry();
+    }
+
+    let default = importer.default_label();
+    let conf = SyslinuxConf{
+        global: Global{
+            default: default,
+            timeout: importer.timeout,
+            ..Global::default()
+        },
+        labels: importer.labels,
+    };
+
+    (conf, importer.diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+    use std;
+
+    use super::{GrubTokenizer, from_grub_cfg, to_grub_cfg};
+    use types::{Diagnostic, Global, Kernel, KernelFile, KernelOrConfig, Label,
+                Labels, SyslinuxConf};
+
+    fn linux(append: &str) -> Label {
+        Label{
+            kernel_or_config: KernelOrConfig::Kernel(Kernel{
+                kernel_file: Some(KernelFile::Linux(
+                    std::path::PathBuf::from("/srv/root/boot/vmlinuz"))),
+                initrd: Some(std::path::PathBuf::from(
+                    "/srv/root/boot/it's initrd.img")),
+                append: Some(String::from(append)),
+                ..Kernel::default()
+            }),
+            ..Label::default()
+        }
+    }
+
+    fn convert(label: Label) -> String {
+        let mut labels = Labels::new();
+        labels.insert(String::from("linux"), label);
+        let conf = SyslinuxConf{
+            global: Global::default(),
+            labels: labels,
+        };
+        to_grub_cfg(&conf, std::path::Path::new("/srv/root")).0
+    }
+
+    #[test]
+    fn writes_linux_entry() {
+        assert_eq!(convert(linux("root=/dev/sda1 ro quiet")),
+                   "\n\
+                    menuentry 'linux' --id 'linux' {\n\
+                    \x20   linux '/boot/vmlinuz' root=/dev/sda1 ro quiet\n\
+                    \x20   initrd '/boot/it'\\''s initrd.img'\n\
+                    }\n");
+    }
+
+    #[test]
+    fn quotes_kernel_arguments() {
+        let grub_cfg = convert(linux("root=$x; init=/bin/sh \"a b\" #c"));
+        assert!(grub_cfg.contains(
+            "linux '/boot/vmlinuz' 'root=$x;' init=/bin/sh '\"a' 'b\"' '#c'\n"),
+            "{}", grub_cfg);
+    }
+
+    #[test]
+    fn writes_no_arguments_for_append_dash() {
+        assert!(convert(linux("-")).contains("linux '/boot/vmlinuz'\n"));
+    }
+
+    #[test]
+    fn skips_files_outside_of_root() {
+        let mut label = linux("");
+        match label.kernel_or_config {
+            KernelOrConfig::Kernel(ref mut kernel) => {
+                kernel.kernel_file = Some(KernelFile::Linux(
+                    std::path::PathBuf::from("/elsewhere/vmlinuz")));
+            },
+        };
+        assert_eq!(convert(label), "");
+    }
+
+    fn words(text: &str) -> Vec<Vec<String>> {
+        GrubTokenizer::tokenize(text).iter()
+            .map(|command| command.words.iter()
+                 .map(|word| word.text.clone())
+                 .collect())
+            .collect()
+    }
+
+    fn codes(diagnostics: &[Diagnostic]) -> Vec<&str> {
+        diagnostics.iter().map(|diagnostic| &diagnostic.code[..]).collect()
+    }
+
+    fn kernel(label: &Label) -> &Kernel {
+        match label.kernel_or_config {
+            KernelOrConfig::Kernel(ref kernel) => kernel,
+        }
+    }
+
+    #[test]
+    fn tokenizes_quoted_words() {
+        assert_eq!(words("menuentry 'Debian GNU/Linux' --id \"a\\\"b\" {"),
+                   vec![vec!["menuentry", "Debian GNU/Linux", "--id", "a\"b",
+                             "{"]]);
+        assert_eq!(words("echo it'''s' \"one\"two"),
+                   vec![vec!["echo", "its", "onetwo"]]);
+    }
+
+    #[test]
+    fn tokenizes_commands() {
+        let commands = GrubTokenizer::tokenize(
+            "# Comment\n\
+             set a=1; set b=2 # Comment\n\
+             linux /vmlinuz \\\n    quiet\n\
+             echo a#b\n");
+        let lines: Vec<usize> = commands.iter()
+            .map(|command| command.line)
+            .collect();
+
+        assert_eq!(lines, vec![2, 2, 3, 5]);
+        assert_eq!(words("set a=1; set b=2 # Comment\n\
+                          linux /vmlinuz \\\n    quiet\n\
+                          echo a#b\n"),
+                   vec![vec!["set", "a=1"], vec!["set", "b=2"],
+                        vec!["linux", "/vmlinuz", "quiet"],
+                        vec!["echo", "a#b"]]);
+    }
+
+    #[test]
+    fn marks_variables() {
+        let commands = GrubTokenizer::tokenize("linux $prefix/vmlinuz '$x'");
+        let variables: Vec<bool> = commands[0].words.iter()
+            .map(|word| word.variable)
+            .collect();
+        assert_eq!(variables, vec![false, true, false]);
+    }
+
+    #[test]
+    fn imports_menu_entries() {
+        let (conf, diagnostics) = from_grub_cfg(
+            "insmod gzio\n\
+             set default=\"1\"\n\
+             set timeout=5\n\
+             menuentry 'Linux' --class gnu-linux --id linux {\n\
+             \x20   linux (hd0,1)/vmlinuz root=/dev/sda1 'quiet splash'\n\
+             \x20   initrd /initrd.img /early.img\n\
+             }\n\
+             menuentry \"Windows\" {\n\
+             \x20   chainloader /EFI/bootmgfw.efi\n\
+             }\n");
+
+        assert_eq!(conf.global.default, Some(String::from("Windows")));
+        assert_eq!(conf.global.timeout, Some(5.0));
+        let label_names: Vec<&String> = conf.labels.keys().collect();
+        assert_eq!(label_names, vec!["linux", "Windows"]);
+
+        let linux = kernel(conf.labels.get("linux").unwrap());
+        assert_eq!(linux.kernel_file, Some(KernelFile::Linux(
+            std::path::PathBuf::from("/vmlinuz"))));
+        assert_eq!(linux.append,
+                   Some(String::from("root=/dev/sda1 quiet splash")));
+        assert_eq!(linux.initrd, Some(std::path::PathBuf::from(
+            "/initrd.img,/early.img")));
+
+        let windows = kernel(conf.labels.get("Windows").unwrap());
+        assert_eq!(windows.kernel_file, Some(KernelFile::Boot(
+            std::path::PathBuf::from("/EFI/bootmgfw.efi"))));
+
+        assert_eq!(codes(&diagnostics),
+                   vec!["grub-ignored", "grub-device-ignored"]);
+    }
+
+    #[test]
+    fn reports_unsupported_constructs() {
+        let (conf, diagnostics) = from_grub_cfg(
+            "set timeout=0\n\
+             if [ x$feature = xy ]; then\n\
+             \x20 set default=missing\n\
+             fi\n\
+             submenu 'Advanced' {\n\
+             \x20 menuentry 'Rescue' {\n\
+             \x20   linux $root/vmlinuz\n\
+             \x20 }\n\
+             }\n");
+
+        assert_eq!(conf.global.timeout, None);
+        assert_eq!(conf.global.default, None);
+        assert_eq!(conf.labels.len(), 1);
+        assert_eq!(codes(&diagnostics),
+                   vec!["grub-zero-timeout", "grub-variable",
+                        "grub-scripting", "grub-scripting", "grub-scripting",
+                        "grub-submenu", "grub-variable",
+                        "grub-default-not-found"]);
+    }
+
+    #[test]
+    fn imports_what_it_exports() {
+        let mut label = linux("root=$x; init=/bin/sh");
+        match label.kernel_or_config {
+            KernelOrConfig::Kernel(ref mut kernel) => kernel.initrd = None,
+        };
+        let (conf, _) = from_grub_cfg(&convert(label));
+
+        let linux = kernel(conf.labels.get("linux").unwrap());
+        assert_eq!(linux.append,
+                   Some(String::from("root=$x; init=/bin/sh")));
+    }
+}
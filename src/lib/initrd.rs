@@ -0,0 +1,653 @@
+use std;
+use std::io::Read;
+
+extern crate bzip2;
+extern crate flate2;
+extern crate lz4;
+extern crate xz2;
+extern crate zstd;
+
+use types::{CpioEntry, InitrdCompression, InitrdInfo, InitrdSegment};
+
+const CPIO_HEADER_SIZE: usize = 110;
+const CPIO_TRAILER: &'static str = "TRAILER!!!";
+const CPIO_ALIGNMENT: u64 = 4;
+// PATH_MAX of Linux, including terminating NUL.
+const CPIO_MAX_NAME_SIZE: u64 = 4096;
+
+const MAGIC_SIZE: usize = 6;
+const MAGICS: [(&'static [u8], InitrdCompression); 11] = [
+    (b"070701",                   InitrdCompression::Uncompressed),
+    (b"070702",                   InitrdCompression::Uncompressed),
+    (b"\x1f\x8b",                 InitrdCompression::Gzip),
+    (b"\x1f\x9e",                 InitrdCompression::Gzip),
+    (b"BZh",                      InitrdCompression::Bzip2),
+    (b"\x5d\x00\x00",             InitrdCompression::Lzma),
+    (b"\xfd\x37\x7a\x58\x5a\x00", InitrdCompression::Xz),
+    (b"\x89\x4c\x5a\x4f",         InitrdCompression::Lzo),
+    (LZ4_LEGACY_MAGIC,            InitrdCompression::Lz4),
+    (b"\x04\x22\x4d\x18",         InitrdCompression::Lz4),
+    (b"\x28\xb5\x2f\xfd",         InitrdCompression::Zstd),
+];
+
+// Legacy LZ4 format produced by "lz4 -l", which is the only one supported by
+// Linux kernel.
+const LZ4_LEGACY_MAGIC: &'static [u8] = b"\x02\x21\x4c\x18";
+const LZ4_LEGACY_BLOCK_SIZE: i32 = 8 * 1024 * 1024;
+
+fn invalid_data(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+fn unexpected_eof() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::UnexpectedEof,
+                        "Unexpected end of file")
+}
+
+// Like read_exact(), but returns number of bytes read before EOF.
+fn read_full<R: Read>(input: &mut R, buf: &mut [u8])
+        -> std::io::Result<usize> {
+    let mut done = 0;
+    while done < buf.len() {
+        match input.read(&mut buf[done..]) {
+            Ok(0) => break,
+            Ok(size) => done += size,
+            Err(ref err) if err.kind() == std::io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(done)
+}
+
+fn detect_compression(magic: &[u8]) -> Option<InitrdCompression> {
+    MAGICS.iter()
+        .find(|&&(prefix, _)| magic.starts_with(prefix))
+        .map(|&(_, compression)| compression)
+}
+
+// Reader which allows to look at the data before consuming it.
+struct PeekReader<R> {
+    input: R,
+    peeked: Vec<u8>,
+    position: u64,
+}
+
+impl<R: Read> PeekReader<R> {
+    fn new(input: R) -> PeekReader<R> {
+        PeekReader{
+            input: input,
+            peeked: Vec::new(),
+            position: 0,
+        }
+    }
+
+    fn peek(&mut self, size: usize) -> std::io::Result<&[u8]> {
+        if self.peeked.len() < size {
+            let mut buf = vec![0; size - self.peeked.len()];
+            let read = try!(read_full(&mut self.input, &mut buf));
+            self.peeked.extend_from_slice(&buf[..read]);
+        }
+
+        let size = std::cmp::min(size, self.peeked.len());
+        Ok(&self.peeked[..size])
+    }
+
+    fn skip_zeros(&mut self) -> std::io::Result<()> {
+        loop {
+            match try!(self.peek(1)).first() {
+                Some(&0) => (),
+                _ => return Ok(()),
+            };
+            self.peeked.remove(0);
+            self.position += 1;
+        }
+    }
+}
+
+impl<R: Read> Read for PeekReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let size = match self.peeked.is_empty() {
+            true => try!(self.input.read(buf)),
+            false => {
+                let size = std::cmp::min(buf.len(), self.peeked.len());
+                buf[..size].copy_from_slice(&self.peeked[..size]);
+                self.peeked.drain(..size);
+                size
+            },
+        };
+
+        self.position += size as u64;
+        Ok(size)
+    }
+}
+
+struct Lz4LegacyDecoder<R> {
+    input: R,
+    block: Vec<u8>,
+    block_position: usize,
+}
+
+impl<R: Read> Lz4LegacyDecoder<R> {
+    fn new(mut input: R) -> std::io::Result<Lz4LegacyDecoder<R>> {
+        let mut magic = [0; 4];
+        try!(input.read_exact(&mut magic));
+        match &magic[..] == LZ4_LEGACY_MAGIC {
+            true => Ok(Lz4LegacyDecoder{
+                input: input,
+                block: Vec::new(),
+                block_position: 0,
+            }),
+            false => Err(invalid_data("Not a legacy LZ4 stream")),
+        }
+    }
+
+    // Returns false on the end of stream.
+    fn next_block(&mut self) -> std::io::Result<bool> {
+        loop {
+            let mut size = [0; 4];
+            match try!(read_full(&mut self.input, &mut size)) {
+                0 => return Ok(false),
+                4 => (),
+                _ => return Err(unexpected_eof()),
+            };
+
+            // Concatenated streams: skip magic of the next one.
+            if &size[..] == LZ4_LEGACY_MAGIC {
+                continue;
+            }
+
+            let size = (size[0] as u64) | ((size[1] as u64) << 8) |
+                       ((size[2] as u64) << 16) | ((size[3] as u64) << 24);
+            let mut compressed = Vec::new();
+            try!(self.input.by_ref().take(size).read_to_end(&mut compressed));
+            if compressed.len() as u64 != size {
+                return Err(unexpected_eof());
+            }
+
+            self.block = try!(lz4::block::decompress(
+                &compressed, Some(LZ4_LEGACY_BLOCK_SIZE)));
+            self.block_position = 0;
+            return Ok(true);
+        }
+    }
+}
+
+impl<R: Read> Read for Lz4LegacyDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.block_position == self.block.len() {
+            if !try!(self.next_block()) {
+                return Ok(0);
+            }
+        }
+
+        let size = std::cmp::min(buf.len(),
+                                 self.block.len() - self.block_position);
+        buf[..size].copy_from_slice(
+            &self.block[self.block_position..self.block_position + size]);
+        self.block_position += size;
+        Ok(size)
+    }
+}
+
+fn decompress<'a, R: Read + 'a>(compression: InitrdCompression,
+                                magic: &[u8],
+                                input: R)
+        -> std::io::Result<Box<Read + 'a>> {
+    match compression {
+        InitrdCompression::Uncompressed => Ok(Box::new(input)),
+
+        InitrdCompression::Gzip => {
+            Ok(Box::new(flate2::read::MultiGzDecoder::new(input)))
+        },
+
+        InitrdCompression::Lzma => {
+            match xz2::stream::Stream::new_lzma_decoder(u64::max_value()) {
+                Ok(stream) => {
+                    Ok(Box::new(xz2::read::XzDecoder::new_stream(
+                        input, stream)))
+                },
+                Err(err) => Err(std::io::Error::new(
+                    std::io::ErrorKind::Other, err)),
+            }
+        },
+
+        InitrdCompression::Xz => {
+            Ok(Box::new(xz2::read::XzDecoder::new_multi_decoder(input)))
+        },
+
+        InitrdCompression::Lz4 => match magic.starts_with(LZ4_LEGACY_MAGIC) {
+            true => Ok(Box::new(try!(Lz4LegacyDecoder::new(input)))),
+            false => Ok(Box::new(try!(lz4::Decoder::new(input)))),
+        },
+
+        InitrdCompression::Zstd => {
+            Ok(Box::new(try!(zstd::stream::read::Decoder::new(input))))
+        },
+
+        InitrdCompression::Bzip2 => {
+            Ok(Box::new(bzip2::read::BzDecoder::new(input)))
+        },
+
+        InitrdCompression::Lzo => {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!("Decompression of {:?} is not supported",
+                        compression)))
+        },
+    }
+}
+
+// Reader of "newc" cpio archives.
+struct CpioReader<R> {
+    input: R,
+    position: u64,
+}
+
+impl<R: Read> CpioReader<R> {
+    fn new(input: R) -> CpioReader<R> {
+        CpioReader{
+            input: input,
+            position: 0,
+        }
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> std::io::Result<()> {
+        let read = try!(read_full(&mut self.input, buf));
+        self.position += read as u64;
+        match read == buf.len() {
+            true => Ok(()),
+            false => Err(unexpected_eof()),
+        }
+    }
+
+    fn skip(&mut self, size: u64) -> std::io::Result<()> {
+        let skipped = try!(std::io::copy(&mut self.input.by_ref().take(size),
+                                         &mut std::io::sink()));
+        self.position += skipped;
+        match skipped == size {
+            true => Ok(()),
+            false => Err(unexpected_eof()),
+        }
+    }
+
+    fn align(&mut self) -> std::io::Result<()> {
+        let padding = (CPIO_ALIGNMENT - self.position % CPIO_ALIGNMENT) %
+                      CPIO_ALIGNMENT;
+        self.skip(padding)
+    }
+
+    // Returns None on the end of stream. Zero padding between archives is
+    // skipped, like Linux kernel does.
+    fn next_header(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+        let mut header = vec![0; CPIO_HEADER_SIZE];
+        loop {
+            let read = try!(read_full(&mut self.input,
+                                      &mut header[..CPIO_ALIGNMENT as usize]));
+            self.position += read as u64;
+
+            match read {
+                0 => return Ok(None),
+                4 => (),
+                _ => return Err(unexpected_eof()),
+            };
+
+            if header[..CPIO_ALIGNMENT as usize].iter().any(|v| *v != 0) {
+                break;
+            }
+        }
+
+        try!(self.read_exact(&mut header[CPIO_ALIGNMENT as usize..]));
+        Ok(Some(header))
+    }
+
+    fn get_field(header: &[u8], index: usize) -> std::io::Result<u64> {
+        // Six bytes of magic, then 8 hexadecimal digits per field.
+        let start = MAGIC_SIZE + index * 8;
+        let field = &header[start..start + 8];
+        match std::str::from_utf8(field).ok().and_then(
+                |field| u64::from_str_radix(field, 16).ok()) {
+            Some(value) => Ok(value),
+            None => Err(invalid_data("Invalid cpio header field")),
+        }
+    }
+
+    // Reads one archive up to and including the trailer entry. Returns false
+    // if there is no more archives in the stream.
+    fn read_archive(&mut self, entries: &mut Vec<CpioEntry>)
+            -> std::io::Result<bool> {
+        let mut first = true;
+        loop {
+            let header = match try!(self.next_header()) {
+                Some(header) => header,
+                None => match first {
+                    true => return Ok(false),
+                    false => return Err(unexpected_eof()),
+                },
+            };
+            first = false;
+
+            if detect_compression(&header) !=
+                    Some(InitrdCompression::Uncompressed) {
+                return Err(invalid_data("Invalid cpio header magic"));
+            }
+
+            let mode = try!(CpioReader::<R>::get_field(&header, 1));
+            let size = try!(CpioReader::<R>::get_field(&header, 6));
+            let name_size = try!(CpioReader::<R>::get_field(&header, 11));
+
+            if name_size > CPIO_MAX_NAME_SIZE {
+                return Err(invalid_data("cpio entry name is too long"));
+            }
+
+            let mut name = vec![0; name_size as usize];
+            try!(self.read_exact(&mut name));
+            try!(self.align());
+            // Name is terminated by NUL.
+            let name = String::from_utf8_lossy(
+                name.split(|v| *v == 0).next().unwrap_or(&[])).into_owned();
+
+            try!(self.skip(size));
+            try!(self.align());
+
+            if name == CPIO_TRAILER {
+                return Ok(true);
+            }
+
+            entries.push(CpioEntry{
+                name: name,
+                mode: mode as u32,
+                size: size,
+            });
+        }
+    }
+}
+
+// Whether archives with given compression can be listed.
+pub fn is_supported(compression: InitrdCompression) -> bool {
+    compression != InitrdCompression::Lzo
+}
+
+fn read_segment<R: Read>(input: R,
+                         offset: u64,
+                         compression: InitrdCompression,
+                         magic: &[u8])
+        -> InitrdSegment {
+    let mut segment = InitrdSegment{
+        offset: offset,
+        compression: Some(compression),
+        entries: Vec::new(),
+        uncompressed_size: 0,
+        error: None,
+    };
+
+    let input = match decompress(compression, magic, input) {
+        Ok(input) => input,
+        Err(err) => {
+            segment.error = Some(format!("{}", err));
+            return segment;
+        },
+    };
+
+    let mut cpio = CpioReader::new(input);
+    let result = match compression {
+        // Uncompressed archive ends with the trailer, something else may
+        // follow.
+        InitrdCompression::Uncompressed => {
+            cpio.read_archive(&mut segment.entries).map(|_| ())
+        },
+
+        // Compressed stream may contain several archives and lasts up to the
+        // end of file.
+        _ => {
+            let mut result = Ok(());
+            loop {
+                match cpio.read_archive(&mut segment.entries) {
+                    Ok(true) => (),
+                    Ok(false) => break,
+                    Err(err) => {
+                        result = Err(err);
+                        break;
+                    },
+                }
+            }
+            result
+        },
+    };
+
+    segment.uncompressed_size = cpio.position;
+    if let Err(err) = result {
+        segment.error = Some(format!("{}", err));
+    }
+    segment
+}
+
+pub fn read_initrd_info(path: &std::path::Path)
+        -> std::io::Result<InitrdInfo> {
+    let file = try!(std::fs::File::open(path));
+    let mut input = PeekReader::new(std::io::BufReader::new(file));
+    let mut segments = Vec::new();
+
+    loop {
+        try!(input.skip_zeros());
+
+        let magic = try!(input.peek(MAGIC_SIZE)).to_vec();
+        if magic.is_empty() {
+            break;
+        }
+
+        let offset = input.position;
+        let segment = match detect_compression(&magic) {
+            Some(compression) => {
+                read_segment(input.by_ref(), offset, compression, &magic)
+            },
+
+            None => InitrdSegment{
+                offset: offset,
+                compression: None,
+                entries: Vec::new(),
+                uncompressed_size: 0,
+                error: Some(String::from("Unknown data format")),
+            },
+        };
+
+        // Only uncompressed archive may be followed by something, and only
+        // if it was read successfully.
+        let last = segment.error.is_some() ||
+                   segment.compression != Some(InitrdCompression::Uncompressed);
+        segments.push(segment);
+        if last {
+            break;
+        }
+    }
+
+    let uncompressed_size = segments.iter()
+        .map(|segment| segment.uncompressed_size)
+        .fold(0, |total, size| total + size);
+
+    Ok(InitrdInfo{
+        segments: segments,
+        uncompressed_size: uncompressed_size,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::{bzip2, flate2, read_initrd_info};
+    use test_util::TempDir;
+    use types::{InitrdCompression, InitrdInfo};
+
+    fn pad(data: &mut Vec<u8>) {
+        while data.len() % 4 != 0 {
+            data.push(0);
+        }
+    }
+
+    fn entry_with_name_size(archive: &mut Vec<u8>, name: &str, mode: u32,
+                            contents: &[u8], name_size: usize) {
+        let fields = [0, mode as usize, 0, 0, 1, 0, contents.len(),
+                      0, 0, 0, 0, name_size, 0];
+        archive.extend_from_slice(b"070701");
+        for field in fields.iter() {
+            archive.extend_from_slice(format!("{:08x}", field).as_bytes());
+        }
+        archive.extend_from_slice(name.as_bytes());
+        archive.push(0);
+        pad(archive);
+        archive.extend_from_slice(contents);
+        pad(archive);
+    }
+
+    fn entry(archive: &mut Vec<u8>, name: &str, mode: u32, contents: &[u8]) {
+        entry_with_name_size(archive, name, mode, contents, name.len() + 1);
+    }
+
+    fn newc(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut archive = Vec::new();
+        for &(name, contents) in files {
+            entry(&mut archive, name, 0o100644, contents);
+        }
+        entry(&mut archive, "TRAILER!!!", 0, b"");
+        archive
+    }
+
+    fn gzip_compress(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(
+            Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn bzip2_compress(data: &[u8]) -> Vec<u8> {
+        let mut encoder = bzip2::write::BzEncoder::new(
+            Vec::new(), bzip2::Compression::Default);
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn read(data: &[u8]) -> InitrdInfo {
+        let dir = TempDir::new();
+        let path = dir.write("initrd.img", data);
+        read_initrd_info(&path).unwrap()
+    }
+
+    fn names(info: &InitrdInfo, segment: usize) -> Vec<String> {
+        info.segments[segment].entries.iter()
+            .map(|entry| entry.name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn reads_uncompressed_archive() {
+        let archive = newc(&[("init", b"#!/bin/sh\n"), ("etc/fstab", b"")]);
+        let info = read(&archive);
+
+        assert_eq!(info.segments.len(), 1);
+        let segment = &info.segments[0];
+        assert_eq!(segment.compression,
+                   Some(InitrdCompression::Uncompressed));
+        assert_eq!(segment.error, None);
+        assert_eq!(names(&info, 0), vec!["init", "etc/fstab"]);
+        assert_eq!(segment.entries[0].mode, 0o100644);
+        assert_eq!(segment.entries[0].size, 10);
+        assert_eq!(info.uncompressed_size, archive.len() as u64);
+    }
+
+    #[test]
+    fn reads_compressed_archive() {
+        let archive = newc(&[("init", b"#!/bin/sh\n")]);
+        let info = read(&gzip_compress(&archive));
+
+        assert_eq!(info.segments.len(), 1);
+        assert_eq!(info.segments[0].compression,
+                   Some(InitrdCompression::Gzip));
+        assert_eq!(info.segments[0].error, None);
+        assert_eq!(names(&info, 0), vec!["init"]);
+        assert_eq!(info.uncompressed_size, archive.len() as u64);
+    }
+
+    #[test]
+    fn reads_bzip2_archive() {
+        let info = read(&bzip2_compress(&newc(&[("init", b"#!/bin/sh\n")])));
+
+        assert_eq!(info.segments[0].compression,
+                   Some(InitrdCompression::Bzip2));
+        assert_eq!(info.segments[0].error, None);
+        assert_eq!(names(&info, 0), vec!["init"]);
+    }
+
+    #[test]
+    fn reads_microcode_followed_by_compressed_archive() {
+        let microcode = newc(&[("kernel/x86/microcode/GenuineIntel.bin",
+                                b"\x01\x02\x03")]);
+        let mut data = microcode.clone();
+        // Zero padding between archives is skipped.
+        data.extend_from_slice(&[0; 512]);
+        data.extend(gzip_compress(&newc(&[("init", b"")])));
+        let info = read(&data);
+
+        assert_eq!(info.segments.len(), 2);
+        assert_eq!(info.segments[0].offset, 0);
+        assert_eq!(info.segments[1].offset, microcode.len() as u64 + 512);
+        assert_eq!(names(&info, 0),
+                   vec!["kernel/x86/microcode/GenuineIntel.bin"]);
+        assert_eq!(info.segments[1].compression,
+                   Some(InitrdCompression::Gzip));
+        assert_eq!(names(&info, 1), vec!["init"]);
+    }
+
+    #[test]
+    fn rejects_too_long_name() {
+        let mut archive = Vec::new();
+        entry_with_name_size(&mut archive, "init", 0o100644, b"", 0xffffffff);
+        let info = read(&archive);
+
+        let error = info.segments[0].error.clone().unwrap();
+        assert!(error.contains("too long"), "{}", error);
+        assert!(info.segments[0].entries.is_empty());
+    }
+
+    #[test]
+    fn reports_truncated_archive() {
+        let archive = newc(&[("init", b"#!/bin/sh\n")]);
+        let info = read(&archive[..archive.len() - 20]);
+
+        assert!(info.segments[0].error.is_some());
+        assert_eq!(names(&info, 0), vec!["init"]);
+    }
+
+    #[test]
+    fn reports_unsupported_compression() {
+        let info = read(b"\x89\x4c\x5a\x4f\0\x0d\x0a\x1a\x0a");
+
+        assert_eq!(info.segments[0].compression,
+                   Some(InitrdCompression::Lzo));
+        assert!(info.segments[0].error.is_some());
+    }
+
+    #[test]
+    fn reports_unknown_format() {
+        let info = read(b"not an archive");
+
+        assert_eq!(info.segments.len(), 1);
+        assert_eq!(info.segments[0].compression, None);
+        assert!(info.segments[0].error.is_some());
+    }
+
+    #[test]
+    fn reads_empty_file() {
+        let info = read(b"");
+        assert!(info.segments.is_empty());
+        assert_eq!(info.uncompressed_size, 0);
+    }
+
+    #[test]
+    fn skips_contents_of_large_entries() {
+        let contents = vec![0x5a; 3 * 1024 * 1024 + 1];
+        let info = read(&newc(&[("lib/firmware.bin", &contents)]));
+
+        assert_eq!(info.segments[0].error, None);
+        assert_eq!(info.segments[0].entries[0].size, contents.len() as u64);
+    }
+}
@@ -0,0 +1,616 @@
+use std;
+
+use initrd::{is_supported, read_initrd_info};
+use types::{Diagnostic, Kernel, KernelFile, KernelFileSource,
+            KernelOrConfig, Label, Global, Labels, LinuxKernelInfo, Severity,
+            SyslinuxConf};
+
+// Enough to cover real-mode part of any Linux kernel and headers of all
+// other supported file types.
+const HEADER_SIZE: u64 = 64 * 1024;
+
+const LINUX_MAGIC_OFFSET: usize = 0x202;
+const LINUX_MAGIC: &'static [u8] = b"HdrS";
+
+// Fields of Linux boot protocol setup header.
+const LINUX_PROTOCOL_OFFSET: usize = 0x206;
+const LINUX_KERNEL_VERSION_OFFSET: usize = 0x20e;
+const LINUX_LOADFLAGS_OFFSET: usize = 0x211;
+const LINUX_XLOADFLAGS_OFFSET: usize = 0x236;
+const LINUX_HANDOVER_OFFSET_OFFSET: usize = 0x264;
+
+// Kernel version pointer is relative to the start of setup header.
+const LINUX_KERNEL_VERSION_BASE: usize = 0x200;
+
+const LINUX_LOADFLAGS: [(u32, &'static str); 5] = [
+    (0x01, "LOADED_HIGH"),
+    (0x02, "KASLR_FLAG"),
+    (0x20, "QUIET_FLAG"),
+    (0x40, "KEEP_SEGMENTS"),
+    (0x80, "CAN_USE_HEAP"),
+];
+
+const LINUX_XLOADFLAGS: [(u32, &'static str); 7] = [
+    (0x01, "XLF_KERNEL_64"),
+    (0x02, "XLF_CAN_BE_LOADED_ABOVE_4G"),
+    (0x04, "XLF_EFI_HANDOVER_32"),
+    (0x08, "XLF_EFI_HANDOVER_64"),
+    (0x10, "XLF_EFI_KEXEC"),
+    (0x20, "XLF_5LEVEL"),
+    (0x40, "XLF_5LEVEL_ENABLED"),
+];
+
+// Non-x86 Linux images, as booted by U-Boot: ARM zImage, ARM64 and RISC-V
+// Image.
+const ARM_ZIMAGE_MAGIC_OFFSET: usize = 0x24;
+const ARM_ZIMAGE_MAGIC: &'static [u8] = b"\x18\x28\x6f\x01";
+const ARM64_IMAGE_MAGIC_OFFSET: usize = 0x38;
+const ARM64_IMAGE_MAGIC: &'static [u8] = b"ARM\x64";
+const RISCV_IMAGE_MAGIC_OFFSET: usize = 0x34;
+const RISCV_IMAGE_MAGIC: &'static [u8] = b"RSC\x05";
+
+const ELF_MAGIC: &'static [u8] = b"\x7fELF";
+// "mov eax, 21cd4cffh" at the start of old-style COM32 modules.
+const COM32_MAGIC: &'static [u8] = b"\xb8\xff\x4c\xcd\x21";
+
+const BOOT_SECTOR_SIZE: u64 = 512;
+const BOOT_SIGNATURE_OFFSET: usize = 510;
+const BOOT_SIGNATURE: &'static [u8] = b"\x55\xaa";
+
+// Signatures of PXE API structures which network bootstrap programs look
+// for in memory.
+const PXE_SIGNATURES: [&'static [u8]; 2] = [b"PXENV+", b"!PXE"];
+
+// Standard floppy sizes: 360K, 720K, 1.2M, 1.44M and 2.88M.
+const FLOPPY_SIZES: [u64; 5] = [368640, 737280, 1228800, 1474560, 2949120];
+
+#[derive(Debug, Clone)]
+pub struct InspectOptions {
+    // Detect type of files specified by KERNEL tag using their contents.
+    // Off by default, as it reads every referenced kernel file.
+    pub detect_kernel_files: bool,
+    // Read metadata from setup headers of Linux kernels.
+    pub linux_kernels: bool,
+    // Read compression and list of files from initrd archives.
+    pub initrds: bool,
+}
+
+impl Default for InspectOptions {
+    fn default() -> InspectOptions {
+        InspectOptions{
+            detect_kernel_files: false,
+            linux_kernels: false,
+            initrds: false,
+        }
+    }
+}
+
+fn read_header(path: &std::path::Path) -> std::io::Result<(Vec<u8>, u64)> {
+    use std::io::Read;
+
+    let file = try!(std::fs::File::open(path));
+    let size = try!(file.metadata()).len();
+
+    let mut header = Vec::new();
+    try!(file.take(HEADER_SIZE).read_to_end(&mut header));
+    Ok((header, size))
+}
+
+fn has_bytes_at(data: &[u8], offset: usize, bytes: &[u8]) -> bool {
+    match data.len() >= offset + bytes.len() {
+        true => &data[offset..offset + bytes.len()] == bytes,
+        false => false,
+    }
+}
+
+fn get_u8(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset).map(|v| *v as u32)
+}
+
+fn get_u16_le(data: &[u8], offset: usize) -> Option<u32> {
+    match (get_u8(data, offset), get_u8(data, offset + 1)) {
+        (Some(low), Some(high)) => Some(low | (high << 8)),
+        _ => None,
+    }
+}
+
+fn get_u32_le(data: &[u8], offset: usize) -> Option<u32> {
+    match (get_u16_le(data, offset), get_u16_le(data, offset + 2)) {
+        (Some(low), Some(high)) => Some(low | (high << 16)),
+        _ => None,
+    }
+}
+
+fn get_c_string(data: &[u8], offset: usize) -> Option<String> {
+    match offset < data.len() {
+        true => {
+            let tail = &data[offset..];
+            let end = tail.iter().position(|v| *v == 0).unwrap_or(tail.len());
+            Some(String::from_utf8_lossy(&tail[..end]).into_owned())
+        },
+        false => None,
+    }
+}
+
+fn flag_names(value: u32, names: &[(u32, &'static str)]) -> Vec<String> {
+    names.iter()
+        .filter(|&&(flag, _)| value & flag != 0)
+        .map(|&(_, name)| String::from(name))
+        .collect()
+}
+
+fn contains_bytes(data: &[u8], bytes: &[u8]) -> bool {
+    data.windows(bytes.len()).any(|window| window == bytes)
+}
+
+pub fn detect_kernel_file(path: &std::path::Path)
+        -> std::io::Result<Option<KernelFile>> {
+    let (header, size) = try!(read_header(path));
+    let path = path.to_path_buf();
+    let has_boot_signature = size >= BOOT_SECTOR_SIZE &&
+        has_bytes_at(&header, BOOT_SIGNATURE_OFFSET, BOOT_SIGNATURE);
+
+    let kernel_file = if has_bytes_at(&header, LINUX_MAGIC_OFFSET,
+                                      LINUX_MAGIC) ||
+                         has_bytes_at(&header, ARM_ZIMAGE_MAGIC_OFFSET,
+                                      ARM_ZIMAGE_MAGIC) ||
+                         has_bytes_at(&header, ARM64_IMAGE_MAGIC_OFFSET,
+                                      ARM64_IMAGE_MAGIC) ||
+                         has_bytes_at(&header, RISCV_IMAGE_MAGIC_OFFSET,
+                                      RISCV_IMAGE_MAGIC) {
+        Some(KernelFile::Linux(path))
+    } else if has_bytes_at(&header, 0, ELF_MAGIC) ||
+              has_bytes_at(&header, 0, COM32_MAGIC) {
+        Some(KernelFile::Com32(path))
+    } else if FLOPPY_SIZES.contains(&size) {
+        Some(KernelFile::FDImage(path))
+    } else if size == BOOT_SECTOR_SIZE && has_boot_signature {
+        Some(KernelFile::Boot(path))
+    } else if PXE_SIGNATURES.iter().any(
+            |signature| contains_bytes(&header, signature)) {
+        Some(KernelFile::PXE(path))
+    } else if has_boot_signature {
+        // Boot sector followed by more code, e.g. saved MBR with the rest
+        // of the first track.
+        Some(KernelFile::Boot(path))
+    } else {
+        None
+    };
+
+    Ok(kernel_file)
+}
+
+pub fn read_linux_kernel_info(path: &std::path::Path)
+        -> std::io::Result<Option<LinuxKernelInfo>> {
+    let (header, _) = try!(read_header(path));
+    if !has_bytes_at(&header, LINUX_MAGIC_OFFSET, LINUX_MAGIC) {
+        return Ok(None);
+    }
+
+    let protocol = get_u16_le(&header, LINUX_PROTOCOL_OFFSET).unwrap_or(0);
+    let at_least = |version| protocol >= version;
+
+    let version = match get_u16_le(&header, LINUX_KERNEL_VERSION_OFFSET) {
+        Some(0) | None => None,
+        Some(pointer) => get_c_string(
+            &header, LINUX_KERNEL_VERSION_BASE + pointer as usize),
+    };
+
+    let xload_flags = match at_least(0x20c) {
+        true => get_u16_le(&header, LINUX_XLOADFLAGS_OFFSET).unwrap_or(0),
+        false => 0,
+    };
+
+    let handover_offset = match at_least(0x20b) {
+        true => match get_u32_le(&header, LINUX_HANDOVER_OFFSET_OFFSET) {
+            Some(0) | None => None,
+            Some(offset) => Some(offset),
+        },
+        false => None,
+    };
+
+    Ok(Some(LinuxKernelInfo{
+        version: version,
+        protocol: format!("{}.{:02}", protocol >> 8, protocol & 0xff),
+        load_flags: flag_names(
+            get_u8(&header, LINUX_LOADFLAGS_OFFSET).unwrap_or(0),
+            &LINUX_LOADFLAGS),
+        xload_flags: flag_names(xload_flags, &LINUX_XLOADFLAGS),
+        handover_offset: handover_offset,
+    }))
+}
+
+impl Kernel {
+    fn detect_kernel_file(mut self) -> Kernel {
+        // Explicitly specified type always wins, like in syslinux.
+        if self.kernel_file_source != Some(KernelFileSource::Extension) {
+            return self;
+        }
+
+        let path = match self.kernel_file.as_ref().map(
+                |kernel_file| kernel_file.path().clone()) {
+            Some(path) => path,
+            None => return self,
+        };
+
+        match detect_kernel_file(&path) {
+            Ok(Some(kernel_file)) => {
+                self.kernel_file = Some(kernel_file);
+                self.kernel_file_source = Some(KernelFileSource::Content);
+            },
+
+            Ok(None) => {
+                debug!("Unable to detect type of kernel file {:?}, keeping \
+                        guess based on extension", path);
+            },
+
+            Err(err) => {
+                debug!("Unable to read kernel file {:?}: {}", path, err);
+            },
+        };
+
+        self
+    }
+
+    fn inspect_linux_kernel(mut self) -> Kernel {
+        let path = match self.kernel_file {
+            Some(KernelFile::Linux(ref path)) => Some(path.clone()),
+            _ => None,
+        };
+        let path = match path {
+            Some(path) => path,
+            None => return self,
+        };
+
+        match read_linux_kernel_info(&path) {
+            Ok(Some(linux_info)) => self.linux_info = Some(linux_info),
+
+            Ok(None) => {
+                warn!("File {:?} is not a Linux kernel with boot protocol \
+                       2.00+", path);
+            },
+
+            Err(err) => {
+                warn!("Unable to read Linux kernel {:?}: {}", path, err);
+            },
+        };
+
+        self
+    }
+
+    fn inspect_initrd(mut self) -> Kernel {
+        let path = match self.initrd.clone() {
+            Some(path) => path,
+            None => return self,
+        };
+
+        match read_initrd_info(&path) {
+            // Problems with contents are reported by initrd_diagnostics().
+            Ok(initrd_info) => self.initrd_info = Some(initrd_info),
+
+            Err(err) => {
+                warn!("Unable to read initrd {:?}: {}", path, err);
+            },
+        };
+
+        self
+    }
+}
+
+pub trait Inspector {
+    fn inspect(self, options: &InspectOptions) -> Self;
+}
+
+impl Inspector for Kernel {
+    fn inspect(mut self, options: &InspectOptions) -> Kernel {
+        if options.detect_kernel_files {
+            self = self.detect_kernel_file();
+        }
+        if options.linux_kernels {
+            self = self.inspect_linux_kernel();
+        }
+        if options.initrds {
+            self = self.inspect_initrd();
+        }
+        self
+    }
+}
+
+impl Inspector for KernelOrConfig {
+    fn inspect(self, options: &InspectOptions) -> KernelOrConfig {
+        match self {
+            KernelOrConfig::Kernel(kernel) => {
+                KernelOrConfig::Kernel(kernel.inspect(options))
+            },
+        }
+    }
+}
+
+impl Inspector for Label {
+    fn inspect(mut self, options: &InspectOptions) -> Label {
+        self.kernel_or_config = self.kernel_or_config.inspect(options);
+        self
+    }
+}
+
+impl Inspector for Global {
+    fn inspect(mut self, options: &InspectOptions) -> Global {
+        self.label_defaults = self.label_defaults.inspect(options);
+        self
+    }
+}
+
+impl Inspector for SyslinuxConf {
+    fn inspect(mut self, options: &InspectOptions) -> SyslinuxConf {
+        self.global = self.global.inspect(options);
+
+        use std::iter::FromIterator;
+        self.labels = Labels::from_iter(
+            self.labels.into_iter().map(
+                |(label_name, label)| (label_name, label.inspect(options))));
+
+        self
+    }
+}
+
+fn label_initrd_diagnostics(label_name: Option<&str>,
+                            label: &Label,
+                            seen: &mut Vec<std::path::PathBuf>,
+                            diagnostics: &mut Vec<Diagnostic>) {
+    let kernel = match label.kernel_or_config {
+        KernelOrConfig::Kernel(ref kernel) => kernel,
+    };
+    let (path, initrd_info) = match (&kernel.initrd, &kernel.initrd_info) {
+        (&Some(ref path), &Some(ref initrd_info)) => (path, initrd_info),
+        _ => return,
+    };
+    // Labels inherit initrd from defaults, report it only once.
+    if seen.contains(path) {
+        return;
+    }
+    seen.push(path.clone());
+
+    for segment in &initrd_info.segments {
+        let err = match segment.error {
+            Some(ref err) => err,
+            None => continue,
+        };
+
+        let diagnostic = match segment.compression {
+            Some(compression) if !is_supported(compression) => {
+                Diagnostic::new(
+                    Severity::Warning, "initrd-unsupported",
+                    format!("Compression {:?} of initrd {:?} at offset {} \
+                             is not supported", compression, path,
+                            segment.offset))
+            },
+
+            _ => Diagnostic::new(
+                Severity::Warning, "initrd-invalid",
+                format!("Unable to read initrd {:?} at offset {}: {}",
+                        path, segment.offset, err)),
+        };
+        diagnostics.push(match label_name {
+            Some(label_name) => diagnostic.for_label(label_name),
+            None => diagnostic,
+        });
+    }
+}
+
+// Problems found while inspecting initrd archives.
+pub fn initrd_diagnostics(conf: &SyslinuxConf) -> Vec<Diagnostic> {
+    let mut seen = Vec::new();
+    let mut diagnostics = Vec::new();
+
+    label_initrd_diagnostics(None, &conf.global.label_defaults, &mut seen,
+                             &mut diagnostics);
+    for (label_name, label) in conf.labels.iter() {
+        label_initrd_diagnostics(Some(label_name), label, &mut seen,
+                                 &mut diagnostics);
+    }
+
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use std;
+
+    use super::{InspectOptions, Inspector, detect_kernel_file,
+                initrd_diagnostics, read_linux_kernel_info};
+    use test_util::TempDir;
+    use types::{Global, InitrdCompression, InitrdInfo, InitrdSegment, Kernel,
+                KernelFile, KernelFileSource, KernelOrConfig, Label, Labels,
+                SyslinuxConf};
+
+    fn put(data: &mut Vec<u8>, offset: usize, bytes: &[u8]) {
+        data[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn bzimage() -> Vec<u8> {
+        let mut data = vec![0; 0x400];
+        put(&mut data, 0x1fe, b"\x55\xaa");
+        put(&mut data, 0x202, b"HdrS");
+        put(&mut data, 0x206, b"\x0c\x02");
+        put(&mut data, 0x20e, b"\x80\x00");
+        put(&mut data, 0x211, b"\x01");
+        put(&mut data, 0x236, b"\x03\x00");
+        put(&mut data, 0x264, b"\x90\x01\x00\x00");
+        put(&mut data, 0x280, b"6.1.0 (builder@host) #1\0");
+        data
+    }
+
+    fn detect(data: &[u8]) -> Option<KernelFile> {
+        let dir = TempDir::new();
+        let path = dir.write("kernel", data);
+        detect_kernel_file(&path).unwrap()
+    }
+
+    fn linux(path: &std::path::Path) -> Kernel {
+        Kernel{
+            kernel_file: Some(KernelFile::Linux(path.to_path_buf())),
+            kernel_file_source: Some(KernelFileSource::Extension),
+            ..Kernel::default()
+        }
+    }
+
+    #[test]
+    fn detects_linux_kernel() {
+        match detect(&bzimage()) {
+            Some(KernelFile::Linux(_)) => (),
+            other => panic!("Unexpected kernel file: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn detects_com32_module() {
+        match detect(b"\x7fELF\x01\x01\x01\0\0\0\0\0\0\0\0\0") {
+            Some(KernelFile::Com32(_)) => (),
+            other => panic!("Unexpected kernel file: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn detects_boot_sector() {
+        let mut sector = vec![0; 512];
+        put(&mut sector, 510, b"\x55\xaa");
+        match detect(&sector) {
+            Some(KernelFile::Boot(_)) => (),
+            other => panic!("Unexpected kernel file: {:?}", other),
+        }
+
+        // Boot sector followed by more sectors.
+        sector.extend(vec![0; 2048]);
+        match detect(&sector) {
+            Some(KernelFile::Boot(_)) => (),
+            other => panic!("Unexpected kernel file: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn detects_pxe_program() {
+        let mut program = vec![0; 4096];
+        put(&mut program, 1000, b"PXENV+");
+        match detect(&program) {
+            Some(KernelFile::PXE(_)) => (),
+            other => panic!("Unexpected kernel file: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn detects_floppy_image() {
+        match detect(&vec![0; 1474560]) {
+            Some(KernelFile::FDImage(_)) => (),
+            other => panic!("Unexpected kernel file: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn does_not_detect_unknown_file() {
+        assert_eq!(detect(b"memtest"), None);
+    }
+
+    #[test]
+    fn reads_linux_kernel_info() {
+        let dir = TempDir::new();
+        let path = dir.write("vmlinuz", &bzimage());
+        let info = read_linux_kernel_info(&path).unwrap().unwrap();
+
+        assert_eq!(info.version,
+                   Some(String::from("6.1.0 (builder@host) #1")));
+        assert_eq!(info.protocol, "2.12");
+        assert_eq!(info.load_flags, vec![String::from("LOADED_HIGH")]);
+        assert_eq!(info.xload_flags,
+                   vec![String::from("XLF_KERNEL_64"),
+                        String::from("XLF_CAN_BE_LOADED_ABOVE_4G")]);
+        assert_eq!(info.handover_offset, Some(0x190));
+    }
+
+    #[test]
+    fn does_not_read_info_of_other_files() {
+        let dir = TempDir::new();
+        let path = dir.write("memtest", &vec![0; 1024]);
+        assert!(read_linux_kernel_info(&path).unwrap().is_none());
+    }
+
+    #[test]
+    fn does_not_read_files_by_default() {
+        let dir = TempDir::new();
+        let path = dir.write("memtest", b"\x7fELF");
+
+        let kernel = linux(&path).inspect(&InspectOptions::default());
+        assert_eq!(kernel.kernel_file, Some(KernelFile::Linux(path.clone())));
+        assert_eq!(kernel.kernel_file_source,
+                   Some(KernelFileSource::Extension));
+    }
+
+    #[test]
+    fn detection_overrides_extension() {
+        let dir = TempDir::new();
+        let path = dir.write("memtest", b"\x7fELF");
+
+        let kernel = linux(&path).inspect(&InspectOptions{
+            detect_kernel_files: true,
+            ..InspectOptions::default()
+        });
+        assert_eq!(kernel.kernel_file, Some(KernelFile::Com32(path.clone())));
+        assert_eq!(kernel.kernel_file_source, Some(KernelFileSource::Content));
+    }
+
+    #[test]
+    fn detection_keeps_explicit_type() {
+        let dir = TempDir::new();
+        let path = dir.write("memtest", b"\x7fELF");
+
+        let mut kernel = linux(&path);
+        kernel.kernel_file_source = Some(KernelFileSource::Tag);
+        let kernel = kernel.inspect(&InspectOptions{
+            detect_kernel_files: true,
+            ..InspectOptions::default()
+        });
+        assert_eq!(kernel.kernel_file, Some(KernelFile::Linux(path.clone())));
+    }
+
+    fn with_initrd(path: &str, compression: InitrdCompression) -> Label {
+        Label{
+            kernel_or_config: KernelOrConfig::Kernel(Kernel{
+                initrd: Some(std::path::PathBuf::from(path)),
+                initrd_info: Some(InitrdInfo{
+                    segments: vec![InitrdSegment{
+                        offset: 0,
+                        compression: Some(compression),
+                        entries: Vec::new(),
+                        uncompressed_size: 0,
+                        error: Some(String::from("Failed")),
+                    }],
+                    uncompressed_size: 0,
+                }),
+                ..Kernel::default()
+            }),
+            ..Label::default()
+        }
+    }
+
+    #[test]
+    fn reports_initrd_problems_once() {
+        let defaults = with_initrd("/initrd.lzo", InitrdCompression::Lzo);
+        let mut labels = Labels::new();
+        labels.insert(String::from("linux"), defaults.clone());
+        labels.insert(String::from("rescue"),
+                      with_initrd("/rescue.gz", InitrdCompression::Gzip));
+        let conf = SyslinuxConf{
+            global: Global{
+                label_defaults: defaults,
+                ..Global::default()
+            },
+            labels: labels,
+        };
+
+        let diagnostics = initrd_diagnostics(&conf);
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].code, "initrd-unsupported");
+        assert_eq!(diagnostics[0].label, None);
+        assert_eq!(diagnostics[1].code, "initrd-invalid");
+        assert_eq!(diagnostics[1].label, Some(String::from("rescue")));
+    }
+}
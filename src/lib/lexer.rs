@@ -0,0 +1,337 @@
+use std;
+
+use types::{Diagnostic, Location, Severity};
+
+// Syslinux works with bytes, text is decoded only to be kept in the model.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    UTF8,
+    // ISO 8859-1.
+    Latin1,
+    // Code page of VGA text mode, which syslinux menus are shown in.
+    CP437,
+}
+
+// Upper half of code page 437, the lower half is ASCII.
+const CP437_HIGH: [char; 128] = [
+    '\u{00C7}', '\u{00FC}', '\u{00E9}', '\u{00E2}', '\u{00E4}', '\u{00E0}',
+    '\u{00E5}', '\u{00E7}', '\u{00EA}', '\u{00EB}', '\u{00E8}', '\u{00EF}',
+    '\u{00EE}', '\u{00EC}', '\u{00C4}', '\u{00C5}', '\u{00C9}', '\u{00E6}',
+    '\u{00C6}', '\u{00F4}', '\u{00F6}', '\u{00F2}', '\u{00FB}', '\u{00F9}',
+    '\u{00FF}', '\u{00D6}', '\u{00DC}', '\u{00A2}', '\u{00A3}', '\u{00A5}',
+    '\u{20A7}', '\u{0192}', '\u{00E1}', '\u{00ED}', '\u{00F3}', '\u{00FA}',
+    '\u{00F1}', '\u{00D1}', '\u{00AA}', '\u{00BA}', '\u{00BF}', '\u{2310}',
+    '\u{00AC}', '\u{00BD}', '\u{00BC}', '\u{00A1}', '\u{00AB}', '\u{00BB}',
+    '\u{2591}', '\u{2592}', '\u{2593}', '\u{2502}', '\u{2524}', '\u{2561}',
+    '\u{2562}', '\u{2556}', '\u{2555}', '\u{2563}', '\u{2551}', '\u{2557}',
+    '\u{255D}', '\u{255C}', '\u{255B}', '\u{2510}', '\u{2514}', '\u{2534}',
+    '\u{252C}', '\u{251C}', '\u{2500}', '\u{253C}', '\u{255E}', '\u{255F}',
+    '\u{255A}', '\u{2554}', '\u{2569}', '\u{2566}', '\u{2560}', '\u{2550}',
+    '\u{256C}', '\u{2567}', '\u{2568}', '\u{2564}', '\u{2565}', '\u{2559}',
+    '\u{2558}', '\u{2552}', '\u{2553}', '\u{256B}', '\u{256A}', '\u{2518}',
+    '\u{250C}', '\u{2588}', '\u{2584}', '\u{258C}', '\u{2590}', '\u{2580}',
+    '\u{03B1}', '\u{00DF}', '\u{0393}', '\u{03C0}', '\u{03A3}', '\u{03C3}',
+    '\u{00B5}', '\u{03C4}', '\u{03A6}', '\u{0398}', '\u{03A9}', '\u{03B4}',
+    '\u{221E}', '\u{03C6}', '\u{03B5}', '\u{2229}', '\u{2261}', '\u{00B1}',
+    '\u{2265}', '\u{2264}', '\u{2320}', '\u{2321}', '\u{00F7}', '\u{2248}',
+    '\u{00B0}', '\u{2219}', '\u{00B7}', '\u{221A}', '\u{207F}', '\u{00B2}',
+    '\u{25A0}', '\u{00A0}',
+];
+
+// Invalid UTF-8 sequences are replaced with U+FFFD.
+pub fn decode(bytes: &[u8], encoding: Encoding) -> String {
+    match encoding {
+        Encoding::UTF8 => String::from(String::from_utf8_lossy(bytes)),
+        Encoding::Latin1 => bytes.iter().map(|c| *c as char).collect(),
+        Encoding::CP437 => bytes.iter().map(|c| match *c < 0x80 {
+            true => *c as char,
+            false => CP437_HIGH[(*c - 0x80) as usize],
+        }).collect(),
+    }
+}
+
+// Characters which can not be encoded are replaced with "?".
+pub fn encode(text: &str, encoding: Encoding) -> Vec<u8> {
+    match encoding {
+        Encoding::UTF8 => text.as_bytes().to_vec(),
+        Encoding::Latin1 => text.chars().map(|c| match c as u32 {
+            code if code < 0x100 => code as u8,
+            _ => b'?',
+        }).collect(),
+        Encoding::CP437 => text.chars().map(|c| match (c as u32) < 0x80 {
+            true => c as u8,
+            false => CP437_HIGH.iter()
+                .position(|high| *high == c)
+                .map_or(b'?', |index| 0x80 + index as u8),
+        }).collect(),
+    }
+}
+
+// Syslinux takes every control character for whitespace.
+fn is_space(c: char) -> bool {
+    c <= ' '
+}
+
+fn split_word(text: &str) -> (&str, &str) {
+    let text = text.trim_left_matches(is_space);
+    match text.find(is_space) {
+        Some(index) => {
+            (&text[..index], text[index..].trim_left_matches(is_space))
+        },
+        None => (text, ""),
+    }
+}
+
+// Keyword as written, "MENU" and "TEXT" together with the next word, and
+// argument. Keyword ends with whitespace or end of line, so abbreviations
+// and keywords glued to argument are not keywords.
+fn split_directive(line: &str) -> (String, &str) {
+    use std::ascii::AsciiExt;
+
+    let (word, rest) = split_word(line);
+    let upper = word.to_ascii_uppercase();
+    match upper == "MENU" || upper == "TEXT" {
+        true if !rest.is_empty() => {
+            let (next, rest) = split_word(rest);
+            (format!("{} {}", word, next), rest)
+        },
+        _ => (String::from(word), rest),
+    }
+}
+
+// Upper case keyword of line.
+pub fn keyword(line: &str) -> String {
+    use std::ascii::AsciiExt;
+    split_directive(line).0.to_ascii_uppercase()
+}
+
+// TEXT HELP block starts with line without anything after the keyword.
+pub fn starts_text(line: &str) -> bool {
+    use std::ascii::AsciiExt;
+    let (keyword, argument) = split_directive(line);
+    argument.is_empty() && keyword.to_ascii_uppercase() == "TEXT HELP"
+}
+
+// LABEL without name is not a label.
+pub fn starts_label(line: &str) -> bool {
+    use std::ascii::AsciiExt;
+    let (keyword, argument) = split_directive(line);
+    !argument.is_empty() && keyword.to_ascii_uppercase() == "LABEL"
+}
+
+// Anything may follow ENDTEXT.
+pub fn ends_text(line: &str) -> bool {
+    keyword(line) == "ENDTEXT"
+}
+
+// Length of line at start of input with its line ending, if the line ends
+// in input. Line ends with "\n", "\r\n" or bare "\r", like in the parser.
+// "\r" at the end of input may be followed by "\n", so it ends line only
+// if input is complete.
+pub fn line_length(input: &[u8], complete: bool) -> Option<usize> {
+    match input.iter().position(|c| *c == b'\n' || *c == b'\r') {
+        Some(index) if input[index] == b'\r' => {
+            match (input.get(index + 1), complete) {
+                (Some(&b'\n'), _) => Some(index + 2),
+                (None, false) => None,
+                _ => Some(index + 1),
+            }
+        },
+        Some(index) => Some(index + 1),
+        None => None,
+    }
+}
+
+// Lines of input with their line endings, the last one may have none.
+// Syslinux reads configuration line by line and has no line continuation,
+// backslash at the end of line is part of its value, e.g. of APPEND.
+pub fn split_lines(input: &[u8]) -> Vec<&[u8]> {
+    let mut lines = Vec::new();
+    let mut rest = input;
+    while !rest.is_empty() {
+        let length = line_length(rest, true).unwrap_or(rest.len());
+        lines.push(&rest[..length]);
+        rest = &rest[length..];
+    }
+    lines
+}
+
+// Offsets at which lines start, with length of input if the last line has
+// line ending.
+pub fn line_starts(input: &[u8]) -> Vec<usize> {
+    let mut starts = vec![0];
+    let mut offset = 0;
+    for line in split_lines(input) {
+        offset += line.len();
+        if line.ends_with(b"\n") || line.ends_with(b"\r") {
+            starts.push(offset);
+        }
+    }
+    starts
+}
+
+// Line of input at which rest starts, counted from 1.
+pub fn line_at(input: &[u8], rest: &[u8]) -> usize {
+    split_lines(&input[..input.len() - rest.len()]).iter()
+        .filter(|line| line.ends_with(b"\n") || line.ends_with(b"\r"))
+        .count() + 1
+}
+
+// Offset at which line starts, length of input if there is no such line.
+pub fn line_offset(input: &[u8], line: usize) -> usize {
+    split_lines(input).iter()
+        .take(std::cmp::max(line, 1) - 1)
+        .map(|part| part.len())
+        .sum()
+}
+
+// Decodes configuration and rewrites it line for line the way syslinux
+// tokenizes it: every line ends with "\n" only, indentation and trailing
+// whitespace are dropped, keyword is separated from argument by a single
+// space. Arguments and lines of TEXT HELP blocks are kept as they are.
+// Lines of diagnostics are counted from first_line.
+pub fn normalize(input: &[u8], encoding: Encoding, first_line: usize)
+                 -> (String, Vec<Diagnostic>) {
+    let mut text = String::with_capacity(input.len() + 1);
+    let mut diagnostics = Vec::new();
+
+    let mut in_text = false;
+    for (index, line) in split_lines(input).into_iter().enumerate() {
+        if encoding == Encoding::UTF8 && std::str::from_utf8(line).is_err() {
+            diagnostics.push(Diagnostic::new(
+                Severity::Warning, "invalid-encoding",
+                String::from("Line is not valid UTF-8, invalid bytes are \
+                              replaced"))
+                .at(Location::line(first_line + index)));
+        }
+
+        let decoded = decode(line, encoding);
+        let line = decoded.trim_right_matches(is_space);
+        let trimmed = line.trim_left_matches(is_space);
+        if in_text {
+            in_text = !ends_text(line);
+            text.push_str(match in_text {
+                true => line,
+                false => "ENDTEXT",
+            });
+        } else if trimmed.is_empty() || trimmed.starts_with('#') {
+            text.push_str(trimmed);
+        } else {
+            let (keyword, argument) = split_directive(line);
+            in_text = starts_text(line);
+            text.push_str(&keyword);
+            if !argument.is_empty() {
+                text.push(' ');
+                text.push_str(argument);
+            }
+        }
+        text.push('\n');
+    }
+
+    (text, diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Encoding, decode, encode, ends_text, keyword, line_at,
+                line_length, line_offset, line_starts, normalize,
+                split_lines, starts_label, starts_text};
+    use types::Location;
+
+    #[test]
+    fn decodes_and_encodes_text() {
+        assert_eq!(decode(b"caf\xe9", Encoding::Latin1), "caf\u{e9}");
+        assert_eq!(decode(b"caf\x82", Encoding::CP437), "caf\u{e9}");
+        assert_eq!(decode(b"caf\xff", Encoding::UTF8), "caf\u{fffd}");
+
+        assert_eq!(encode("caf\u{e9}", Encoding::Latin1), b"caf\xe9");
+        assert_eq!(encode("caf\u{e9}", Encoding::CP437), b"caf\x82");
+        assert_eq!(encode("\u{2592}", Encoding::CP437), b"\xb1");
+        assert_eq!(encode("\u{2592}", Encoding::Latin1), b"?");
+    }
+
+    #[test]
+    fn finds_keywords_like_syslinux() {
+        assert_eq!(keyword("  kernel\tvmlinuz"), "KERNEL");
+        assert_eq!(keyword("menu \t title Boot menu"), "MENU TITLE");
+        assert_eq!(keyword("MENU"), "MENU");
+        // Abbreviation or keyword glued to argument is not a keyword.
+        assert_eq!(keyword("KERN vmlinuz"), "KERN");
+        assert_eq!(keyword("KERNEL\x01vmlinuz"), "KERNEL");
+        assert_eq!(keyword("KERNEL/vmlinuz"), "KERNEL/VMLINUZ");
+    }
+
+    #[test]
+    fn finds_text_blocks_and_labels() {
+        assert!(starts_text("text\thelp"));
+        assert!(!starts_text("TEXT HELP me"));
+        assert!(ends_text("  ENDTEXT anything"));
+        assert!(!ends_text("ENDTEXTS"));
+        assert!(starts_label("LABEL linux"));
+        assert!(!starts_label("LABEL"));
+    }
+
+    #[test]
+    fn splits_lines_at_any_line_ending() {
+        assert_eq!(split_lines(b"a\nb\r\nc\rd"),
+                   vec![&b"a\n"[..], &b"b\r\n"[..], &b"c\r"[..], &b"d"[..]]);
+        assert_eq!(line_length(b"a\r", false), None);
+        assert_eq!(line_length(b"a\r", true), Some(2));
+        assert_eq!(line_length(b"a\rb", false), Some(2));
+        assert_eq!(line_length(b"a", true), None);
+        assert_eq!(split_lines(b"APPEND a \\\nb\n"),
+                   vec![&b"APPEND a \\\n"[..], &b"b\n"[..]]);
+    }
+
+    #[test]
+    fn counts_lines() {
+        let input = b"a\nb\r\nc\rd";
+        assert_eq!(line_at(input, &input[..]), 1);
+        assert_eq!(line_at(input, &input[5..]), 3);
+        assert_eq!(line_at(input, &input[7..]), 4);
+
+        assert_eq!(line_offset(input, 1), 0);
+        assert_eq!(line_offset(input, 2), 2);
+        assert_eq!(line_offset(input, 4), 7);
+        assert_eq!(line_offset(input, 5), input.len());
+
+        assert_eq!(line_starts(input), vec![0, 2, 5, 7]);
+        assert_eq!(line_starts(b"a\r\n"), vec![0, 3]);
+        assert_eq!(line_starts(b""), vec![0]);
+    }
+
+    #[test]
+    fn normalizes_lines() {
+        let input = b"  kernel\t\tvmlinuz  \r\n\
+                      # Comment \r\n\
+                      \r\n\
+                      TEXT HELP\r\n\
+                      \x20 Help  text\r\n\
+                      \x20 endtext now\r\n\
+                      LABEL caf\xe9\r\
+                      SAY bare\rline";
+        let (text, diagnostics) = normalize(input, Encoding::Latin1, 1);
+
+        assert_eq!(text, "kernel vmlinuz\n\
+                          # Comment\n\
+                          \n\
+                          TEXT HELP\n\
+                          \x20 Help  text\n\
+                          ENDTEXT\n\
+                          LABEL caf\u{e9}\n\
+                          SAY bare\n\
+                          line\n");
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn reports_invalid_utf8() {
+        let (text, diagnostics) = normalize(b"SAY ok\nSAY \xff\n",
+                                            Encoding::UTF8, 10);
+
+        assert_eq!(text, "SAY ok\nSAY \u{fffd}\n");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, "invalid-encoding");
+        assert_eq!(diagnostics[0].location, Some(Location::line(11)));
+    }
+}
@@ -3,12 +3,52 @@
 #[macro_use] extern crate log;
 #[macro_use] extern crate nom;
 
+mod bls;
+mod boot;
+mod diff;
+mod edit;
+mod envelope;
+mod fdt;
+mod grub;
+mod initrd;
+mod inspect;
+mod lexer;
+mod lint;
+mod manifest;
+mod merge;
 mod parser;
 mod reader;
+mod schema;
+mod source;
+mod stream;
+#[cfg(test)]
+mod test_util;
+mod toml_writer;
 mod types;
+mod validate;
+mod writer;
 
-pub use reader::{LocalConfType, Reader, ReaderError};
+pub use bls::{BlsConf, BlsEntry, from_bls, to_bls, write_bls};
+pub use diff::diff;
+pub use edit::{EditError, KernelField};
+pub use fdt::{find_dtb, read_compatible};
+pub use grub::{from_grub_cfg, to_grub_cfg};
+pub use initrd::read_initrd_info;
+pub use inspect::{InspectOptions, detect_kernel_file, read_linux_kernel_info};
+pub use lexer::{Encoding, decode, encode};
+pub use lint::{LintRule, Linter};
+pub use manifest::build_manifest;
+pub use merge::merge;
+pub use parser::{Dialect, DuplicateLabels, ParserOptions};
+pub use reader::{LocalConfType, Reader, ReaderError, get_device_path,
+                 resolve_paths};
+pub use schema::{SCHEMA_VERSION, envelope_json_schema, json_schema};
+pub use source::{Document, write_file};
+pub use stream::{StreamEvent, StreamParser};
+pub use toml_writer::to_toml;
 pub use types::*;
+pub use validate::{check_commands, check_files, validate};
+pub use writer::to_syslinux_cfg;
 
 // TODO: Tests.
 // TODO: Documentation.
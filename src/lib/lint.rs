@@ -0,0 +1,364 @@
+use std;
+
+use edit::command_label;
+use parser::{Dialect, DuplicateLabels};
+use source::Document;
+use types::{Diagnostic, Global, Severity, SyslinuxConf};
+
+// Syslinux truncates longer label names.
+const MAX_LABEL_LENGTH: usize = 255;
+
+// Kernel parameters which are meant to be given more than once.
+const REPEATABLE_PARAMS: [&'static str; 1] = ["console"];
+
+#[derive(Debug, Clone)]
+pub struct LintRule {
+    pub id: &'static str,
+    pub severity: Severity,
+    pub enabled: bool,
+    pub description: &'static str,
+}
+
+fn rule(id: &'static str, description: &'static str) -> LintRule {
+    LintRule{
+        id: id,
+        severity: Severity::Warning,
+        enabled: true,
+        description: description,
+    }
+}
+
+// Checks for configurations which are valid, but probably do not do what
+// was meant. Unlike validate() it works on source, so it sees duplicates.
+#[derive(Debug, Clone)]
+pub struct Linter {
+    rules: Vec<LintRule>,
+}
+
+impl Default for Linter {
+    fn default() -> Linter {
+        Linter{
+            rules: vec![
+                rule("default-unknown-label",
+                     "DEFAULT is not a label, so it is booted as kernel"),
+                rule("ontimeout-unknown-label",
+                     "ONTIMEOUT is not a label, so it is booted as kernel"),
+                rule("onerror-unknown-label",
+                     "ONERROR is not a label, so it is booted as kernel"),
+                rule("duplicate-label",
+                     "Label is defined more than once, only one of them is \
+                      used"),
+                rule("unreachable-label",
+                     "Label can not be chosen, because there is neither \
+                      prompt nor menu"),
+                rule("no-prompt-no-timeout",
+                     "PROMPT 0 with TIMEOUT 0 boots DEFAULT without a chance \
+                      to choose another label"),
+                rule("label-too-long",
+                     "Label name is longer than syslinux supports"),
+                rule("append-duplicate-param",
+                     "Kernel parameter is given more than once in APPEND"),
+            ],
+        }
+    }
+}
+
+impl Linter {
+    pub fn rules(&self) -> &[LintRule] {
+        &self.rules
+    }
+
+    fn rule_mut(&mut self, id: &str) -> Option<&mut LintRule> {
+        self.rules.iter_mut().find(|rule| rule.id == id)
+    }
+
+    // False if there is no such rule.
+    pub fn set_enabled(&mut self, id: &str, enabled: bool) -> bool {
+        match self.rule_mut(id) {
+            Some(rule) => {
+                rule.enabled = enabled;
+                true
+            },
+            None => false,
+        }
+    }
+
+    // False if there is no such rule.
+    pub fn set_severity(&mut self, id: &str, severity: Severity) -> bool {
+        match self.rule_mut(id) {
+            Some(rule) => {
+                rule.severity = severity;
+                true
+            },
+            None => false,
+        }
+    }
+
+    pub fn lint(&self, document: &Document) -> Vec<Diagnostic> {
+        let mut lint = Lint{
+            rules: &self.rules,
+            diagnostics: Vec::new(),
+        };
+
+        let conf = document.conf();
+        lint.label_reference(conf, "default-unknown-label", "DEFAULT",
+                             &conf.global.default);
+        lint.label_reference(conf, "ontimeout-unknown-label", "ONTIMEOUT",
+                             &conf.global.ontimeout);
+        lint.label_reference(conf, "onerror-unknown-label", "ONERROR",
+                             &conf.global.onerror);
+        lint.prompt(conf, document.options().dialect);
+        lint.duplicates(document);
+
+        lint.append(None, &conf.global.label_defaults.kernel().append);
+        for (label_name, label) in conf.labels.iter() {
+            let label_name: &str = label_name;
+            if label_name.len() > MAX_LABEL_LENGTH {
+                lint.report("label-too-long", Some(label_name), format!(
+                    "Label name is longer than {} bytes", MAX_LABEL_LENGTH));
+            }
+            lint.append(Some(label_name), &label.kernel().append);
+        }
+
+        lint.diagnostics
+    }
+}
+
+struct Lint<'a> {
+    rules: &'a [LintRule],
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Lint<'a> {
+    fn report(&mut self, id: &str, label_name: Option<&str>, message: String) {
+        let diagnostic = match self.rules.iter().find(|rule| rule.id == id) {
+            Some(rule) if rule.enabled => {
+                Diagnostic::new(rule.severity, rule.id, message)
+            },
+            _ => return,
+        };
+        self.diagnostics.push(match label_name {
+            Some(label_name) => diagnostic.for_label(label_name),
+            None => diagnostic,
+        });
+    }
+
+    fn label_reference(&mut self, conf: &SyslinuxConf, id: &str, tag: &str,
+                       command: &Option<String>) {
+        if let Some(label_name) = command_label(command) {
+            if !conf.labels.contains_key(label_name) {
+                self.report(id, None, format!("{} \"{}\" is not a label, so \
+                                               it is booted as kernel", tag,
+                                              label_name));
+            }
+        }
+    }
+
+    // Syslinux without PROMPT 1 or UI boots DEFAULT right away, unless Shift
+    // or Alt is held. U-Boot shows menu if there is timeout.
+    fn prompt(&mut self, conf: &SyslinuxConf, dialect: Dialect) {
+        let global: &Global = &conf.global;
+        let no_timeout = global.timeout.map_or(true, |timeout| timeout == 0.0);
+
+        if global.prompt == Some(false) && no_timeout && global.ui.is_none() {
+            self.report("no-prompt-no-timeout", None, String::from(
+                "PROMPT 0 and TIMEOUT 0 boot DEFAULT without a chance to \
+                 choose another label"));
+        }
+
+        let has_choice = global.prompt == Some(true) || match dialect {
+            Dialect::Syslinux => global.ui.is_some(),
+            Dialect::UBoot => !no_timeout,
+        };
+        if has_choice {
+            return;
+        }
+
+        let targets: Vec<&str> = [&global.default, &global.ontimeout,
+                                  &global.onerror].iter()
+            .filter_map(|command| command_label(*command))
+            .collect();
+        for label_name in conf.labels.keys() {
+            let label_name: &str = label_name;
+            if !targets.contains(&label_name) {
+                self.report("unreachable-label", Some(label_name),
+                            String::from("Label can be chosen only if Shift \
+                                          or Alt is held, there is neither \
+                                          prompt nor menu"));
+            }
+        }
+    }
+
+    fn duplicates(&mut self, document: &Document) {
+        let ignored = match document.options().duplicate_labels {
+            DuplicateLabels::LastWins => "former",
+            _ => "latter",
+        };
+        let mut first_lines = std::collections::HashMap::new();
+        for (label_name, line) in document.label_lines() {
+            let first_line = first_lines.get(&label_name).cloned();
+            match first_line {
+                Some(first_line) => {
+                    let message = format!(
+                        "Label is defined at line {} and again at line {}, \
+                         the {} is ignored", first_line, line, ignored);
+                    self.report("duplicate-label", Some(label_name.as_str()),
+                                message);
+                },
+                None => {
+                    first_lines.insert(label_name, line);
+                },
+            };
+        }
+    }
+
+    fn append(&mut self, label_name: Option<&str>, append: &Option<String>) {
+        let append = match *append {
+            Some(ref append) => append,
+            None => return,
+        };
+
+        let mut seen: Vec<&str> = Vec::new();
+        let mut reported: Vec<&str> = Vec::new();
+        for arg in append.split_whitespace() {
+            let name = match arg.find('=') {
+                Some(index) => &arg[..index],
+                None => continue,
+            };
+            if REPEATABLE_PARAMS.iter().any(|param| *param == name) ||
+                    reported.contains(&name) {
+                continue;
+            }
+            match seen.contains(&name) {
+                true => {
+                    self.report("append-duplicate-param", label_name, format!(
+                        "Kernel parameter \"{}\" is given more than once",
+                        name));
+                    reported.push(name);
+                },
+                false => seen.push(name),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std;
+
+    use super::Linter;
+    use parser::{Dialect, ParserOptions};
+    use source::Document;
+    use types::Severity;
+
+    fn lint(text: &str) -> Vec<(Severity, String)> {
+        let document = Document::parse(text, &ParserOptions::default())
+            .unwrap();
+        Linter::default().lint(&document).into_iter()
+            .map(|diagnostic| (diagnostic.severity, diagnostic.code))
+            .collect()
+    }
+
+    fn codes(text: &str) -> Vec<String> {
+        lint(text).into_iter().map(|(_, code)| code).collect()
+    }
+
+    #[test]
+    fn accepts_default_label() {
+        assert!(codes("PROMPT 1\n\
+                       DEFAULT linux single\n\
+                       LABEL linux\n\
+                       \x20 KERNEL vmlinuz\n").is_empty());
+    }
+
+    #[test]
+    fn warns_about_default_command_line() {
+        assert_eq!(lint("PROMPT 1\n\
+                         DEFAULT vmlinuz root=/dev/sda1\n\
+                         LABEL linux\n\
+                         \x20 KERNEL vmlinuz\n"),
+                   vec![(Severity::Warning,
+                         String::from("default-unknown-label"))]);
+    }
+
+    #[test]
+    fn reports_duplicate_labels() {
+        assert_eq!(codes("PROMPT 1\n\
+                          LABEL linux\n\
+                          \x20 KERNEL vmlinuz\n\
+                          LABEL linux\n\
+                          \x20 KERNEL vmlinuz.old\n"),
+                   vec!["duplicate-label"]);
+    }
+
+    #[test]
+    fn reports_unreachable_labels() {
+        assert_eq!(codes("PROMPT 0\n\
+                          TIMEOUT 50\n\
+                          DEFAULT linux\n\
+                          LABEL linux\n\
+                          \x20 KERNEL vmlinuz\n\
+                          LABEL rescue\n\
+                          \x20 KERNEL vmlinuz\n"),
+                   vec!["unreachable-label"]);
+    }
+
+    #[test]
+    fn uboot_menu_needs_timeout_only() {
+        let text = "TIMEOUT 50\n\
+                    DEFAULT linux\n\
+                    LABEL linux\n\
+                    \x20 KERNEL vmlinuz\n\
+                    LABEL rescue\n\
+                    \x20 KERNEL vmlinuz\n";
+        let mut options = ParserOptions::default();
+        options.dialect = Dialect::UBoot;
+        let document = Document::parse(text, &options).unwrap();
+
+        assert!(Linter::default().lint(&document).is_empty());
+    }
+
+    #[test]
+    fn reports_no_prompt_no_timeout() {
+        assert_eq!(codes("PROMPT 0\n\
+                          TIMEOUT 0\n\
+                          DEFAULT linux\n\
+                          LABEL linux\n\
+                          \x20 KERNEL vmlinuz\n"),
+                   vec!["no-prompt-no-timeout"]);
+    }
+
+    #[test]
+    fn reports_duplicate_params_once() {
+        assert_eq!(codes("PROMPT 1\n\
+                          LABEL linux\n\
+                          \x20 KERNEL vmlinuz\n\
+                          \x20 APPEND root=/dev/sda1 console=tty0 \
+                          console=ttyS0 root=/dev/sda2 root=/dev/sda3\n"),
+                   vec!["append-duplicate-param"]);
+    }
+
+    #[test]
+    fn reports_long_label_names() {
+        let label_name: String = std::iter::repeat('x').take(256).collect();
+        let text = format!("PROMPT 1\nLABEL {}\n  KERNEL vmlinuz\n",
+                           label_name);
+        assert_eq!(codes(&text), vec!["label-too-long"]);
+    }
+
+    #[test]
+    fn applies_rule_settings() {
+        let document = Document::parse("PROMPT 1\n\
+                                        DEFAULT vmlinuz\n\
+                                        LABEL linux\n\
+                                        \x20 KERNEL vmlinuz\n",
+                                       &ParserOptions::default()).unwrap();
+        let mut linter = Linter::default();
+
+        assert!(linter.set_severity("default-unknown-label", Severity::Error));
+        assert_eq!(linter.lint(&document)[0].severity, Severity::Error);
+        assert!(linter.set_enabled("default-unknown-label", false));
+        assert!(linter.lint(&document).is_empty());
+        assert!(!linter.set_enabled("no-such-rule", false));
+    }
+}
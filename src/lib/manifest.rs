@@ -0,0 +1,282 @@
+use std;
+
+extern crate sha2;
+
+use reader::get_device_path;
+use types::{KernelOrConfig, Label, Manifest, ManifestEntry, SyslinuxConf};
+
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+fn hash_file(path: &std::path::Path) -> std::io::Result<(String, u64)> {
+    use std::io::Read;
+    use self::sha2::Digest;
+
+    let mut file = try!(std::fs::File::open(path));
+    let mut hasher = sha2::Sha256::default();
+    let mut buf = vec![0; READ_BUFFER_SIZE];
+    let mut size = 0;
+
+    loop {
+        match try!(file.read(&mut buf)) {
+            0 => break,
+            read => {
+                hasher.input(&buf[..read]);
+                size += read as u64;
+            },
+        }
+    }
+
+    let sha256 = hasher.result().iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    Ok((sha256, size))
+}
+
+// Path of file outside of the root directory, relative to it. Unlike host
+// path, it is the same for all build roots with the same layout.
+fn outside_path(root_dir: &std::path::Path, path: &std::path::Path)
+        -> std::path::PathBuf {
+    let root_dir = std::fs::canonicalize(root_dir)
+        .unwrap_or_else(|_| root_dir.to_path_buf());
+    let path = std::fs::canonicalize(path)
+        .unwrap_or_else(|_| path.to_path_buf());
+
+    let common = root_dir.components().zip(path.components())
+        .take_while(|&(root_component, component)| root_component == component)
+        .count();
+
+    let mut relative = std::path::PathBuf::new();
+    for _ in root_dir.components().skip(common) {
+        relative.push("..");
+    }
+    for component in path.components().skip(common) {
+        relative.push(component.as_os_str());
+    }
+    relative
+}
+
+struct ManifestBuilder<'a> {
+    root_dir: &'a std::path::Path,
+    entries: std::collections::BTreeMap<std::path::PathBuf, ManifestEntry>,
+}
+
+impl<'a> ManifestBuilder<'a> {
+    fn add_file(&mut self, path: &std::path::Path, role: String) {
+        // Paths relative to the boot device, so manifests of different image
+        // builds are comparable.
+        let device_path = get_device_path(self.root_dir, path)
+            .unwrap_or_else(|| outside_path(self.root_dir, path));
+
+        if let Some(entry) = self.entries.get_mut(&device_path) {
+            entry.roles.push(role);
+            return;
+        }
+
+        let (sha256, size, error) = match hash_file(path) {
+            Ok((sha256, size)) => (Some(sha256), Some(size), None),
+            Err(err) => {
+                warn!("Unable to hash file {:?}: {}", path, err);
+                (None, None, Some(format!("{}", err)))
+            },
+        };
+
+        self.entries.insert(device_path.clone(), ManifestEntry{
+            path: device_path,
+            sha256: sha256,
+            size: size,
+            error: error,
+            roles: vec![role],
+        });
+    }
+
+    fn add_dir(&mut self, path: &std::path::Path, role: String) {
+        let mut dir_entries: Vec<(std::path::PathBuf, std::fs::FileType)> =
+            match std::fs::read_dir(path) {
+                Ok(dir_entries) => dir_entries
+                    .filter_map(|dir_entry| dir_entry.ok())
+                    .filter_map(|dir_entry| match dir_entry.file_type() {
+                        Ok(file_type) => Some((dir_entry.path(), file_type)),
+                        Err(_) => None,
+                    })
+                    .collect(),
+                Err(err) => {
+                    warn!("Unable to list directory {:?}: {}", path, err);
+                    return;
+                },
+            };
+        dir_entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+        for (dir_entry, file_type) in dir_entries {
+            // Symlinks are not followed into directories, they may form a
+            // loop. Symlinks to files are hashed as files.
+            if file_type.is_dir() {
+                self.add_dir(&dir_entry, role.clone());
+            } else if file_type.is_symlink() && dir_entry.is_dir() {
+                debug!("Not descending into symlink {:?}", dir_entry);
+            } else {
+                self.add_file(&dir_entry, role.clone());
+            }
+        }
+    }
+
+    fn add_label(&mut self, scope: &str, label: &Label) {
+        match label.kernel_or_config {
+            KernelOrConfig::Kernel(ref kernel) => {
+                if let Some(ref kernel_file) = kernel.kernel_file {
+                    self.add_file(kernel_file.path(),
+                                  format!("{}: kernel", scope));
+                }
+                if let Some(ref initrd) = kernel.initrd {
+                    self.add_file(initrd, format!("{}: initrd", scope));
+                }
+                if let Some(ref fdt) = kernel.fdt {
+                    self.add_file(fdt, format!("{}: fdt", scope));
+                }
+                if let Some(ref fdt_dir) = kernel.fdt_dir {
+                    self.add_dir(fdt_dir, format!("{}: fdt", scope));
+                }
+                for fdt_overlay in &kernel.fdt_overlays {
+                    self.add_file(fdt_overlay,
+                                  format!("{}: fdt overlay", scope));
+                }
+            },
+        };
+
+        if let Some(ref display) = label.display {
+            self.add_file(display, format!("{}: display", scope));
+        }
+    }
+}
+
+// Paths in configuration should be already resolved.
+pub fn build_manifest(conf: &SyslinuxConf,
+                      root_dir: &std::path::Path,
+                      conf_files: &[std::path::PathBuf]) -> Manifest {
+    let mut builder = ManifestBuilder{
+        root_dir: root_dir,
+        entries: std::collections::BTreeMap::new(),
+    };
+
+    for conf_file in conf_files {
+        builder.add_file(conf_file, String::from("config"));
+    }
+
+    builder.add_label("global", &conf.global.label_defaults);
+    for (label_name, label) in conf.labels.iter() {
+        builder.add_label(&format!("label {}", label_name), label);
+    }
+
+    Manifest{
+        entries: builder.entries.into_iter().map(|(_, entry)| entry).collect(),
+    }
+}
+
+impl Manifest {
+    // Same format as of sha256sum(1), sorted by path.
+    pub fn to_text(&self) -> String {
+        self.entries.iter().fold(String::new(), |mut text, entry| {
+            let sha256 = match entry.sha256 {
+                Some(ref sha256) => sha256.clone(),
+                None => String::from("MISSING"),
+            };
+            text.push_str(&format!("{}  {}\n", sha256, entry.path.display()));
+            text
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std;
+
+    use super::build_manifest;
+    use test_util::TempDir;
+    use types::{Global, Kernel, KernelFile, KernelOrConfig, Label, Labels,
+                SyslinuxConf};
+
+    fn conf(kernel: Kernel) -> SyslinuxConf {
+        let mut labels = Labels::new();
+        labels.insert(String::from("linux"), Label{
+            kernel_or_config: KernelOrConfig::Kernel(kernel),
+            ..Label::default()
+        });
+        SyslinuxConf{
+            global: Global::default(),
+            labels: labels,
+        }
+    }
+
+    fn paths(conf: &SyslinuxConf, root_dir: &std::path::Path,
+             conf_files: &[std::path::PathBuf]) -> Vec<String> {
+        build_manifest(conf, root_dir, conf_files).entries.iter()
+            .map(|entry| format!("{}", entry.path.display()))
+            .collect()
+    }
+
+    #[test]
+    fn hashes_files_relative_to_root() {
+        let dir = TempDir::new();
+        let conf_file = dir.write("boot/syslinux.cfg", b"");
+        let kernel = dir.write("boot/vmlinuz", b"abc");
+
+        let manifest = build_manifest(
+            &conf(Kernel{
+                kernel_file: Some(KernelFile::Linux(kernel)),
+                initrd: Some(dir.path().join("boot/missing.img")),
+                ..Kernel::default()
+            }),
+            dir.path(), &[conf_file]);
+
+        let sha256: Vec<Option<String>> = manifest.entries.iter()
+            .map(|entry| entry.sha256.clone())
+            .collect();
+        assert_eq!(sha256, vec![
+            None,
+            Some(String::from("e3b0c44298fc1c149afbf4c8996fb924\
+                               27ae41e4649b934ca495991b7852b855")),
+            Some(String::from("ba7816bf8f01cfea414140de5dae2223\
+                               b00361a396177a9cb410ff61f20015ad")),
+        ]);
+        assert!(manifest.to_text().starts_with(
+            "MISSING  /boot/missing.img\ne3b0c442"));
+        assert_eq!(manifest.entries[2].size, Some(3));
+        assert_eq!(manifest.entries[2].roles,
+                   vec![String::from("label linux: kernel")]);
+        assert!(manifest.entries[0].error.is_some());
+    }
+
+    #[test]
+    fn uses_relative_path_outside_of_root() {
+        let dir = TempDir::new();
+        let root_dir = dir.path().join("root");
+        std::fs::create_dir(&root_dir).unwrap();
+        let kernel = dir.write("other/vmlinuz", b"");
+
+        let conf = conf(Kernel{
+            kernel_file: Some(KernelFile::Linux(kernel)),
+            ..Kernel::default()
+        });
+        assert_eq!(paths(&conf, &root_dir, &[]), vec!["../other/vmlinuz"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn does_not_follow_directory_symlinks() {
+        let dir = TempDir::new();
+        dir.write("dtbs/board.dtb", b"");
+        dir.write("dtbs/vendor/other.dtb", b"");
+        let target = dir.write("target.dtb", b"");
+        std::os::unix::fs::symlink(dir.path().join("dtbs"),
+                                   dir.path().join("dtbs/loop")).unwrap();
+        std::os::unix::fs::symlink(&target,
+                                   dir.path().join("dtbs/link.dtb")).unwrap();
+
+        let conf = conf(Kernel{
+            fdt_dir: Some(dir.path().join("dtbs")),
+            ..Kernel::default()
+        });
+        assert_eq!(paths(&conf, dir.path(), &[]),
+                   vec!["/dtbs/board.dtb", "/dtbs/link.dtb",
+                        "/dtbs/vendor/other.dtb"]);
+    }
+}
@@ -0,0 +1,441 @@
+use std;
+
+use diff::{diff_label, directives_value, kernel_file_value, path_value,
+           prompt_value, timeout_value};
+use types::{Diagnostic, Global, Kernel, KernelOrConfig, Label, Labels,
+            MergeConflict, MergeResult, Severity, SyslinuxConf};
+
+// Merged configuration is meant to be written in syslinux syntax, so
+// inspection results are dropped.
+
+fn same_label(a: &Label, b: &Label) -> bool {
+    diff_label(a, b).is_none()
+}
+
+fn string_value(value: &Option<String>) -> Option<String> {
+    value.clone()
+}
+
+fn paths_value(paths: &Vec<std::path::PathBuf>) -> Option<String> {
+    match paths.is_empty() {
+        true => None,
+        false => Some(paths.iter()
+            .map(|path| String::from(path.to_string_lossy()))
+            .collect::<Vec<String>>()
+            .join(" ")),
+    }
+}
+
+fn order_value(labels: &[String]) -> Option<String> {
+    Some(labels.join(" "))
+}
+
+// Labels of side which are also in other, in order of side.
+fn common<'a>(side: &'a Labels, other: &Labels) -> Vec<&'a str> {
+    side.keys()
+        .filter(|name| other.contains_key(*name))
+        .map(|name| name.as_str())
+        .collect()
+}
+
+struct Merger {
+    conflicts: Vec<MergeConflict>,
+}
+
+impl Merger {
+    fn conflict(&mut self, label_name: Option<&str>, field: &str,
+                base: Option<String>, ours: Option<String>,
+                theirs: Option<String>) {
+        self.conflicts.push(MergeConflict{
+            label: label_name.map(String::from),
+            field: String::from(field),
+            base: base,
+            ours: ours,
+            theirs: theirs,
+        });
+    }
+
+    // Change of one side wins, changes of both sides must be the same.
+    fn field<T, F>(&mut self, label_name: Option<&str>, field: &str, base: &T,
+                   ours: &T, theirs: &T, value: F) -> T
+        where T: Clone + PartialEq,
+              F: Fn(&T) -> Option<String>
+    {
+        if ours == theirs || theirs == base {
+            ours.clone()
+        } else if ours == base {
+            theirs.clone()
+        } else {
+            self.conflict(label_name, field, value(base), value(ours),
+                          value(theirs));
+            ours.clone()
+        }
+    }
+
+    fn kernel(&mut self, label_name: Option<&str>, base: &Kernel,
+              ours: &Kernel, theirs: &Kernel) -> Kernel {
+        // Kernel file and the way its type is specified come together.
+        let (kernel_file, kernel_file_source) = self.field(
+            label_name, "kernel_file",
+            &(base.kernel_file.clone(), base.kernel_file_source),
+            &(ours.kernel_file.clone(), ours.kernel_file_source),
+            &(theirs.kernel_file.clone(), theirs.kernel_file_source),
+            |value| kernel_file_value(&value.0, value.1));
+
+        Kernel{
+            kernel_file: kernel_file,
+            kernel_file_source: kernel_file_source,
+            initrd: self.field(label_name, "initrd", &base.initrd,
+                               &ours.initrd, &theirs.initrd, path_value),
+            fdt_dir: self.field(label_name, "fdt_dir", &base.fdt_dir,
+                                &ours.fdt_dir, &theirs.fdt_dir, path_value),
+            append: self.field(label_name, "append", &base.append,
+                               &ours.append, &theirs.append, string_value),
+            fdt: self.field(label_name, "fdt", &base.fdt, &ours.fdt,
+                            &theirs.fdt, path_value),
+            fdt_overlays: self.field(label_name, "fdt_overlays",
+                                     &base.fdt_overlays, &ours.fdt_overlays,
+                                     &theirs.fdt_overlays, paths_value),
+            linux_info: None,
+            initrd_info: None,
+        }
+    }
+
+    fn label(&mut self, label_name: Option<&str>, base: &Label, ours: &Label,
+             theirs: &Label) -> Label {
+        Label{
+            text_help: self.field(label_name, "text_help", &base.text_help,
+                                  &ours.text_help, &theirs.text_help,
+                                  string_value),
+            kernel_or_config: KernelOrConfig::Kernel(
+                self.kernel(label_name, base.kernel(), ours.kernel(),
+                            theirs.kernel())),
+            say: self.field(label_name, "say", &base.say, &ours.say,
+                            &theirs.say, string_value),
+            display: self.field(label_name, "display", &base.display,
+                                &ours.display, &theirs.display, path_value),
+            unknown_directives: self.field(label_name, "unknown_directives",
+                                           &base.unknown_directives,
+                                           &ours.unknown_directives,
+                                           &theirs.unknown_directives,
+                                           directives_value),
+        }
+    }
+
+    fn global(&mut self, base: &Global, ours: &Global, theirs: &Global)
+              -> Global {
+        Global{
+            default: self.field(None, "default", &base.default, &ours.default,
+                                &theirs.default, string_value),
+            ontimeout: self.field(None, "ontimeout", &base.ontimeout,
+                                  &ours.ontimeout, &theirs.ontimeout,
+                                  string_value),
+            onerror: self.field(None, "onerror", &base.onerror,
+                                &ours.onerror, &theirs.onerror,
+                                string_value),
+            timeout: self.field(None, "timeout", &base.timeout, &ours.timeout,
+                                &theirs.timeout, |t| timeout_value(*t)),
+            total_timeout: self.field(None, "total_timeout",
+                                      &base.total_timeout,
+                                      &ours.total_timeout,
+                                      &theirs.total_timeout,
+                                      |t| timeout_value(*t)),
+            menu_title: self.field(None, "menu_title", &base.menu_title,
+                                   &ours.menu_title, &theirs.menu_title,
+                                   string_value),
+            prompt: self.field(None, "prompt", &base.prompt, &ours.prompt,
+                               &theirs.prompt, |p| prompt_value(*p)),
+            ui: self.field(None, "ui", &base.ui, &ours.ui, &theirs.ui,
+                           string_value),
+            label_defaults: self.label(None, &base.label_defaults,
+                                       &ours.label_defaults,
+                                       &theirs.label_defaults),
+            unknown_directives: self.field(None, "unknown_directives",
+                                           &base.unknown_directives,
+                                           &ours.unknown_directives,
+                                           &theirs.unknown_directives,
+                                           directives_value),
+        }
+    }
+
+    // Label removed by one side and changed by the other is a conflict.
+    fn removed_label(&mut self, label_name: &str, base: &Label,
+                     kept: &Label, removed_by_theirs: bool) -> Option<Label> {
+        if same_label(base, kept) {
+            return None;
+        }
+
+        let (ours, theirs) = match removed_by_theirs {
+            true => (Some("changed"), None),
+            false => (None, Some("changed")),
+        };
+        self.conflict(Some(label_name), "label", Some(String::from("present")),
+                      ours.map(String::from), theirs.map(String::from));
+        match removed_by_theirs {
+            true => Some(kept.clone()),
+            false => None,
+        }
+    }
+
+    fn labels(&mut self, base: &Labels, ours: &Labels, theirs: &Labels)
+              -> Labels {
+        let mut names: Vec<&String> = Vec::new();
+        for name in ours.keys().chain(theirs.keys()).chain(base.keys()) {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+
+        let empty = Label::default();
+        let mut merged = Labels::new();
+        for name in names {
+            let label = match (base.get(name), ours.get(name),
+                               theirs.get(name)) {
+                // Labels added by both sides are merged field by field.
+                (base_label, Some(ours_label), Some(theirs_label)) => {
+                    Some(self.label(Some(name.as_str()),
+                                    base_label.unwrap_or(&empty), ours_label,
+                                    theirs_label))
+                },
+                (None, Some(label), None) | (None, None, Some(label)) => {
+                    Some(label.clone())
+                },
+                (Some(base_label), Some(ours_label), None) => {
+                    self.removed_label(name, base_label, ours_label, true)
+                },
+                (Some(base_label), None, Some(theirs_label)) => {
+                    self.removed_label(name, base_label, theirs_label, false)
+                },
+                _ => None,
+            };
+            if let Some(label) = label {
+                merged.insert(name.clone(), label);
+            }
+        }
+
+        self.order(base, ours, theirs, merged)
+    }
+
+    // Order of the side which reordered labels is kept, labels added by the
+    // other side follow the same labels as there.
+    fn order(&mut self, base: &Labels, ours: &Labels, theirs: &Labels,
+             mut merged: Labels) -> Labels {
+        let ours_reordered = common(ours, base) != common(base, ours);
+        let theirs_reordered = common(theirs, base) != common(base, theirs);
+
+        if ours_reordered && theirs_reordered {
+            let in_all = |side: &Labels, other: &Labels| -> Vec<String> {
+                common(side, other).into_iter()
+                    .filter(|name| base.contains_key(*name))
+                    .map(String::from)
+                    .collect()
+            };
+            let ours_order = in_all(ours, theirs);
+            let theirs_order = in_all(theirs, ours);
+            if ours_order != theirs_order {
+                let base_order: Vec<String> = base.keys()
+                    .filter(|name| ours_order.contains(*name))
+                    .cloned()
+                    .collect();
+                self.conflict(None, "order", order_value(&base_order),
+                              order_value(&ours_order),
+                              order_value(&theirs_order));
+            }
+        }
+
+        let (backbone, other) = match (ours_reordered, theirs_reordered) {
+            (false, true) => (theirs, ours),
+            _ => (ours, theirs),
+        };
+
+        let mut order: Vec<String> = backbone.keys()
+            .filter(|name| merged.contains_key(*name))
+            .cloned()
+            .collect();
+        let mut previous: Option<usize> = None;
+        for name in other.keys() {
+            if !merged.contains_key(name) {
+                continue;
+            }
+            previous = match order.iter().position(|n| n == name) {
+                Some(index) => Some(index),
+                None => {
+                    let index = previous.map_or(0, |index| index + 1);
+                    order.insert(index, name.clone());
+                    Some(index)
+                },
+            };
+        }
+
+        let mut labels = Labels::new();
+        for name in order {
+            if let Some(label) = merged.remove(&name) {
+                labels.insert(name, label);
+            }
+        }
+        labels
+    }
+}
+
+// Three-way merge of configurations changed independently from base, e.g.
+// by administrator and by package hooks.
+pub fn merge(base: &SyslinuxConf, ours: &SyslinuxConf, theirs: &SyslinuxConf)
+             -> MergeResult {
+    let mut merger = Merger{
+        conflicts: Vec::new(),
+    };
+
+    let conf = SyslinuxConf{
+        global: merger.global(&base.global, &ours.global, &theirs.global),
+        labels: merger.labels(&base.labels, &ours.labels, &theirs.labels),
+    };
+
+    MergeResult{
+        conf: conf,
+        conflicts: merger.conflicts,
+    }
+}
+
+impl MergeConflict {
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        let value = |value: &Option<String>| match *value {
+            Some(ref value) => format!("{:?}", value),
+            None => String::from("none"),
+        };
+        let diagnostic = Diagnostic::new(
+            Severity::Error, "merge-conflict",
+            format!("{} changed on both sides: base {}, ours {}, theirs {}",
+                    self.field, value(&self.base), value(&self.ours),
+                    value(&self.theirs)));
+        match self.label {
+            Some(ref label_name) => diagnostic.for_label(label_name),
+            None => diagnostic,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::merge;
+    use test_util::parse;
+    use types::MergeResult;
+
+    const BASE: &'static str = "DEFAULT linux\n\
+                                TIMEOUT 50\n\
+                                LABEL linux\n\
+                                \x20 KERNEL vmlinuz\n\
+                                \x20 APPEND root=/dev/sda1\n\
+                                LABEL rescue\n\
+                                \x20 KERNEL vmlinuz\n\
+                                LABEL memtest\n\
+                                \x20 LINUX memtest.bin\n";
+
+    fn merge_text(ours: &str, theirs: &str) -> MergeResult {
+        merge(&parse(BASE), &parse(ours), &parse(theirs))
+    }
+
+    fn label_names(result: &MergeResult) -> Vec<&str> {
+        result.conf.labels.keys().map(|name| name.as_str()).collect()
+    }
+
+    #[test]
+    fn takes_changes_of_both_sides() {
+        let ours = BASE.replace("TIMEOUT 50", "TIMEOUT 100");
+        let theirs = BASE.replace("root=/dev/sda1", "root=/dev/sda2");
+        let result = merge_text(&ours, &theirs);
+
+        assert!(result.conflicts.is_empty());
+        assert_eq!(result.conf.global.timeout, Some(10.0));
+        assert_eq!(result.conf.labels["linux"].kernel().append,
+                   Some(String::from("root=/dev/sda2")));
+    }
+
+    #[test]
+    fn reports_conflicts_in_favour_of_ours() {
+        let ours = BASE.replace("root=/dev/sda1", "root=/dev/sda2");
+        let theirs = BASE.replace("root=/dev/sda1", "root=/dev/sda3");
+        let result = merge_text(&ours, &theirs);
+
+        assert_eq!(result.conflicts.len(), 1);
+        let conflict = &result.conflicts[0];
+        assert_eq!(conflict.label, Some(String::from("linux")));
+        assert_eq!(conflict.field, "append");
+        assert_eq!(conflict.base, Some(String::from("root=/dev/sda1")));
+        assert_eq!(conflict.theirs, Some(String::from("root=/dev/sda3")));
+        assert_eq!(result.conf.labels["linux"].kernel().append,
+                   Some(String::from("root=/dev/sda2")));
+        assert_eq!(conflict.to_diagnostic().code, "merge-conflict");
+    }
+
+    #[test]
+    fn adds_and_removes_labels() {
+        let ours = format!("{}LABEL new\n  KERNEL vmlinuz.new\n", BASE);
+        let theirs = BASE.replace("LABEL memtest\n  LINUX memtest.bin\n", "");
+        let result = merge_text(&ours, &theirs);
+
+        assert!(result.conflicts.is_empty());
+        assert_eq!(label_names(&result), vec!["linux", "rescue", "new"]);
+    }
+
+    #[test]
+    fn reports_removed_and_changed_label() {
+        let ours = BASE.replace("LINUX memtest.bin", "LINUX memtest86.bin");
+        let theirs = BASE.replace("LABEL memtest\n  LINUX memtest.bin\n", "");
+        let result = merge_text(&ours, &theirs);
+
+        assert_eq!(result.conflicts.len(), 1);
+        assert_eq!(result.conflicts[0].field, "label");
+        assert_eq!(result.conflicts[0].ours, Some(String::from("changed")));
+        assert!(result.conf.labels.contains_key("memtest"));
+    }
+
+    #[test]
+    fn keeps_order_of_reordering_side() {
+        let theirs = "DEFAULT linux\n\
+                      TIMEOUT 50\n\
+                      LABEL memtest\n\
+                      \x20 LINUX memtest.bin\n\
+                      LABEL linux\n\
+                      \x20 KERNEL vmlinuz\n\
+                      \x20 APPEND root=/dev/sda1\n\
+                      LABEL rescue\n\
+                      \x20 KERNEL vmlinuz\n";
+        let ours = BASE.replace("LABEL rescue\n",
+                                "LABEL new\n  KERNEL vmlinuz.new\n\
+                                 LABEL rescue\n");
+        let result = merge_text(&ours, theirs);
+
+        assert!(result.conflicts.is_empty());
+        assert_eq!(label_names(&result),
+                   vec!["memtest", "linux", "new", "rescue"]);
+    }
+
+    #[test]
+    fn reports_conflicting_orders() {
+        let ours = "DEFAULT linux\n\
+                    TIMEOUT 50\n\
+                    LABEL rescue\n\
+                    \x20 KERNEL vmlinuz\n\
+                    LABEL linux\n\
+                    \x20 KERNEL vmlinuz\n\
+                    \x20 APPEND root=/dev/sda1\n\
+                    LABEL memtest\n\
+                    \x20 LINUX memtest.bin\n";
+        let theirs = "DEFAULT linux\n\
+                      TIMEOUT 50\n\
+                      LABEL memtest\n\
+                      \x20 LINUX memtest.bin\n\
+                      LABEL linux\n\
+                      \x20 KERNEL vmlinuz\n\
+                      \x20 APPEND root=/dev/sda1\n\
+                      LABEL rescue\n\
+                      \x20 KERNEL vmlinuz\n";
+        let result = merge_text(ours, theirs);
+
+        assert_eq!(result.conflicts.len(), 1);
+        assert_eq!(result.conflicts[0].field, "order");
+        assert_eq!(result.conflicts[0].base,
+                   Some(String::from("linux rescue memtest")));
+        assert_eq!(label_names(&result), vec!["rescue", "linux", "memtest"]);
+    }
+}
@@ -2,11 +2,177 @@ use std;
 
 extern crate nom;
 
-use types::{KernelFile, KernelOrConfig, Label, Global, Labels, SyslinuxConf};
+use lexer;
+use lexer::Encoding;
+use types::{Diagnostic, KernelFile, KernelFileSource, KernelOrConfig, Label,
+            Global, Labels, Location, Severity, SyslinuxConf,
+            UnknownDirective};
 
 // TODO: Support INCLUDE tag.
 // TODO: Support CONFIG tags.
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dialect {
+    Syslinux,
+    // extlinux.conf as read by "pxe" and "sysboot" commands of U-Boot.
+    UBoot,
+}
+
+// What to do with labels with the same name as one of the previous labels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DuplicateLabels {
+    // Like syslinux.
+    FirstWins,
+    LastWins,
+    // Parsing stops at the duplicate.
+    Error,
+}
+
+#[derive(Debug, Clone)]
+pub struct ParserOptions {
+    pub dialect: Dialect,
+    pub duplicate_labels: DuplicateLabels,
+    // Unknown tags, tags in wrong scope and invalid values are reported as
+    // errors instead of being skipped quietly.
+    pub strict: bool,
+    pub encoding: Encoding,
+}
+
+impl Default for ParserOptions {
+    fn default() -> ParserOptions {
+        ParserOptions{
+            dialect: Dialect::Syslinux,
+            duplicate_labels: DuplicateLabels::FirstWins,
+            strict: false,
+            encoding: Encoding::UTF8,
+        }
+    }
+}
+
+// Tags which are valid only in global scope.
+const GLOBAL_TAGS: [&'static str; 8] = [
+    "DEFAULT", "ONTIMEOUT", "ONERROR", "TIMEOUT", "TOTALTIMEOUT", "PROMPT",
+    "UI", "MENU TITLE"];
+
+const NUMERIC_TAGS: [&'static str; 3] = ["TIMEOUT", "TOTALTIMEOUT", "PROMPT"];
+
+const LABEL_TAGS: [&'static str; 14] = [
+    "KERNEL", "LINUX", "BOOT", "BSS", "PXE", "FDIMAGE", "COMBOOT", "COM32",
+    "INITRD", "FDTDIR", "APPEND", "SAY", "DISPLAY", "TEXT HELP"];
+
+const UBOOT_LABEL_TAGS: [&'static str; 4] = [
+    "FDT", "DEVICETREE", "DEVICETREEDIR", "FDTOVERLAYS"];
+
+fn unknown_directive(text: &str, line: usize) -> UnknownDirective {
+    let (keyword, argument) = match text.find(char::is_whitespace) {
+        Some(index) => (&text[..index], text[index..].trim()),
+        None => (text, ""),
+    };
+    UnknownDirective{
+        keyword: String::from(keyword),
+        argument: String::from(argument),
+        line: line,
+    }
+}
+
+// State of parsing of one input.
+struct Context<'a> {
+    input: &'a [u8],
+    // Line input starts at.
+    first_line: usize,
+    // Offsets of lines of input, so that lines are not counted again for
+    // every tag of large configuration.
+    line_starts: Vec<usize>,
+    options: &'a ParserOptions,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Context<'a> {
+    // Line of byte at offset.
+    fn line_of(&self, offset: usize) -> usize {
+        let index = match self.line_starts.binary_search(&offset) {
+            Ok(index) => index,
+            Err(index) => index - 1,
+        };
+        index + self.first_line
+    }
+
+    // Line of input at which rest starts.
+    fn line_at(&self, rest: &[u8]) -> usize {
+        self.line_of(self.input.len() - rest.len())
+    }
+
+    // Lines from start of rest up to start of next.
+    fn span(&self, rest: &[u8], next: &[u8]) -> Location {
+        let start = self.input.len() - rest.len();
+        let end = self.input.len() - next.len();
+        match end > start {
+            true => Location::lines(self.line_of(start), self.line_of(end - 1)),
+            false => Location::line(self.line_of(start)),
+        }
+    }
+
+    fn report(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+    }
+
+    // Like syslinux, line which can not be parsed at all is skipped.
+    fn skip_invalid_line<'b>(&mut self, rest: &'b [u8]) -> &'b [u8] {
+        let end = rest.iter()
+            .position(|c| *c == b'\n' || *c == b'\r')
+            .unwrap_or(rest.len());
+        let text = String::from_utf8_lossy(&rest[..end]).trim().to_string();
+        let severity = match self.options.strict {
+            true => Severity::Error,
+            false => Severity::Warning,
+        };
+        let line = self.line_at(rest);
+        warn!("Unable to parse line {}, skipping it", line);
+        self.report(Diagnostic::new(
+            severity, "invalid-line",
+            format!("Unable to parse line \"{}\", it is skipped", text))
+            .at(Location::line(line)));
+
+        match end < rest.len() {
+            true => &rest[end + 1..],
+            false => &rest[end..],
+        }
+    }
+
+    // Line which was not parsed as a tag valid in its scope. Returns whether
+    // it is kept as unknown directive, invalid value of known tag is not
+    // written back.
+    fn report_unknown(&mut self, text: &str, line: usize, in_label: bool)
+                      -> bool {
+        let keyword = lexer::keyword(text);
+        let known = |tags: &[&str]| tags.contains(&keyword.as_str());
+        let uboot = self.options.dialect == Dialect::UBoot;
+        let label_tag = known(&LABEL_TAGS) ||
+            (uboot && known(&UBOOT_LABEL_TAGS));
+
+        let (code, message) = if in_label && known(&GLOBAL_TAGS) {
+            ("wrong-scope", format!("{} is valid only in global scope",
+                                    keyword))
+        } else if known(&NUMERIC_TAGS) {
+            ("invalid-number", format!("Invalid number in \"{}\"", text))
+        } else if label_tag || known(&GLOBAL_TAGS) {
+            ("invalid-value", format!("Invalid value in \"{}\"", text))
+        } else {
+            ("unknown-tag", format!("Unknown or unsupported tag: \"{}\"",
+                                    text))
+        };
+
+        let severity = match (self.options.strict, code) {
+            (true, _) => Severity::Error,
+            (false, "unknown-tag") => Severity::Info,
+            (false, _) => Severity::Warning,
+        };
+        self.report(Diagnostic::new(severity, code, message)
+                        .at(Location::line(line)));
+        code == "unknown-tag" || code == "wrong-scope"
+    }
+}
+
 enum Error {
     FromUTF8Failed,
     InvalidTag,
@@ -95,6 +261,14 @@ named!(
         get_line,
         |s: String| std::path::PathBuf::from(s)));
 
+named!(
+    get_paths(&[u8]) -> Vec<std::path::PathBuf>,
+    map!(
+        get_line,
+        |s: String| s.split_whitespace()
+            .map(|path| std::path::PathBuf::from(path))
+            .collect()));
+
 named!(
     skip_empty_line(&[u8]) -> (),
     chain!(
@@ -130,13 +304,14 @@ macro_rules! named_tag_ci {
 }
 
 trait LineParser<FieldType> {
-    fn parse_line(&[u8]) -> nom::IResult<&[u8], Option<Option<FieldType>>>;
+    fn parse_line<'a>(input: &'a [u8], options: &ParserOptions)
+                      -> nom::IResult<&'a [u8], Option<Option<FieldType>>>;
 
-    fn get_value<'a>(input: &'a [u8])
+    fn get_value<'a>(input: &'a [u8], options: &ParserOptions)
                      -> nom::IResult<&'a [u8], Option<FieldType>>
         where FieldType: LineParser<FieldType>
     {
-        match FieldType::parse_line(input) {
+        match FieldType::parse_line(input, options) {
             // Next LABEL found => stop parsing.
             nom::IResult::Done(i, None) => {
                 nom::IResult::Error(nom::Err::Position(
@@ -154,25 +329,34 @@ trait LineParser<FieldType> {
 }
 
 trait StructBuilder<FieldType, StructType> {
-    fn build(mut self, f: FieldType) -> StructType;
+    fn build(mut self, f: FieldType, line: usize, context: &mut Context)
+             -> StructType;
 
-    fn parse(input: &[u8]) -> nom::IResult<&[u8], StructType>
+    fn parse<'a>(input: &'a [u8], context: &mut Context)
+                 -> nom::IResult<&'a [u8], StructType>
         where FieldType: LineParser<FieldType>,
               StructType: Default + StructBuilder<FieldType, StructType>
     {
-
-        let build_or_skip = |s, f| {
-            match f {
-                Some(v) => StructType::build(s, v),
-                // Empty line or comment line or unknown/unsupported tag.
-                None => s,
-            }
-        };
-
-        fold_many0!(input,
-                    FieldType::get_value,
-                    StructType::default(),
-                    build_or_skip)
+        let mut rest = input;
+        let mut result = StructType::default();
+        while !rest.is_empty() {
+            match FieldType::get_value(rest, context.options) {
+                nom::IResult::Done(next, value) if next.len() < rest.len() => {
+                    // Empty line or comment line is skipped.
+                    if let Some(field) = value {
+                        let line = context.line_at(rest);
+                        result = StructType::build(result, field, line,
+                                                   context);
+                    }
+                    rest = next;
+                },
+                nom::IResult::Error(nom::Err::Position(
+                        nom::ErrorKind::Custom(kind), _))
+                        if kind == Error::NextLabelFound as u32 => break,
+                _ => rest = context.skip_invalid_line(rest),
+            };
+        }
+        nom::IResult::Done(rest, result)
     }
 }
 
@@ -190,7 +374,7 @@ fn get_extension(file_path: &std::path::PathBuf) -> &str {
 }
 
 impl KernelFile {
-    fn guess(kernel_path: std::path::PathBuf) -> KernelFile {
+    pub fn guess(kernel_path: std::path::PathBuf) -> KernelFile {
         match get_extension(&kernel_path) {
             "bin" | "bs"  => KernelFile::Boot(kernel_path),
             "bss"         => KernelFile::BSS(kernel_path),
@@ -205,9 +389,11 @@ impl KernelFile {
 
 #[derive(Debug)]
 enum LabelKernelOrConfigField {
-    KernelFile(KernelFile),
+    KernelFile(KernelFile, KernelFileSource),
     InitRD(std::path::PathBuf),
+    FDT(std::path::PathBuf),
     FDTDir(std::path::PathBuf),
+    FDTOverlays(Vec<std::path::PathBuf>),
     Append(String),
 }
 
@@ -217,6 +403,7 @@ enum LabelField {
     KernelOrConfig(LabelKernelOrConfigField),
     Say(String),
     Display(std::path::PathBuf),
+    Unknown(String),
 }
 
 macro_rules! catch_label_field {
@@ -236,7 +423,8 @@ macro_rules! catch_kernel_file_type {
         |v| some2!(
                 LabelField::KernelOrConfig(
                     LabelKernelOrConfigField::KernelFile(
-                        KernelFile::$kernel_file_type(v))))
+                        KernelFile::$kernel_file_type(v),
+                        KernelFileSource::Tag)))
     }
 }
 
@@ -257,6 +445,29 @@ named_tag_ci!(get_tag_fdtdir,  "FDTDIR",  std::path::PathBuf, get_path);
 named_tag_ci!(get_tag_append,  "APPEND",  String,             get_line);
 named_tag_ci!(get_tag_say,     "SAY",     String,             get_line);
 named_tag_ci!(get_tag_display, "DISPLAY", std::path::PathBuf, get_path);
+
+// Tags valid in LABEL scope of U-Boot configuration.
+named_tag_ci!(get_tag_fdt,        "FDT",        std::path::PathBuf, get_path);
+named_tag_ci!(get_tag_devicetree, "DEVICETREE", std::path::PathBuf, get_path);
+named_tag_ci!(get_tag_devicetreedir, "DEVICETREEDIR",
+              std::path::PathBuf, get_path);
+named_tag_ci!(get_tag_fdtoverlays, "FDTOVERLAYS",
+              Vec<std::path::PathBuf>, get_paths);
+// Lexer ends every line with "\n", empty lines of text included.
+named!(
+    get_text_line(&[u8]) -> String,
+    alt_complete!(
+        map_res!(
+            get_line,
+            |line: String| {
+                use std::ascii::AsciiExt;
+                if line.to_ascii_uppercase() == "ENDTEXT" {
+                    Err(())
+                } else {
+                    Ok(line)
+                }}) |
+        tag!("\n") => { |_| String::new() }));
+
 named!(
     get_tag_text_help(&[u8]) -> String,
     chain!(
@@ -266,21 +477,14 @@ named!(
         skip_spaces0 ~
         skip_line_end ~
         text: fold_many0!(
-            map_res!(
-                get_line,
-                |line: String| {
-                    use std::ascii::AsciiExt;
-                    if line.to_ascii_uppercase() == "ENDTEXT" {
-                        Err(())
-                    } else {
-                        Ok(line)
-                    }}),
+            get_text_line,
             String::new(),
             |acc: String, item: String| {
                 use std::ops::Add;
-                match acc.is_empty() {
-                    true => acc.add(&item),
-                    false => acc.add(" ").add(&item),
+                match (acc.is_empty(), item.is_empty()) {
+                    (_, true) => acc,
+                    (true, false) => acc.add(&item),
+                    (false, false) => acc.add(" ").add(&item),
                 }
             }) ~
         call!(skip_tag_ci, "ENDTEXT"),
@@ -313,47 +517,85 @@ impl LabelField {
                 |v| some2!(
                         LabelField::KernelOrConfig(
                             LabelKernelOrConfigField::KernelFile(
-                                KernelFile::guess(v))))
+                                KernelFile::guess(v),
+                                KernelFileSource::Extension)))
             }));
+
+    // U-Boot always boots KERNEL as Linux kernel (or zImage/Image), no matter
+    // of its extension.
+    named!(
+        parse_uboot_tag(&[u8]) -> Option<Option<LabelField> >,
+        alt_complete!(
+            get_tag_fdt           => {     catch_kernel_field!(FDT)         } |
+            get_tag_devicetree    => {     catch_kernel_field!(FDT)         } |
+            get_tag_devicetreedir => {     catch_kernel_field!(FDTDir)      } |
+            get_tag_fdtoverlays   => {     catch_kernel_field!(FDTOverlays) } |
+            get_tag_kernel        => { catch_kernel_file_type!(Linux)       }));
+
+    fn parse_dialect_tag<'a>(input: &'a [u8], options: &ParserOptions)
+                             -> nom::IResult<&'a [u8],
+                                             Option<Option<LabelField>>> {
+        match options.dialect {
+            Dialect::Syslinux => LabelField::parse_tag(input),
+            Dialect::UBoot => alt_complete!(
+                input,
+                call!(LabelField::parse_uboot_tag) |
+                call!(LabelField::parse_tag)),
+        }
+    }
 }
 
 impl LineParser<LabelField> for LabelField {
-    named!(
-        parse_line(&[u8]) -> Option<Option<LabelField> >,
+    fn parse_line<'a>(input: &'a [u8], options: &ParserOptions)
+                      -> nom::IResult<&'a [u8], Option<Option<LabelField>>> {
         alt!(
+            input,
             // Try to parse known tags.
-            call!(LabelField::parse_tag) |
+            call!(LabelField::parse_dialect_tag, options) |
             // No known tags => unknown/unsupported tag.
             get_line => { |line| {
                 debug!("Unknown or unsupported tag: \"{}\"", line);
-                Some(None)
-            }}));
+                some2!(LabelField::Unknown(line))
+            }})
+    }
 }
 
 impl Label {
     fn build_kernel(&mut self, field: LabelKernelOrConfigField) {
         match self.kernel_or_config {
             KernelOrConfig::Kernel(ref mut k) => match field {
-                LabelKernelOrConfigField::KernelFile(v) =>
-                    k.kernel_file = Some(v),
-                LabelKernelOrConfigField::InitRD(v)     =>
-                    k.initrd      = Some(v),
-                LabelKernelOrConfigField::FDTDir(v)     =>
-                    k.fdt_dir     = Some(v),
-                LabelKernelOrConfigField::Append(v)     =>
-                    k.append      = Some(v),
+                LabelKernelOrConfigField::KernelFile(v, source) => {
+                    k.kernel_file        = Some(v);
+                    k.kernel_file_source = Some(source);
+                },
+                LabelKernelOrConfigField::InitRD(v)      =>
+                    k.initrd       = Some(v),
+                LabelKernelOrConfigField::FDT(v)         =>
+                    k.fdt          = Some(v),
+                LabelKernelOrConfigField::FDTDir(v)      =>
+                    k.fdt_dir      = Some(v),
+                LabelKernelOrConfigField::FDTOverlays(v) =>
+                    k.fdt_overlays = v,
+                LabelKernelOrConfigField::Append(v)      =>
+                    k.append       = Some(v),
             },
         }
     }
 }
 
 impl StructBuilder<LabelField, Label> for Label {
-    fn build(mut self, field: LabelField) -> Label {
+    fn build(mut self, field: LabelField, line: usize, context: &mut Context)
+             -> Label {
         match field {
             LabelField::TextHelp(v)       => self.text_help = Some(v),
             LabelField::Say(v)            => self.say       = Some(v),
             LabelField::Display(v)        => self.display   = Some(v),
             LabelField::KernelOrConfig(v) => self.build_kernel(v),
+            LabelField::Unknown(v)        => {
+                if context.report_unknown(&v, line, true) {
+                    self.unknown_directives.push(unknown_directive(&v, line));
+                }
+            },
         };
         self
     }
@@ -366,6 +608,9 @@ enum GlobalField {
     OnError(String),
     Timeout(u32),
     TotalTimeout(u32),
+    MenuTitle(String),
+    Prompt(u32),
+    UI(String),
     Label(LabelField),
 }
 
@@ -375,6 +620,18 @@ named_tag_ci!(get_tag_ontimeout,    "ONTIMEOUT",    String, get_line);
 named_tag_ci!(get_tag_onerror,      "ONERROR",      String, get_line);
 named_tag_ci!(get_tag_timeout,      "TIMEOUT",      u32,    get_u32);
 named_tag_ci!(get_tag_totaltimeout, "TOTALTIMEOUT", u32,    get_u32);
+named_tag_ci!(get_tag_prompt,       "PROMPT",       u32,    get_u32);
+named_tag_ci!(get_tag_ui,           "UI",           String, get_line);
+named!(
+    get_tag_menu_title(&[u8]) -> String,
+    chain!(
+        skip_spaces0 ~
+        call!(skip_tag_ci, "MENU") ~
+        skip_spaces1 ~
+        call!(skip_tag_ci, "TITLE") ~
+        skip_spaces1 ~
+        title: get_line,
+        || title));
 
 impl GlobalField {
     named!(
@@ -384,18 +641,22 @@ impl GlobalField {
             get_tag_ontimeout    => { |v| GlobalField::OnTimeout(v)    } |
             get_tag_onerror      => { |v| GlobalField::OnError(v)      } |
             get_tag_timeout      => { |v| GlobalField::Timeout(v)      } |
-            get_tag_totaltimeout => { |v| GlobalField::TotalTimeout(v) }));
+            get_tag_totaltimeout => { |v| GlobalField::TotalTimeout(v) } |
+            get_tag_menu_title   => { |v| GlobalField::MenuTitle(v)    } |
+            get_tag_prompt       => { |v| GlobalField::Prompt(v)       } |
+            get_tag_ui           => { |v| GlobalField::UI(v)           }));
 }
 
 impl LineParser<GlobalField> for GlobalField {
-    named!(
-        parse_line(&[u8]) -> Option<Option<GlobalField> >,
+    fn parse_line<'a>(input: &'a [u8], options: &ParserOptions)
+                      -> nom::IResult<&'a [u8], Option<Option<GlobalField>>> {
         alt!(
+            input,
             call!(GlobalField::get_tag) => {
                 |v| some2!(v)
             } |
 
-            call!(LabelField::parse_line) => {
+            call!(LabelField::parse_line, options) => {
                 |v| match v {
                     Some(Some(label_field)) => {
                         some2!(GlobalField::Label(label_field))
@@ -403,78 +664,434 @@ impl LineParser<GlobalField> for GlobalField {
                     Some(None) => Some(None),
                     None => None,
                 }
-            }));
+            })
+    }
 }
 
 impl Global {
-    fn conv_timeout(timeout: u32) -> Option<f64> {
-        match timeout {
-            // Zero means disabled timeout.
-            0 => None,
+    fn conv_timeout(timeout: u32, options: &ParserOptions) -> Option<f64> {
+        match (timeout, options.dialect) {
+            // Zero means disabled timeout in syslinux.
+            (0, Dialect::Syslinux) => None,
+            // U-Boot boots the default label immediately.
             // Timeout is in units of 1/10s.
-            t => Some(t as f64 / 10.0),
+            (t, _) => Some(t as f64 / 10.0),
         }
     }
 }
 
 impl StructBuilder<GlobalField, Global> for Global {
-    fn build(mut self, field: GlobalField) -> Global {
+    fn build(mut self, field: GlobalField, line: usize, context: &mut Context)
+             -> Global {
+        let options = context.options;
         match field {
-            GlobalField::Default(v)   => self.default   = Some(v),
-            GlobalField::OnTimeout(v) => self.ontimeout = Some(v),
-            GlobalField::OnError(v)   => self.onerror   = Some(v),
+            GlobalField::Default(v)   => self.default    = Some(v),
+            GlobalField::OnTimeout(v) => self.ontimeout  = Some(v),
+            GlobalField::OnError(v)   => self.onerror    = Some(v),
+            GlobalField::MenuTitle(v) => self.menu_title = Some(v),
+            GlobalField::Prompt(v)    => self.prompt     = Some(v != 0),
+            GlobalField::UI(v)        => self.ui         = Some(v),
 
             GlobalField::Timeout(v)      => self.timeout =
-                Global::conv_timeout(v),
+                Global::conv_timeout(v, options),
             GlobalField::TotalTimeout(v) => self.total_timeout =
-                Global::conv_timeout(v),
+                Global::conv_timeout(v, options),
 
+            GlobalField::Label(LabelField::Unknown(v)) => {
+                if context.report_unknown(&v, line, false) {
+                    self.unknown_directives.push(unknown_directive(&v, line));
+                }
+            },
             GlobalField::Label(v) => {
-                self.label_defaults = Label::build(self.label_defaults, v)
+                self.label_defaults =
+                    Label::build(self.label_defaults, v, line, context)
             }
         };
         self
     }
 }
 
-trait LabelBuilder {
-    fn build(mut self, label_pair: (String, Label)) -> Labels;
+// What to do with label which was read.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LabelAction {
+    Add,
+    // Duplicate which is ignored.
+    Ignore,
+    // Duplicate which takes place of the previous label.
+    Replace,
 }
 
-impl LabelBuilder for Labels {
-    fn build(mut self, label_pair: (String, Label)) -> Labels {
-        let (label_name, label_data) = label_pair;
+// Names of labels read so far, to find duplicates.
+pub struct LabelNames {
+    // Line of the first LABEL of every name.
+    lines: std::collections::HashMap<String, usize>,
+}
 
-        match self.get(&label_name) {
-            // Label with this name already exists => ignore it.
-            Some(_) => {
-                warn!("Duplicate label \"{}\"", label_name);
-                self
-            },
+impl LabelNames {
+    pub fn new() -> LabelNames {
+        LabelNames{
+            lines: std::collections::HashMap::new(),
+        }
+    }
+
+    // None if parsing should stop.
+    fn check(&mut self, label_name: &str, location: Location,
+             context: &mut Context) -> Option<LabelAction> {
+        let first_line = match self.lines.get(label_name).cloned() {
+            Some(first_line) => first_line,
             None => {
-                self.insert(label_name, label_data);
-                self
-            }
+                self.lines.insert(String::from(label_name), location.line);
+                return Some(LabelAction::Add);
+            },
+        };
+
+        let policy = context.options.duplicate_labels;
+        let (severity, message) = match policy {
+            DuplicateLabels::FirstWins => (Severity::Warning, format!(
+                "Duplicate label \"{}\" is ignored, the one at line {} is \
+                 used", label_name, first_line)),
+            DuplicateLabels::LastWins => (Severity::Warning, format!(
+                "Duplicate label \"{}\" replaces the one at line {}",
+                label_name, first_line)),
+            DuplicateLabels::Error => (Severity::Error, format!(
+                "Duplicate label \"{}\", the first one is at line {}",
+                label_name, first_line)),
+        };
+
+        warn!("{}", message);
+        context.report(
+            Diagnostic::new(severity, "duplicate-label", message)
+                .for_label(label_name)
+                .at(location)
+                .related_to(Location::line(first_line)));
+        match policy {
+            DuplicateLabels::FirstWins => Some(LabelAction::Ignore),
+            DuplicateLabels::LastWins => Some(LabelAction::Replace),
+            DuplicateLabels::Error => None,
         }
     }
 }
 
+// Part of configuration, e.g. a single LABEL block.
+pub struct Section {
+    // Empty if section starts with LABEL.
+    pub global: Global,
+    pub labels: Vec<(String, Label, LabelAction)>,
+    // In order of lines.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+// LABEL line together with the following tags of label.
+fn parse_label_block<'a>(input: &'a [u8], context: &mut Context)
+                         -> Option<(&'a [u8], String, Label, Location)> {
+    let (after_name, label_name) = match get_tag_label(input) {
+        nom::IResult::Done(next, label_name) => (next, label_name),
+        _ => return None,
+    };
+
+    let reported = context.diagnostics.len();
+    let (next, label_data) = match Label::parse(after_name, context) {
+        nom::IResult::Done(next, label_data) => (next, label_data),
+        _ => return None,
+    };
+    for diagnostic in &mut context.diagnostics[reported..] {
+        diagnostic.label = Some(label_name.clone());
+    }
+
+    let location = context.span(input, next);
+    Some((next, label_name, label_data, location))
+}
+
+// Section of input which starts at first_line of configuration, either at
+// the beginning or at a LABEL line. Returns rest of input if parsing stopped
+// at duplicate label.
+pub fn parse_section<'a>(input: &'a [u8], first_line: usize,
+                         options: &ParserOptions, names: &mut LabelNames)
+                         -> (&'a [u8], Section) {
+    let (text, lexer_diagnostics) = lexer::normalize(input, options.encoding,
+                                                     first_line);
+    let text = text.as_bytes();
+    let mut context = Context{
+        input: text,
+        first_line: first_line,
+        line_starts: lexer::line_starts(text),
+        options: options,
+        diagnostics: lexer_diagnostics,
+    };
+
+    let (mut rest, global) = match Global::parse(text, &mut context) {
+        nom::IResult::Done(rest, global) => (rest, global),
+        _ => (text, Global::default()),
+    };
+
+    let mut labels = Vec::new();
+    while let Some((next, label_name, label_data, location)) =
+            parse_label_block(rest, &mut context) {
+        match names.check(&label_name, location, &mut context) {
+            Some(action) => labels.push((label_name, label_data, action)),
+            None => break,
+        };
+        rest = next;
+    }
+
+    let mut diagnostics = context.diagnostics;
+    diagnostics.sort_by_key(|diagnostic| {
+        diagnostic.location.as_ref().map_or(0, |location| location.line)
+    });
+
+    // Lines are kept by lexer, so rest starts at the same line of input.
+    let offset = match rest.is_empty() {
+        true => input.len(),
+        false => lexer::line_offset(input, lexer::line_at(text, rest)),
+    };
+    let section = Section{
+        global: global,
+        labels: labels,
+        diagnostics: diagnostics,
+    };
+    (&input[offset..], section)
+}
+
 impl SyslinuxConf {
-    named!(
-        pub parse(&[u8]) -> SyslinuxConf,
-        chain!(
-            global: call!(Global::parse) ~
-            labels: fold_many0!(
-                chain!(
-                    label_name: get_tag_label ~
-                    label_data: call!(Label::parse),
-                    || (label_name, label_data) ),
-                Labels::new(),
-                Labels::build),
-            move || SyslinuxConf{
-                    global: global,
-                    labels: labels,
-            }
-        )
-    );
+    pub fn parse(input: &[u8]) -> nom::IResult<&[u8], SyslinuxConf> {
+        SyslinuxConf::parse_with_options(input, &ParserOptions::default())
+    }
+
+    pub fn parse_with_options<'a>(input: &'a [u8], options: &ParserOptions)
+                                  -> nom::IResult<&'a [u8], SyslinuxConf> {
+        match SyslinuxConf::parse_with_diagnostics(input, options) {
+            nom::IResult::Done(rest, (conf, _)) => {
+                nom::IResult::Done(rest, conf)
+            },
+            nom::IResult::Error(err) => nom::IResult::Error(err),
+            nom::IResult::Incomplete(needed) => {
+                nom::IResult::Incomplete(needed)
+            },
+        }
+    }
+
+    // Diagnostics have lines, but not file. With duplicate label policy
+    // Error parsing stops at the duplicate.
+    pub fn parse_with_diagnostics<'a>(input: &'a [u8], options: &ParserOptions)
+            -> nom::IResult<&'a [u8], (SyslinuxConf, Vec<Diagnostic>)> {
+        let (rest, section) = parse_section(input, 1, options,
+                                            &mut LabelNames::new());
+
+        let mut labels = Labels::new();
+        for (label_name, label_data, action) in section.labels {
+            match action {
+                LabelAction::Add => {
+                    labels.insert(label_name, label_data);
+                },
+                // Position of the first one is kept.
+                LabelAction::Replace => {
+                    if let Some(label) = labels.get_mut(&label_name) {
+                        *label = label_data;
+                    }
+                },
+                LabelAction::Ignore => {},
+            };
+        }
+
+        let conf = SyslinuxConf{
+            global: section.global,
+            labels: labels,
+        };
+        nom::IResult::Done(rest, (conf, section.diagnostics))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate nom;
+    extern crate serde_json;
+
+    use super::{Dialect, DuplicateLabels, ParserOptions};
+    use types::{Diagnostic, Location, Severity, SyslinuxConf,
+                UnknownDirective};
+
+    const DUPLICATES: &'static str = "LABEL linux\n\
+                                      \x20 KERNEL vmlinuz\n\
+                                      LABEL rescue\n\
+                                      \x20 KERNEL vmlinuz\n\
+                                      LABEL linux\n\
+                                      \x20 KERNEL vmlinuz.new\n";
+
+    // Rest of input is returned as text.
+    fn parse_with(text: &str, options: &ParserOptions)
+                  -> (String, SyslinuxConf, Vec<Diagnostic>) {
+        match SyslinuxConf::parse_with_diagnostics(text.as_bytes(), options) {
+            nom::IResult::Done(rest, (conf, diagnostics)) => {
+                (String::from_utf8(rest.to_vec()).unwrap(), conf,
+                 diagnostics)
+            },
+            _ => panic!("Unable to parse configuration:\n{}", text),
+        }
+    }
+
+    fn duplicates(policy: DuplicateLabels)
+                  -> (String, SyslinuxConf, Vec<Diagnostic>) {
+        let mut options = ParserOptions::default();
+        options.duplicate_labels = policy;
+        parse_with(DUPLICATES, &options)
+    }
+
+    fn kernel_path(conf: &SyslinuxConf, label_name: &str) -> String {
+        let kernel_file = conf.labels[label_name].kernel().kernel_file
+            .clone().unwrap();
+        String::from(kernel_file.path().to_string_lossy())
+    }
+
+    fn label_names(conf: &SyslinuxConf) -> Vec<&str> {
+        conf.labels.keys().map(|name| name.as_str()).collect()
+    }
+
+    fn directives(directives: &[UnknownDirective]) -> Vec<(&str, &str, usize)> {
+        directives.iter()
+            .map(|directive| (directive.keyword.as_str(),
+                              directive.argument.as_str(),
+                              directive.line))
+            .collect()
+    }
+
+    #[test]
+    fn first_duplicate_wins() {
+        let (rest, conf, diagnostics) =
+            duplicates(DuplicateLabels::FirstWins);
+
+        assert_eq!(rest, "");
+        assert_eq!(label_names(&conf), vec!["linux", "rescue"]);
+        assert_eq!(kernel_path(&conf, "linux"), "vmlinuz");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!(diagnostics[0].code, "duplicate-label");
+        assert_eq!(diagnostics[0].label, Some(String::from("linux")));
+        assert_eq!(diagnostics[0].location, Some(Location::lines(5, 6)));
+        assert_eq!(diagnostics[0].related_locations, vec![Location::line(1)]);
+    }
+
+    #[test]
+    fn last_duplicate_wins_in_place_of_first() {
+        let (rest, conf, diagnostics) =
+            duplicates(DuplicateLabels::LastWins);
+
+        assert_eq!(rest, "");
+        assert_eq!(label_names(&conf), vec!["linux", "rescue"]);
+        assert_eq!(kernel_path(&conf, "linux"), "vmlinuz.new");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+    }
+
+    #[test]
+    fn duplicate_stops_parsing() {
+        let (rest, conf, diagnostics) = duplicates(DuplicateLabels::Error);
+
+        assert_eq!(rest, "LABEL linux\n  KERNEL vmlinuz.new\n");
+        assert_eq!(label_names(&conf), vec!["linux", "rescue"]);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(diagnostics[0].location, Some(Location::lines(5, 6)));
+    }
+
+    #[test]
+    fn reports_diagnostics_with_lines_and_labels() {
+        let (_, conf, diagnostics) = parse_with(
+            "SERIAL 0 115200\n\
+             TIMEOUT soon\n\
+             LABEL linux\n\
+             \x20 KERNEL vmlinuz\n\
+             \x20 DEFAULT rescue\n",
+            &ParserOptions::default());
+
+        let found: Vec<(Severity, &str, Option<&str>, usize)> =
+            diagnostics.iter()
+                .map(|diagnostic| (diagnostic.severity,
+                                   diagnostic.code.as_str(),
+                                   diagnostic.label.as_ref()
+                                       .map(|label| label.as_str()),
+                                   diagnostic.location.as_ref().unwrap().line))
+                .collect();
+        assert_eq!(found,
+                   vec![(Severity::Info, "unknown-tag", None, 1),
+                        (Severity::Warning, "invalid-number", None, 2),
+                        (Severity::Warning, "wrong-scope", Some("linux"), 5)]);
+        assert_eq!(conf.global.timeout, None);
+        assert_eq!(conf.global.default, None);
+        assert_eq!(directives(&conf.global.unknown_directives),
+                   vec![("SERIAL", "0 115200", 1)]);
+        assert_eq!(directives(&conf.labels["linux"].unknown_directives),
+                   vec![("DEFAULT", "rescue", 5)]);
+    }
+
+    #[test]
+    fn reports_nothing_for_valid_configuration() {
+        let (_, _, diagnostics) = parse_with(
+            "# Comment\n\
+             DEFAULT linux\n\
+             \n\
+             LABEL linux\n\
+             \x20 KERNEL vmlinuz\n\
+             \x20 TEXT HELP\n\
+             \x20 Help\n\
+             \x20 ENDTEXT\n",
+            &ParserOptions::default());
+
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn strict_mode_reports_errors() {
+        let text = "SERIAL 0 115200\n\
+                    LABEL linux\n\
+                    \x20 KERNEL vmlinuz\n\
+                    \x20 FDT board.dtb\n";
+        let mut options = ParserOptions::default();
+        options.strict = true;
+        let (rest, conf, diagnostics) = parse_with(text, &options);
+
+        // Strict mode reports, but does not change what is read.
+        assert_eq!(rest, "");
+        assert!(conf.labels.contains_key("linux"));
+        let found: Vec<(Severity, &str)> = diagnostics.iter()
+            .map(|diagnostic| (diagnostic.severity, diagnostic.code.as_str()))
+            .collect();
+        assert_eq!(found, vec![(Severity::Error, "unknown-tag"),
+                               (Severity::Error, "unknown-tag")]);
+    }
+
+    #[test]
+    fn uboot_tags_are_known_in_uboot_dialect() {
+        let text = "LABEL linux\n\
+                    \x20 KERNEL vmlinuz\n\
+                    \x20 FDT board.dtb\n\
+                    \x20 FDTOVERLAYS a.dtbo b.dtbo\n";
+        let mut options = ParserOptions::default();
+        options.strict = true;
+        options.dialect = Dialect::UBoot;
+        let (_, conf, diagnostics) = parse_with(text, &options);
+
+        assert!(diagnostics.is_empty());
+        assert_eq!(conf.labels["linux"].kernel().fdt_overlays.len(), 2);
+    }
+
+    #[test]
+    fn keeps_unknown_directives() {
+        let (_, conf, _) = parse_with(
+            "SERIAL 0 115200\n\
+             DEFAULT linux\n\
+             LABEL linux\n\
+             \x20 KERNEL vmlinuz\n\
+             \x20 IPAPPEND   2\n\
+             \x20 SYSAPPEND\n",
+            &ParserOptions::default());
+
+        assert_eq!(directives(&conf.global.unknown_directives),
+                   vec![("SERIAL", "0 115200", 1)]);
+        assert_eq!(directives(&conf.labels["linux"].unknown_directives),
+                   vec![("IPAPPEND", "2", 5), ("SYSAPPEND", "", 6)]);
+
+        let json = serde_json::to_string(&conf).unwrap();
+        assert!(json.contains("\"keyword\":\"SERIAL\""), "{}", json);
+        assert!(json.contains("\"keyword\":\"IPAPPEND\""), "{}", json);
+    }
 }
@@ -3,10 +3,18 @@ use std;
 extern crate enum_derive;
 extern crate nom;
 
+use inspect::{InspectOptions, Inspector, initrd_diagnostics};
+use lexer;
+use parser::ParserOptions;
+use stream::{StreamEvent, StreamParser};
 use types;
 
+// Size of chunks configuration file is streamed in.
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
 custom_derive! {
-    #[derive(Debug, IterVariants(LocalConfTypeVariants))]
+    #[derive(Debug, Clone, Copy, PartialEq,
+             IterVariants(LocalConfTypeVariants))]
     pub enum LocalConfType {
         SysLinux,  // syslinux/syslinux.cfg
         IsoLinux,  // isolinux/isolinux.cfg
@@ -34,6 +42,16 @@ impl LocalConfType {
         }
     }
 
+    // Type of configuration by name of its file.
+    fn from_file_name(path: &std::path::Path) -> Option<LocalConfType> {
+        match path.file_name().and_then(|file_name| file_name.to_str()) {
+            Some("syslinux.cfg") => Some(LocalConfType::SysLinux),
+            Some("isolinux.cfg") => Some(LocalConfType::IsoLinux),
+            Some("extlinux.conf") => Some(LocalConfType::ExtLinux),
+            _ => None,
+        }
+    }
+
     fn get_all_paths(root: std::path::PathBuf) -> Vec<std::path::PathBuf> {
         LocalConfType::iter_variants().map(
             |local_type| local_type.get_paths(root.clone())
@@ -51,14 +69,77 @@ pub struct Reader {
     root_dir: std::path::PathBuf,
     conf_dir: std::path::PathBuf,
     conf_file_path: std::path::PathBuf,
+    conf_type: Option<LocalConfType>,
+    inspect_options: InspectOptions,
+    parser_options: ParserOptions,
 }
 
-// TODO: Detailed errors.
 #[derive(Debug)]
-pub struct ReaderError {}
+pub enum ReaderError {
+    // None of autodetected paths exists.
+    ConfNotFound(Vec<std::path::PathBuf>),
+    InvalidConfPath(std::path::PathBuf),
+    ConfOutsideRoot(std::path::PathBuf),
+    Io(std::io::Error),
+    // Line where parser stopped, if known, and diagnostics reported before
+    // it, e.g. the duplicate label parsing stopped at.
+    Parse(std::path::PathBuf, Option<usize>, Vec<types::Diagnostic>),
+}
 
 impl std::convert::From<std::io::Error> for ReaderError {
-    fn from(_: std::io::Error) -> ReaderError { ReaderError{} }
+    fn from(err: std::io::Error) -> ReaderError { ReaderError::Io(err) }
+}
+
+impl std::fmt::Display for ReaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            ReaderError::ConfNotFound(ref paths) => {
+                try!(write!(f, "Configuration file not found, tried:"));
+                for path in paths {
+                    try!(write!(f, " {:?}", path));
+                }
+                Ok(())
+            },
+            ReaderError::InvalidConfPath(ref path) => {
+                write!(f, "Invalid configuration file path {:?}", path)
+            },
+            ReaderError::ConfOutsideRoot(ref path) => {
+                write!(f, "Configuration file {:?} is outside of the root \
+                           directory", path)
+            },
+            ReaderError::Io(ref err) => write!(f, "{}", err),
+            ReaderError::Parse(ref path, Some(line), _) => {
+                write!(f, "Unable to parse {:?} at line {}", path, line)
+            },
+            ReaderError::Parse(ref path, None, _) => {
+                write!(f, "Unable to parse {:?}", path)
+            },
+        }
+    }
+}
+
+impl ReaderError {
+    pub fn code(&self) -> &'static str {
+        match *self {
+            ReaderError::ConfNotFound(_)    => "conf-not-found",
+            ReaderError::InvalidConfPath(_) => "conf-invalid-path",
+            ReaderError::ConfOutsideRoot(_) => "conf-outside-root",
+            ReaderError::Io(_)              => "io",
+            ReaderError::Parse(_, _, _)     => "parse",
+        }
+    }
+
+    pub fn diagnostics(&self) -> &[types::Diagnostic] {
+        match *self {
+            ReaderError::Parse(_, _, ref diagnostics) => diagnostics,
+            _ => &[],
+        }
+    }
+
+    pub fn to_diagnostic(&self) -> types::Diagnostic {
+        types::Diagnostic::new(types::Severity::Error, self.code(),
+                               format!("{}", self))
+    }
 }
 
 fn sanitize_path(path: std::path::PathBuf) -> std::path::PathBuf {
@@ -99,6 +180,44 @@ fn resolve_one_path(path: std::path::PathBuf,
     }
 }
 
+// File included by INCLUDE or MENU INCLUDE directive.
+fn include_path(directive: &types::UnknownDirective) -> Option<&str> {
+    let argument = match directive.keyword.to_ascii_uppercase().as_str() {
+        "INCLUDE" => directive.argument.as_str(),
+        "MENU" => {
+            let mut words = directive.argument.splitn(2, char::is_whitespace);
+            match words.next() {
+                Some(word) if word.eq_ignore_ascii_case("INCLUDE") => {
+                    words.next().unwrap_or("")
+                },
+                _ => return None,
+            }
+        },
+        _ => return None,
+    };
+    // MENU INCLUDE may be followed by tag name.
+    argument.split_whitespace().next()
+}
+
+// Reverse of resolve_one_path(): path as seen from the boot device. None if
+// path is outside of the root directory.
+pub fn get_device_path(root_dir: &std::path::Path, path: &std::path::Path)
+        -> Option<std::path::PathBuf> {
+    let root = std::path::Path::new("/");
+
+    if let Ok(relative) = path.strip_prefix(root_dir) {
+        return Some(root.join(relative));
+    }
+
+    match (std::fs::canonicalize(root_dir), std::fs::canonicalize(path)) {
+        (Ok(root_dir), Ok(path)) => match path.strip_prefix(&root_dir) {
+            Ok(relative) => Some(root.join(relative)),
+            Err(_) => None,
+        },
+        _ => None,
+    }
+}
+
 trait PathResolver {
     fn resolve(self, root_dir: &std::path::PathBuf,
                conf_dir: &std::path::PathBuf) -> Self;
@@ -172,6 +291,9 @@ impl PathResolver for types::Kernel {
         self.kernel_file = resolve_some!(self.kernel_file, root_dir, conf_dir);
         self.initrd = resolve_some_path!(self.initrd, root_dir, conf_dir);
         self.fdt_dir = resolve_some_path!(self.fdt_dir, root_dir, conf_dir);
+        self.fdt = resolve_some_path!(self.fdt, root_dir, conf_dir);
+        self.fdt_overlays = self.fdt_overlays.into_iter().map(
+            |path| resolve_one_path(path, root_dir, conf_dir)).collect();
         self
     }
 }
@@ -219,12 +341,21 @@ impl PathResolver for types::SyslinuxConf {
     }
 }
 
+// For configurations which do not come from a file on the boot device, e.g.
+// imported from other boot loaders: relative paths are relative to the root.
+pub fn resolve_paths(conf: types::SyslinuxConf, root_dir: &std::path::Path)
+        -> types::SyslinuxConf {
+    let root_dir = root_dir.to_path_buf();
+    conf.resolve(&root_dir, &root_dir)
+}
+
 impl Reader {
     fn find_existing_local_conf(paths: Vec<std::path::PathBuf>)
             -> Result<std::path::PathBuf, ReaderError> {
-        match paths.into_iter().find(|path| path.exists()) {
-            Some(path) => Ok(path),
-            None => Err(ReaderError{}),
+        let found = paths.iter().position(|path| path.exists());
+        match found {
+            Some(index) => Ok(paths[index].clone()),
+            None => Err(ReaderError::ConfNotFound(paths)),
         }
     }
 
@@ -233,7 +364,7 @@ impl Reader {
         let conf_dir = try!(std::fs::canonicalize(&self.conf_dir));
         match conf_dir.starts_with(root_dir) {
             true => Ok(self),
-            false => Err(ReaderError{}),
+            false => Err(ReaderError::ConfOutsideRoot(self.conf_file_path)),
         }
     }
 
@@ -247,9 +378,14 @@ impl Reader {
                     true => std::path::PathBuf::from("."),
                     false => conf_dir.to_path_buf(),
                 },
-                None => return Err(ReaderError{}),
+                None => {
+                    return Err(ReaderError::InvalidConfPath(conf_file_path))
+                },
             },
+            conf_type: LocalConfType::from_file_name(&conf_file_path),
             conf_file_path: conf_file_path,
+            inspect_options: InspectOptions::default(),
+            parser_options: ParserOptions::default(),
         }.check_paths()
     }
 
@@ -274,31 +410,267 @@ impl Reader {
             LocalConfType::get_all_paths(root))
     }
 
+    pub fn root_dir(&self) -> &std::path::Path {
+        &self.root_dir
+    }
+
+    pub fn conf_file_path(&self) -> &std::path::Path {
+        &self.conf_file_path
+    }
+
+    // Path from configuration as seen on the host.
+    pub fn resolve_path(&self, path: &std::path::Path) -> std::path::PathBuf {
+        resolve_one_path(path.to_path_buf(), &self.root_dir, &self.conf_dir)
+    }
+
+    // Detected by name of the configuration file.
+    pub fn conf_type(&self) -> Option<LocalConfType> {
+        self.conf_type
+    }
+
+    // Configuration file and all files included from it, even though
+    // contents of included files are not parsed into configuration yet.
+    pub fn conf_files(&self) -> Vec<std::path::PathBuf> {
+        let mut conf_files = vec![self.conf_file_path.clone()];
+        let mut next = 0;
+        while next < conf_files.len() {
+            let included = self.included_files(&conf_files[next]);
+            for conf_file in included {
+                if !conf_files.contains(&conf_file) {
+                    conf_files.push(conf_file);
+                }
+            }
+            next += 1;
+        }
+        conf_files
+    }
+
+    // Unreadable files are not skipped, so that manifest reports them.
+    fn included_files(&self, conf_file_path: &std::path::Path)
+            -> Vec<std::path::PathBuf> {
+        use std::io::Read;
+
+        let mut contents = Vec::new();
+        let read = std::fs::File::open(conf_file_path)
+            .and_then(|mut file| file.read_to_end(&mut contents));
+        if read.is_err() {
+            return Vec::new();
+        }
+
+        let conf = match types::SyslinuxConf::parse_with_diagnostics(
+                &contents[..], &self.parser_options) {
+            nom::IResult::Done(_, (conf, _)) => conf,
+            _ => return Vec::new(),
+        };
+
+        conf.global.unknown_directives.iter()
+            .chain(conf.labels.values()
+                       .flat_map(|label| label.unknown_directives.iter()))
+            .filter_map(include_path)
+            .map(|path| resolve_one_path(std::path::PathBuf::from(path),
+                                         &self.root_dir, &self.conf_dir))
+            .collect()
+    }
+
+    pub fn with_inspect_options(mut self, options: InspectOptions) -> Reader {
+        self.inspect_options = options;
+        self
+    }
+
+    pub fn with_parser_options(mut self, options: ParserOptions) -> Reader {
+        self.parser_options = options;
+        self
+    }
+
     fn get_conf_contents(&self) -> Result<Vec<u8>, ReaderError> {
         let mut file = try!(std::fs::File::open(&self.conf_file_path));
 
         {
             use std::io::prelude::*;
             let mut buf = Vec::new();
-            match file.read_to_end(&mut buf) {
-                Ok(_) => Ok(buf),
-                Err(_) => Err(ReaderError{}),
-            }
+            try!(file.read_to_end(&mut buf));
+            Ok(buf)
         }
     }
 
-    fn read_raw(&self) -> Result<types::SyslinuxConf, ReaderError> {
-        match types::SyslinuxConf::parse(&try!(self.get_conf_contents())[..]) {
-            nom::IResult::Done(remaining, conf) => match remaining.len() {
-                0 => Ok(conf),
-                _ => Err(ReaderError{}),
+    // Configuration as written in the file: paths are not resolved, files
+    // are not inspected.
+    pub fn read_raw(&self) -> Result<types::SyslinuxConf, ReaderError> {
+        self.read_raw_with_diagnostics().map(|(conf, _)| conf)
+    }
+
+    pub fn read(&self) -> Result<types::SyslinuxConf, ReaderError> {
+        self.read_with_diagnostics().map(|(conf, _)| conf)
+    }
+
+    // Diagnostics are about things parser tolerated, e.g. unknown tags.
+    pub fn read_raw_with_diagnostics(&self)
+            -> Result<(types::SyslinuxConf, Vec<types::Diagnostic>),
+                      ReaderError> {
+        let contents = try!(self.get_conf_contents());
+
+        match types::SyslinuxConf::parse_with_diagnostics(
+                &contents[..], &self.parser_options) {
+            nom::IResult::Done(remaining, (conf, diagnostics)) => {
+                let diagnostics = diagnostics.into_iter()
+                    .map(|d| d.in_file(&self.conf_file_path))
+                    .collect();
+                match remaining.len() {
+                    0 => Ok((conf, diagnostics)),
+                    _ => {
+                        let line = lexer::line_at(&contents, remaining);
+                        Err(ReaderError::Parse(self.conf_file_path.clone(),
+                                               Some(line), diagnostics))
+                    },
+                }
             },
-            _ => Err(ReaderError{}),
+            _ => Err(ReaderError::Parse(self.conf_file_path.clone(), None,
+                                        Vec::new())),
         }
     }
 
-    pub fn read(&self) -> Result<types::SyslinuxConf, ReaderError> {
-        self.read_raw().map(
-            |conf| conf.resolve(&self.root_dir, &self.conf_dir))
+    // Raw configuration part by part, without reading the whole file into
+    // memory.
+    pub fn read_stream<F>(&self, mut handler: F) -> Result<(), ReaderError>
+        where F: FnMut(StreamEvent)
+    {
+        use std::io::Read;
+
+        let mut file = try!(std::fs::File::open(&self.conf_file_path));
+        let mut parser = StreamParser::new(&self.parser_options);
+        let mut chunk = vec![0u8; STREAM_CHUNK_SIZE];
+        loop {
+            let size = try!(file.read(&mut chunk));
+            let events = match size {
+                0 => parser.finish(),
+                _ => parser.feed(&chunk[..size]),
+            };
+            for event in events {
+                handler(match event {
+                    StreamEvent::Diagnostic(diagnostic) => {
+                        StreamEvent::Diagnostic(
+                            diagnostic.in_file(&self.conf_file_path))
+                    },
+                    event => event,
+                });
+            }
+            if size == 0 {
+                break;
+            }
+        }
+
+        // Diagnostics were given to handler already.
+        match parser.stopped_at() {
+            Some(line) => Err(ReaderError::Parse(self.conf_file_path.clone(),
+                                                 Some(line), Vec::new())),
+            None => Ok(()),
+        }
+    }
+
+    pub fn read_with_diagnostics(&self)
+            -> Result<(types::SyslinuxConf, Vec<types::Diagnostic>),
+                      ReaderError> {
+        self.read_raw_with_diagnostics().map(|(conf, mut diagnostics)| {
+            let conf = conf.resolve(&self.root_dir, &self.conf_dir)
+                .inspect(&self.inspect_options);
+            diagnostics.extend(initrd_diagnostics(&conf));
+            (conf, diagnostics)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LocalConfType, Reader, ReaderError};
+    use parser::{DuplicateLabels, ParserOptions};
+    use test_util::TempDir;
+
+    #[test]
+    fn finds_configuration_by_type() {
+        let dir = TempDir::new();
+        let conf_file = dir.write("isolinux/isolinux.cfg", b"");
+
+        let reader = Reader::from_local(dir.path().to_path_buf()).unwrap();
+        assert_eq!(reader.conf_file_path(), conf_file.as_path());
+        assert_eq!(reader.conf_type(), Some(LocalConfType::IsoLinux));
+    }
+
+    #[test]
+    fn reports_error_codes() {
+        let dir = TempDir::new();
+        let outside = TempDir::new();
+        let conf_file = outside.write("syslinux.cfg", b"");
+
+        let err = Reader::from_local(dir.path().to_path_buf()).unwrap_err();
+        assert_eq!(err.code(), "conf-not-found");
+        match Reader::from_local_conf_file_path(dir.path().to_path_buf(),
+                                                conf_file) {
+            Err(err @ ReaderError::ConfOutsideRoot(_)) => {
+                assert_eq!(err.to_diagnostic().code, "conf-outside-root");
+            },
+            result => panic!("Unexpected result {:?}", result),
+        };
+    }
+
+    #[test]
+    fn returns_diagnostics_with_file() {
+        let dir = TempDir::new();
+        let conf_file = dir.write("syslinux.cfg", b"SERIAL 0\n\
+                                                    LABEL linux\n\
+                                                    \x20 KERNEL vmlinuz\n");
+
+        let reader = Reader::from_local(dir.path().to_path_buf()).unwrap();
+        let (conf, diagnostics) = reader.read_with_diagnostics().unwrap();
+        assert!(conf.labels.contains_key("linux"));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, "unknown-tag");
+        let location = diagnostics[0].location.clone().unwrap();
+        assert_eq!(location.file, Some(conf_file));
+        assert_eq!(location.line, 1);
+    }
+
+    #[test]
+    fn keeps_diagnostics_of_parse_error() {
+        let dir = TempDir::new();
+        dir.write("syslinux.cfg", b"LABEL linux\n\
+                                    \x20 KERNEL vmlinuz\n\
+                                    LABEL linux\n\
+                                    \x20 KERNEL vmlinuz.new\n");
+        let mut options = ParserOptions::default();
+        options.duplicate_labels = DuplicateLabels::Error;
+        let reader = Reader::from_local(dir.path().to_path_buf()).unwrap()
+            .with_parser_options(options);
+
+        let err = reader.read_with_diagnostics().unwrap_err();
+        match err {
+            ReaderError::Parse(_, Some(3), _) => (),
+            ref err => panic!("Unexpected error {:?}", err),
+        };
+        let diagnostics = err.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, "duplicate-label");
+        assert_eq!(diagnostics[0].related_locations[0].line, 1);
+    }
+
+    #[test]
+    fn lists_included_conf_files() {
+        let dir = TempDir::new();
+        let conf_file = dir.write(
+            "boot/syslinux.cfg",
+            b"INCLUDE common.cfg\n\
+              MENU INCLUDE /boot/menu.cfg Main\n\
+              LABEL linux\n\
+              \x20 KERNEL vmlinuz\n");
+        dir.write("boot/common.cfg", b"INCLUDE syslinux.cfg\n\
+                                       INCLUDE missing.cfg\n");
+        dir.write("boot/menu.cfg", b"MENU TITLE Boot\n");
+
+        let reader = Reader::from_local_conf_file_path(
+            dir.path().to_path_buf(), conf_file.clone()).unwrap();
+        assert_eq!(reader.conf_files(),
+                   vec![conf_file,
+                        dir.path().join("boot/common.cfg"),
+                        dir.path().join("boot/menu.cfg"),
+                        dir.path().join("boot/missing.cfg")]);
     }
 }
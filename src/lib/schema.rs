@@ -0,0 +1,334 @@
+extern crate serde_json;
+
+use self::serde_json::Value;
+
+// Version of JSON output format. Major version is increased on changes which
+// may break consumers (removed or renamed fields, changed types), minor
+// version on compatible additions. Must be kept in sync with "id" of
+// schema.json.
+pub const SCHEMA_VERSION: &'static str = "1.5";
+
+const SCHEMA: &'static str = include_str!("schema.json");
+
+// JSON Schema (draft 4) of serialized SyslinuxConf.
+pub fn json_schema() -> &'static str {
+    SCHEMA
+}
+
+// JSON Schema of output with --envelope. Definitions are the same, but the
+// root is Envelope and SyslinuxConf becomes one of definitions.
+pub fn envelope_json_schema() -> String {
+    let mut conf_schema = match serde_json::from_str(SCHEMA) {
+        Ok(Value::Object(conf_schema)) => conf_schema,
+        _ => panic!("schema.json is not an object"),
+    };
+    let mut definitions = match conf_schema.remove("definitions") {
+        Some(Value::Object(definitions)) => definitions,
+        _ => panic!("schema.json has no definitions"),
+    };
+    let meta_schema = conf_schema.remove("$schema");
+    conf_schema.remove("id");
+
+    let mut schema = serde_json::Map::new();
+    if let Some(meta_schema) = meta_schema {
+        schema.insert(String::from("$schema"), meta_schema);
+    }
+    schema.insert(String::from("id"), Value::String(format!(
+        "urn:syslinux-conf:envelope-schema:{}", SCHEMA_VERSION)));
+    schema.insert(String::from("title"), Value::String(
+        String::from("Envelope")));
+    if let Some(&Value::Object(ref envelope)) = definitions.get("Envelope") {
+        for (key, value) in envelope.iter() {
+            schema.insert(key.clone(), value.clone());
+        }
+    }
+    definitions.insert(String::from("SyslinuxConf"),
+                       Value::Object(conf_schema));
+    schema.insert(String::from("definitions"), Value::Object(definitions));
+
+    let mut text = serde_json::to_string_pretty(&Value::Object(schema))
+        .unwrap();
+    text.push('\n');
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate serde;
+    extern crate serde_json;
+
+    use std;
+
+    use self::serde_json::Value;
+    use super::{SCHEMA_VERSION, envelope_json_schema, json_schema};
+    use test_util::parse;
+    use types::{CpioEntry, Diagnostic, Envelope, InitrdCompression,
+                InitrdInfo, InitrdSegment, KernelOrConfig, LinuxKernelInfo,
+                Location, Severity, SyslinuxConf};
+
+    fn get<'a>(value: &'a Value, key: &str) -> Option<&'a Value> {
+        value.as_object().and_then(|object| object.get(key))
+    }
+
+    fn has_type(value: &Value, type_name: &Value) -> bool {
+        match *type_name {
+            Value::String(ref type_name) => match type_name.as_str() {
+                "object" => value.is_object(),
+                "array" => value.is_array(),
+                "string" => value.is_string(),
+                "boolean" => value.is_boolean(),
+                "null" => value.is_null(),
+                "number" => value.is_number(),
+                "integer" => value.is_u64() || value.is_i64(),
+                _ => panic!("Unknown type {}", type_name),
+            },
+            _ => panic!("Type is not a string: {}", type_name),
+        }
+    }
+
+    // Only keywords used by schema.json are supported.
+    fn schema_errors(value: &Value, schema: &Value, root: &Value)
+                     -> Vec<String> {
+        if let Some(&Value::String(ref reference)) = get(schema, "$ref") {
+            let target = match reference.as_str() {
+                "#" => Some(root),
+                _ => get(root, "definitions").and_then(|definitions| {
+                    get(definitions,
+                        reference.trim_left_matches("#/definitions/"))
+                }),
+            };
+            return match target {
+                Some(target) => schema_errors(value, target, root),
+                None => vec![format!("Unresolved reference {}", reference)],
+            };
+        }
+
+        let mut errors = Vec::new();
+        match get(schema, "type") {
+            Some(&Value::Array(ref types)) => {
+                if !types.iter().any(|type_name| has_type(value, type_name)) {
+                    errors.push(format!("{} is not one of {:?}", value,
+                                        types));
+                }
+            },
+            Some(type_name) => if !has_type(value, type_name) {
+                errors.push(format!("{} is not {}", value, type_name));
+            },
+            None => (),
+        };
+        if let Some(&Value::Array(ref values)) = get(schema, "enum") {
+            if !values.contains(value) {
+                errors.push(format!("{} is not one of {:?}", value, values));
+            }
+        }
+        if let Some(minimum) = get(schema, "minimum")
+                .and_then(|minimum| minimum.as_f64()) {
+            if value.as_f64().map_or(false, |number| number < minimum) {
+                errors.push(format!("{} is less than {}", value, minimum));
+            }
+        }
+        if let Some(&Value::Array(ref schemas)) = get(schema, "oneOf") {
+            let matching = schemas.iter()
+                .filter(|schema| schema_errors(value, schema, root).is_empty())
+                .count();
+            if matching != 1 {
+                errors.push(format!("{} matches {} of oneOf", value,
+                                    matching));
+            }
+        }
+
+        match *value {
+            Value::Object(ref object) => {
+                for (key, item) in object.iter() {
+                    let item_schema = get(schema, "properties")
+                        .and_then(|properties| get(properties, key));
+                    match (item_schema, get(schema, "additionalProperties")) {
+                        (Some(item_schema), _) | (None, Some(item_schema))
+                                if item_schema.is_object() => {
+                            errors.extend(schema_errors(item, item_schema,
+                                                        root));
+                        },
+                        (None, Some(&Value::Bool(false))) => {
+                            errors.push(format!("Unexpected property {}",
+                                                key));
+                        },
+                        _ => (),
+                    };
+                }
+                if let Some(&Value::Array(ref required)) =
+                        get(schema, "required") {
+                    for key in required {
+                        if let Value::String(ref key) = *key {
+                            if !object.contains_key(key) {
+                                errors.push(format!("Missing property {}",
+                                                    key));
+                            }
+                        }
+                    }
+                }
+            },
+            Value::Array(ref items) => if let Some(item_schema) =
+                    get(schema, "items") {
+                for item in items {
+                    errors.extend(schema_errors(item, item_schema, root));
+                }
+            },
+            _ => (),
+        };
+        errors
+    }
+
+    fn assert_valid<T: serde::Serialize>(value: &T, schema: &str) {
+        let schema: Value = serde_json::from_str(schema).unwrap();
+        let value = serde_json::value::to_value(value);
+        let errors = schema_errors(&value, &schema, &schema);
+        assert!(errors.is_empty(), "{}\n{:#?}", value, errors);
+    }
+
+    // Uses every type and every optional field at least once.
+    fn full_conf() -> SyslinuxConf {
+        let mut conf = parse("UI menu.c32\n\
+                              PROMPT 1\n\
+                              MENU TITLE Boot menu\n\
+                              TIMEOUT 50\n\
+                              TOTALTIMEOUT 600\n\
+                              DEFAULT linux\n\
+                              ONTIMEOUT linux\n\
+                              ONERROR boot\n\
+                              APPEND quiet\n\
+                              SERIAL 0 115200\n\
+                              LABEL linux\n\
+                              \x20 KERNEL vmlinuz\n\
+                              \x20 INITRD initrd.img\n\
+                              \x20 FDTDIR dtbs\n\
+                              \x20 APPEND root=/dev/sda1\n\
+                              \x20 SAY Booting\n\
+                              \x20 DISPLAY boot.msg\n\
+                              \x20 MENU DEFAULT\n\
+                              \x20 TEXT HELP\n\
+                              \x20 Help\n\
+                              \x20 ENDTEXT\n\
+                              LABEL boot\n\
+                              \x20 BOOT bootsect.bin\n\
+                              LABEL bss\n\
+                              \x20 BSS bootsect.bin\n\
+                              LABEL pxe\n\
+                              \x20 PXE pxelinux.0\n\
+                              LABEL fdimage\n\
+                              \x20 FDIMAGE floppy.img\n\
+                              LABEL comboot\n\
+                              \x20 COMBOOT menu.com\n\
+                              LABEL com32\n\
+                              \x20 COM32 menu.c32\n\
+                              LABEL empty\n");
+
+        match conf.labels.get_mut("linux").unwrap().kernel_or_config {
+            KernelOrConfig::Kernel(ref mut kernel) => {
+                kernel.fdt = Some(std::path::PathBuf::from("board.dtb"));
+                kernel.fdt_overlays = vec![
+                    std::path::PathBuf::from("overlay.dtbo")];
+                kernel.linux_info = Some(LinuxKernelInfo{
+                    version: Some(String::from("6.1.0")),
+                    protocol: String::from("2.15"),
+                    load_flags: vec![String::from("LOADED_HIGH")],
+                    xload_flags: Vec::new(),
+                    handover_offset: Some(400),
+                });
+                kernel.initrd_info = Some(InitrdInfo{
+                    segments: vec![
+                        InitrdSegment{
+                            offset: 0,
+                            compression: Some(InitrdCompression::Gzip),
+                            entries: vec![CpioEntry{
+                                name: String::from("init"),
+                                mode: 0o100755,
+                                size: 10,
+                            }],
+                            uncompressed_size: 512,
+                            error: None,
+                        },
+                        InitrdSegment{
+                            offset: 512,
+                            compression: None,
+                            entries: Vec::new(),
+                            uncompressed_size: 0,
+                            error: Some(String::from("Unknown format")),
+                        },
+                    ],
+                    uncompressed_size: 512,
+                });
+            },
+        };
+        conf
+    }
+
+    fn diagnostics() -> Vec<Diagnostic> {
+        vec![
+            Diagnostic::new(Severity::Info, "info", String::from("Info")),
+            Diagnostic::new(Severity::Warning, "duplicate-label",
+                            String::from("Duplicate"))
+                .for_label("linux")
+                .at(Location::lines(10, 12))
+                .related_to(Location::line(3))
+                .in_file(std::path::Path::new("/boot/syslinux.cfg")),
+            Diagnostic::new(Severity::Error, "error", String::from("Error")),
+        ]
+    }
+
+    fn envelope(conf: Option<SyslinuxConf>) -> Envelope {
+        Envelope{
+            format_version: String::from(SCHEMA_VERSION),
+            conf_type: Some(String::from("ExtLinux")),
+            root_dir: Some(std::path::PathBuf::from("/")),
+            conf_file_path: Some(std::path::PathBuf::from(
+                "/boot/extlinux/extlinux.conf")),
+            included_files: vec![std::path::PathBuf::from(
+                "/boot/extlinux/menu.conf")],
+            diagnostics: diagnostics(),
+            conf: conf,
+        }
+    }
+
+    #[test]
+    fn schema_id_has_version() {
+        let schema: Value = serde_json::from_str(json_schema()).unwrap();
+        assert_eq!(get(&schema, "id"), Some(&Value::String(format!(
+            "urn:syslinux-conf:schema:{}", SCHEMA_VERSION))));
+    }
+
+    #[test]
+    fn conf_matches_schema() {
+        assert_valid(&full_conf(), json_schema());
+        assert_valid(&parse(""), json_schema());
+    }
+
+    #[test]
+    fn envelope_matches_schema() {
+        assert_valid(&envelope(Some(full_conf())), &envelope_json_schema());
+        assert_valid(&envelope(None), &envelope_json_schema());
+    }
+
+    #[test]
+    fn schema_rejects_other_values() {
+        let schema: Value = serde_json::from_str(json_schema()).unwrap();
+        let conf = serde_json::value::to_value(&full_conf());
+        let envelope = serde_json::value::to_value(&envelope(None));
+
+        assert!(!schema_errors(&envelope, &schema, &schema).is_empty());
+        let mut conf = conf.as_object().unwrap().clone();
+        conf.insert(String::from("unknown"), Value::Null);
+        assert!(!schema_errors(&Value::Object(conf), &schema, &schema)
+                .is_empty());
+    }
+
+    #[test]
+    fn envelope_schema_is_rooted_at_envelope() {
+        let schema: Value = serde_json::from_str(&envelope_json_schema())
+            .unwrap();
+        let conf = serde_json::value::to_value(&full_conf());
+
+        assert_eq!(get(&schema, "title"),
+                   Some(&Value::String(String::from("Envelope"))));
+        assert!(!schema_errors(&conf, &schema, &schema).is_empty());
+    }
+}
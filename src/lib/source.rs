@@ -0,0 +1,911 @@
+use std;
+
+extern crate nom;
+
+use edit::{EditError, KernelField, rename_command};
+use lexer;
+use lexer::Encoding;
+use parser::{DuplicateLabels, ParserOptions};
+use types::{Diagnostic, Label, Severity, SyslinuxConf};
+use writer::label_to_syslinux_cfg;
+
+use std::sync::atomic::{ATOMIC_USIZE_INIT, AtomicUsize, Ordering};
+
+const INDENT: &'static str = "    ";
+
+static NEXT_TEMP_ID: AtomicUsize = ATOMIC_USIZE_INIT;
+
+// Configuration together with its source lines. Edits change only the lines
+// they affect, so comments, formatting and unknown tags are kept. Every edit
+// is checked against the model first and the edited text is parsed again.
+
+#[derive(Debug, PartialEq)]
+enum LineKind {
+    Blank,
+    Comment,
+    // Line inside of TEXT HELP block, ENDTEXT included.
+    Text,
+    // Upper-case keyword, "MENU TITLE" and "TEXT HELP" include both words.
+    Directive(String),
+}
+
+fn split_keyword(line: &str) -> (&str, &str) {
+    let line = line.trim_left();
+    match line.find(char::is_whitespace) {
+        Some(index) => (&line[..index], line[index..].trim()),
+        None => (line, ""),
+    }
+}
+
+fn indent(line: &str) -> &str {
+    &line[..line.len() - line.trim_left().len()]
+}
+
+// Lines keep their endings, so that files with mixed line endings are
+// written back as they were.
+fn line_ending(line: &str) -> &str {
+    let content = line.trim_right_matches(|c| c == '\r' || c == '\n');
+    &line[content.len()..]
+}
+
+// Keeps indentation and spelling of keyword, and line ending.
+fn replace_value(line: &str, value: &str) -> String {
+    let keyword_end = indent(line).len() + split_keyword(line).0.len();
+    format!("{} {}{}", &line[..keyword_end], value, line_ending(line))
+}
+
+// Keywords are recognized like lexer does.
+fn classify(lines: &[String]) -> Vec<LineKind> {
+    let mut in_text = false;
+    lines.iter().map(|line| {
+        let trimmed = line.trim();
+        if in_text {
+            in_text = !lexer::ends_text(line);
+            LineKind::Text
+        } else if trimmed.is_empty() {
+            LineKind::Blank
+        } else if trimmed.starts_with('#') {
+            LineKind::Comment
+        } else {
+            in_text = lexer::starts_text(line);
+            LineKind::Directive(lexer::keyword(line))
+        }
+    }).collect()
+}
+
+fn is_directive(kind: &LineKind, keywords: &[&str]) -> bool {
+    match *kind {
+        LineKind::Directive(ref keyword) => {
+            keywords.iter().any(|k| *k == keyword.as_str())
+        },
+        _ => false,
+    }
+}
+
+fn is_insignificant(kind: &LineKind) -> bool {
+    *kind == LineKind::Blank || *kind == LineKind::Comment
+}
+
+struct Block {
+    name: String,
+    // Comment lines right before LABEL belong to the label.
+    start: usize,
+    label_line: usize,
+    // Trailing empty and comment lines are not part of the label.
+    end: usize,
+}
+
+fn blocks(lines: &[String], kinds: &[LineKind]) -> Vec<Block> {
+    let label_lines: Vec<usize> = (0..lines.len())
+        .filter(|&index| is_directive(&kinds[index], &["LABEL"]))
+        .collect();
+
+    let mut blocks: Vec<Block> = Vec::new();
+    for (i, &label_line) in label_lines.iter().enumerate() {
+        let lower = blocks.last().map_or(0, |block| block.end);
+        let mut start = label_line;
+        while start > lower && kinds[start - 1] == LineKind::Comment {
+            start -= 1;
+        }
+
+        let mut end = label_lines.get(i + 1).cloned().unwrap_or(lines.len());
+        while end > label_line + 1 && is_insignificant(&kinds[end - 1]) {
+            end -= 1;
+        }
+
+        blocks.push(Block{
+            name: String::from(split_keyword(&lines[label_line]).1),
+            start: start,
+            label_line: label_line,
+            end: end,
+        });
+    }
+    blocks
+}
+
+// Block of duplicate labels which parser keeps.
+fn find_block(lines: &[String], kinds: &[LineKind], name: &str,
+              options: &ParserOptions) -> Result<Block, EditError> {
+    let mut matching = blocks(lines, kinds).into_iter()
+        .filter(|block| block.name == name);
+    let block = match options.duplicate_labels {
+        DuplicateLabels::LastWins => matching.last(),
+        _ => matching.next(),
+    };
+    match block {
+        Some(block) => Ok(block),
+        None => Err(EditError::NoSuchLabel(String::from(name))),
+    }
+}
+
+// Everything before the first LABEL is in global scope.
+fn global_end(kinds: &[LineKind]) -> usize {
+    kinds.iter()
+        .position(|kind| is_directive(kind, &["LABEL"]))
+        .unwrap_or(kinds.len())
+}
+
+// Removes label together with empty lines after it.
+fn remove_block(lines: &mut Vec<String>, kinds: &[LineKind], block: &Block) {
+    let mut start = block.start;
+    let mut end = block.end;
+    while end < lines.len() && kinds[end] == LineKind::Blank {
+        end += 1;
+    }
+    if end == lines.len() {
+        while start > 0 && kinds[start - 1] == LineKind::Blank {
+            start -= 1;
+        }
+    }
+    lines.drain(start..end);
+}
+
+fn append_block(lines: &mut Vec<String>, block: Vec<String>) {
+    if lines.last().map_or(false, |line| !line.trim().is_empty()) {
+        lines.push(String::new());
+    }
+    lines.extend(block);
+}
+
+// New lines get line ending of document, the last line keeps having line
+// ending or not.
+fn end_lines(lines: &mut [String], new_ending: &str,
+             final_line_ending: bool) {
+    let count = lines.len();
+    for (index, line) in lines.iter_mut().enumerate() {
+        let has_ending = !line_ending(line).is_empty();
+        match (index + 1 < count || final_line_ending, has_ending) {
+            (true, false) => line.push_str(new_ending),
+            (false, true) => {
+                let length = line.len() - line_ending(line).len();
+                line.truncate(length);
+            },
+            _ => {},
+        };
+    }
+}
+
+// Text is decoded already.
+fn parse_conf(text: &str, options: &ParserOptions)
+              -> Result<(SyslinuxConf, Vec<Diagnostic>), EditError> {
+    let options = ParserOptions{
+        encoding: Encoding::UTF8,
+        ..options.clone()
+    };
+    let line_at = |remaining| lexer::line_at(text.as_bytes(), remaining);
+
+    match SyslinuxConf::parse_with_diagnostics(text.as_bytes(), &options) {
+        nom::IResult::Done(remaining, result) => match remaining.len() {
+            0 => Ok(result),
+            _ => Err(EditError::Reparse(Some(line_at(remaining)))),
+        },
+        _ => Err(EditError::Reparse(None)),
+    }
+}
+
+// Errors and skipped lines mean that text does not say what model does.
+fn problems(diagnostics: Vec<Diagnostic>) -> Vec<Diagnostic> {
+    diagnostics.into_iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error ||
+                             diagnostic.code == "invalid-line")
+        .collect()
+}
+
+// The first problem which was not there before edit. Lines may move, so
+// problems are compared by message.
+fn new_problem<'a>(old: &[Diagnostic], new: &'a [Diagnostic])
+                   -> Option<&'a Diagnostic> {
+    let mut old: Vec<(&str, &str)> = old.iter()
+        .map(|diagnostic| (diagnostic.code.as_str(),
+                           diagnostic.message.as_str()))
+        .collect();
+    new.iter().find(|diagnostic| {
+        let key = (diagnostic.code.as_str(), diagnostic.message.as_str());
+        match old.iter().position(|old_key| *old_key == key) {
+            Some(index) => {
+                old.remove(index);
+                false
+            },
+            None => true,
+        }
+    })
+}
+
+// Name is unique within process, files left by crashed processes are
+// skipped.
+fn create_temp_file(path: &std::path::Path)
+                    -> std::io::Result<(std::path::PathBuf, std::fs::File)> {
+    let file_name = match path.file_name() {
+        Some(file_name) => file_name,
+        None => return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput, "Path is not a file")),
+    };
+
+    loop {
+        let mut temp_name = std::ffi::OsString::from(".");
+        temp_name.push(file_name);
+        temp_name.push(format!(".{}.{}.tmp", std::process::id(),
+                               NEXT_TEMP_ID.fetch_add(1, Ordering::SeqCst)));
+        let temp_path = path.with_file_name(temp_name);
+
+        match std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&temp_path) {
+            Ok(temp_file) => return Ok((temp_path, temp_file)),
+            Err(err) => match err.kind() {
+                std::io::ErrorKind::AlreadyExists => continue,
+                _ => return Err(err),
+            },
+        };
+    }
+}
+
+// Rename is not durable until directory is synced as well.
+#[cfg(unix)]
+fn sync_dir(path: &std::path::Path) -> std::io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if dir != std::path::Path::new("") => dir,
+        _ => std::path::Path::new("."),
+    };
+    std::fs::File::open(dir).and_then(|dir| dir.sync_all())
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_: &std::path::Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+// Writing of Document::write_to, permissions are set if they are given.
+fn replace_file(path: &std::path::Path, bytes: &[u8],
+                permissions: Option<std::fs::Permissions>)
+                -> std::io::Result<()> {
+    use std::io::Write;
+
+    let (temp_path, mut temp_file) = try!(create_temp_file(path));
+    let result = temp_file.write_all(bytes)
+        .and_then(|_| temp_file.sync_all())
+        .and_then(|_| match permissions {
+            Some(permissions) => std::fs::set_permissions(&temp_path,
+                                                          permissions),
+            None => Ok(()),
+        })
+        .and_then(|_| std::fs::rename(&temp_path, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    result.and_then(|_| sync_dir(path))
+}
+
+// Like Document::write_to, but file does not have to exist. Permissions of
+// existing file are kept.
+pub fn write_file(path: &std::path::Path, bytes: &[u8])
+                  -> std::io::Result<()> {
+    let permissions = match std::fs::metadata(path) {
+        Ok(metadata) => Some(metadata.permissions()),
+        Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => None,
+        Err(err) => return Err(err),
+    };
+    replace_file(path, bytes, permissions)
+}
+
+#[derive(Debug, Clone)]
+pub struct Document {
+    // Lines with their line endings, like lexer splits them.
+    lines: Vec<String>,
+    // Line ending of new lines.
+    line_ending: &'static str,
+    final_line_ending: bool,
+    options: ParserOptions,
+    conf: SyslinuxConf,
+    // Problems of the original text, edits must not add more.
+    problems: Vec<Diagnostic>,
+}
+
+impl std::fmt::Display for Document {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.lines.concat())
+    }
+}
+
+impl Document {
+    // Text is decoded already, encoding of options is used when it is
+    // written to file.
+    pub fn parse(text: &str, options: &ParserOptions)
+                 -> Result<Document, EditError> {
+        let (conf, diagnostics) = try!(parse_conf(text, options));
+
+        let lines: Vec<String> = lexer::split_lines(text.as_bytes()).iter()
+            .map(|line| String::from_utf8_lossy(line).into_owned())
+            .collect();
+        let new_ending = match (text.contains("\r\n"), text.contains('\r')) {
+            (true, _) => "\r\n",
+            (false, true) => "\r",
+            (false, false) => "\n",
+        };
+
+        Ok(Document{
+            final_line_ending: lines.last()
+                .map_or(false, |line| !line_ending(line).is_empty()),
+            lines: lines,
+            line_ending: new_ending,
+            options: options.clone(),
+            conf: conf,
+            problems: problems(diagnostics),
+        })
+    }
+
+    // Bytes which are not valid UTF-8 would be replaced when document is
+    // written, so they are refused unless lossy decoding is allowed.
+    pub fn decode(bytes: &[u8], options: &ParserOptions, allow_lossy: bool)
+                  -> Result<Document, EditError> {
+        if options.encoding == Encoding::UTF8 && !allow_lossy {
+            if let Err(err) = std::str::from_utf8(bytes) {
+                let invalid = &bytes[err.valid_up_to()..];
+                return Err(EditError::InvalidEncoding(
+                    lexer::line_at(bytes, invalid)));
+            }
+        }
+        Document::parse(&lexer::decode(bytes, options.encoding), options)
+    }
+
+    // Configuration as written, paths are not resolved.
+    pub fn conf(&self) -> &SyslinuxConf {
+        &self.conf
+    }
+
+    pub fn options(&self) -> &ParserOptions {
+        &self.options
+    }
+
+    // All LABEL lines with line numbers, duplicates included.
+    pub fn label_lines(&self) -> Vec<(String, usize)> {
+        blocks(&self.lines, &classify(&self.lines)).into_iter()
+            .map(|block| (block.name, block.label_line + 1))
+            .collect()
+    }
+
+    fn update(&mut self, mut lines: Vec<String>) -> Result<(), EditError> {
+        end_lines(&mut lines, self.line_ending, self.final_line_ending);
+        let text = lines.concat();
+        let (conf, diagnostics) = try!(parse_conf(&text, &self.options));
+        let problems = problems(diagnostics);
+        if let Some(problem) = new_problem(&self.problems, &problems) {
+            return Err(EditError::Reparse(
+                problem.location.as_ref().map(|location| location.line)));
+        }
+        self.conf = conf;
+        self.problems = problems;
+        self.lines = lines;
+        Ok(())
+    }
+
+    // The last occurrence of tag is changed, because it is the one in effect.
+    fn set_global(&mut self, tag: &str, value: Option<&str>)
+                  -> Result<(), EditError> {
+        let mut lines = self.lines.clone();
+        let kinds = classify(&lines);
+        let global_end = global_end(&kinds);
+        let matching: Vec<usize> = (0..global_end)
+            .filter(|&index| is_directive(&kinds[index], &[tag]))
+            .collect();
+
+        match (value, matching.last()) {
+            (Some(value), Some(&index)) => {
+                lines[index] = replace_value(&lines[index], value);
+            },
+            (Some(value), None) => {
+                let first_directive = (0..global_end)
+                    .find(|&index| !is_insignificant(&kinds[index]));
+                match first_directive {
+                    Some(index) => {
+                        lines.insert(index, format!("{} {}", tag, value));
+                    },
+                    // Keep LABEL blocks separated from global scope.
+                    None => {
+                        let index = blocks(&lines, &kinds).first()
+                            .map_or(lines.len(), |block| block.start);
+                        if index < lines.len() {
+                            lines.insert(index, String::new());
+                        }
+                        lines.insert(index, format!("{} {}", tag, value));
+                    },
+                };
+            },
+            (None, _) => {
+                for &index in matching.iter().rev() {
+                    lines.remove(index);
+                }
+            },
+        };
+        self.update(lines)
+    }
+
+    pub fn set_default(&mut self, name: Option<&str>)
+                       -> Result<(), EditError> {
+        try!(self.conf.clone().set_default(name));
+        self.set_global("DEFAULT", name)
+    }
+
+    pub fn add_label(&mut self, name: &str, label: &Label)
+                     -> Result<(), EditError> {
+        try!(self.conf.clone().add_label(name, label.clone()));
+
+        let mut lines = self.lines.clone();
+        append_block(&mut lines, label_to_syslinux_cfg(name, label)
+            .lines()
+            .map(String::from)
+            .collect());
+        self.update(lines)
+    }
+
+    pub fn remove_label(&mut self, name: &str) -> Result<(), EditError> {
+        try!(self.conf.clone().remove_label(name));
+
+        let mut lines = self.lines.clone();
+        let kinds = classify(&lines);
+        let block = try!(find_block(&lines, &kinds, name,
+                                    &self.options));
+        remove_block(&mut lines, &kinds, &block);
+        self.update(lines)
+    }
+
+    pub fn rename_label(&mut self, old_name: &str, new_name: &str)
+                        -> Result<(), EditError> {
+        try!(self.conf.clone().rename_label(old_name, new_name));
+
+        let mut lines = self.lines.clone();
+        let kinds = classify(&lines);
+        let block = try!(find_block(&lines, &kinds, old_name,
+                                    &self.options));
+        lines[block.label_line] = replace_value(&lines[block.label_line],
+                                                new_name);
+
+        for index in 0..global_end(&kinds) {
+            if !is_directive(&kinds[index],
+                             &["DEFAULT", "ONTIMEOUT", "ONERROR"]) {
+                continue;
+            }
+            let command = Some(String::from(split_keyword(&lines[index]).1));
+            let mut renamed = command.clone();
+            rename_command(&mut renamed, old_name, new_name);
+            if renamed != command {
+                lines[index] = replace_value(&lines[index],
+                                             &renamed.unwrap());
+            }
+        }
+        self.update(lines)
+    }
+
+    pub fn move_label(&mut self, name: &str, index: usize)
+                      -> Result<(), EditError> {
+        let mut conf = self.conf.clone();
+        try!(conf.move_label(name, index));
+        let next_name = conf.labels.keys()
+            .skip_while(|label_name| *label_name != name)
+            .nth(1)
+            .cloned();
+
+        let mut lines = self.lines.clone();
+        let kinds = classify(&lines);
+        let block = try!(find_block(&lines, &kinds, name,
+                                    &self.options));
+        let moved = lines[block.start..block.end].to_vec();
+        remove_block(&mut lines, &kinds, &block);
+
+        match next_name {
+            Some(next_name) => {
+                let kinds = classify(&lines);
+                let next = try!(find_block(&lines, &kinds, &next_name,
+                                           &self.options));
+                let tail = lines.split_off(next.start);
+                lines.extend(moved);
+                lines.push(String::new());
+                lines.extend(tail);
+            },
+            None => append_block(&mut lines, moved),
+        };
+        self.update(lines)
+    }
+
+    // Tag of kernel file is kept, so label does not change its type. New
+    // kernel file is written as KERNEL.
+    pub fn set_kernel_field(&mut self, label_name: &str, field: KernelField,
+                            value: Option<&str>) -> Result<(), EditError> {
+        try!(self.conf.clone().set_kernel_field(label_name, field, value));
+
+        let mut lines = self.lines.clone();
+        let kinds = classify(&lines);
+        let block = try!(find_block(&lines, &kinds, label_name,
+                                    &self.options));
+        let tags = field.tags();
+        let matching: Vec<usize> = (block.label_line + 1..block.end)
+            .filter(|&index| is_directive(&kinds[index], tags))
+            .collect();
+
+        match (value.map(str::trim), matching.last()) {
+            (Some(value), Some(&index)) => {
+                lines[index] = replace_value(&lines[index], value);
+            },
+            (Some(value), None) => {
+                let line = {
+                    let label_indent = (block.label_line + 1..block.end)
+                        .filter(|&index| match kinds[index] {
+                            LineKind::Directive(ref keyword) => {
+                                keyword != "TEXT HELP"
+                            },
+                            _ => false,
+                        })
+                        .map(|index| indent(&lines[index]))
+                        .next()
+                        .unwrap_or(INDENT);
+                    format!("{}{} {}", label_indent, tags[0], value)
+                };
+                lines.insert(block.end, line);
+            },
+            (None, _) => {
+                for &index in matching.iter().rev() {
+                    lines.remove(index);
+                }
+            },
+        };
+        self.update(lines)
+    }
+
+    // APPEND of label is written even if the label inherited it.
+    pub fn add_param(&mut self, label_name: &str, param: &str)
+                     -> Result<(), EditError> {
+        let mut conf = self.conf.clone();
+        try!(conf.add_param(label_name, param));
+        self.set_append_of(label_name, &conf)
+    }
+
+    // "APPEND -" is written when no parameter is left.
+    pub fn remove_param(&mut self, label_name: &str, param: &str)
+                        -> Result<(), EditError> {
+        let mut conf = self.conf.clone();
+        try!(conf.remove_param(label_name, param));
+        self.set_append_of(label_name, &conf)
+    }
+
+    fn set_append_of(&mut self, label_name: &str, conf: &SyslinuxConf)
+                     -> Result<(), EditError> {
+        let append = conf.labels[label_name].kernel().append.clone();
+        self.set_kernel_field(label_name, KernelField::Append,
+                              append.as_ref().map(String::as_str))
+    }
+
+    // Writes into temporary file next to path and renames it over path, so
+    // nobody sees partially written configuration. Permissions of the
+    // original file are kept.
+    pub fn write_to(&self, path: &std::path::Path) -> std::io::Result<()> {
+        let permissions = try!(std::fs::metadata(path)).permissions();
+        let bytes = lexer::encode(&self.to_string(), self.options.encoding);
+        replace_file(path, &bytes, Some(permissions))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std;
+
+    use super::{Document, write_file};
+    use edit::{EditError, KernelField};
+    use lexer::Encoding;
+    use parser::ParserOptions;
+    use test_util::TempDir;
+
+    const CONF: &'static str = "# Boot menu\r\n\
+                                DEFAULT linux\r\n\
+                                \r\n\
+                                # Default kernel\r\n\
+                                LABEL linux\r\n\
+                                \tKERNEL vmlinuz\r\n\
+                                \tAPPEND root=/dev/sda1\r\n\
+                                \r\n\
+                                LABEL rescue\r\n\
+                                \tKERNEL vmlinuz\r\n";
+
+    fn document(text: &str) -> Document {
+        Document::parse(text, &ParserOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn keeps_unchanged_text() {
+        assert_eq!(document(CONF).to_string(), CONF);
+        assert_eq!(document("LABEL linux").to_string(), "LABEL linux");
+        assert_eq!(document("").to_string(), "");
+    }
+
+    #[test]
+    fn keeps_line_endings_of_every_line() {
+        let mut document = document("DEFAULT linux\r\
+                                     LABEL linux\r\
+                                     \tKERNEL vmlinuz\n\
+                                     LABEL rescue\r\n\
+                                     \tKERNEL vmlinuz");
+        assert_eq!(document.label_lines(),
+                   vec![(String::from("linux"), 2),
+                        (String::from("rescue"), 4)]);
+
+        document.set_default(Some("rescue")).unwrap();
+        document.set_kernel_field("linux", KernelField::InitRD,
+                                  Some("initrd.img")).unwrap();
+        assert_eq!(document.to_string(),
+                   "DEFAULT rescue\r\
+                    LABEL linux\r\
+                    \tKERNEL vmlinuz\n\
+                    \tINITRD initrd.img\r\n\
+                    LABEL rescue\r\n\
+                    \tKERNEL vmlinuz");
+    }
+
+    #[test]
+    fn ends_new_lines_like_carriage_return_only_file() {
+        let mut document = document("LABEL linux\r\tKERNEL vmlinuz\r");
+
+        document.add_param("linux", "quiet").unwrap();
+        assert_eq!(document.to_string(),
+                   "LABEL linux\r\tKERNEL vmlinuz\r\tAPPEND quiet\r");
+    }
+
+    #[test]
+    fn changes_only_affected_lines() {
+        let mut document = document(CONF);
+
+        document.set_default(Some("rescue")).unwrap();
+        document.add_param("linux", "quiet").unwrap();
+        document.set_kernel_field("rescue", KernelField::InitRD,
+                                  Some("initrd.img")).unwrap();
+        assert_eq!(document.to_string(),
+                   "# Boot menu\r\n\
+                    DEFAULT rescue\r\n\
+                    \r\n\
+                    # Default kernel\r\n\
+                    LABEL linux\r\n\
+                    \tKERNEL vmlinuz\r\n\
+                    \tAPPEND root=/dev/sda1 quiet\r\n\
+                    \r\n\
+                    LABEL rescue\r\n\
+                    \tKERNEL vmlinuz\r\n\
+                    \tINITRD initrd.img\r\n");
+        assert_eq!(document.conf().global.default,
+                   Some(String::from("rescue")));
+    }
+
+    #[test]
+    fn writes_inherited_params() {
+        let mut document = document("APPEND root=/dev/sda1\n\
+                                     LABEL linux\n\
+                                     \tKERNEL vmlinuz\n\
+                                     LABEL rescue\n\
+                                     \tKERNEL vmlinuz\n\
+                                     \tAPPEND single\n");
+
+        document.add_param("linux", "quiet").unwrap();
+        document.remove_param("rescue", "single").unwrap();
+        assert_eq!(document.to_string(),
+                   "APPEND root=/dev/sda1\n\
+                    LABEL linux\n\
+                    \tKERNEL vmlinuz\n\
+                    \tAPPEND root=/dev/sda1 quiet\n\
+                    LABEL rescue\n\
+                    \tKERNEL vmlinuz\n\
+                    \tAPPEND -\n");
+    }
+
+    #[test]
+    fn keeps_tag_of_kernel_file() {
+        let mut document = document("LABEL memtest\n\
+                                     \tLINUX memtest.bin\n\
+                                     LABEL menu\n");
+
+        document.set_kernel_field("memtest", KernelField::KernelFile,
+                                  Some("memtest.c32")).unwrap();
+        document.set_kernel_field("menu", KernelField::KernelFile,
+                                  Some("menu.c32")).unwrap();
+        assert_eq!(document.to_string(),
+                   "LABEL memtest\n\
+                    \tLINUX memtest.c32\n\
+                    LABEL menu\n    KERNEL menu.c32\n");
+    }
+
+    #[test]
+    fn moves_label_with_its_comments() {
+        let mut document = document(CONF);
+
+        document.move_label("rescue", 0).unwrap();
+        assert_eq!(document.to_string(),
+                   "# Boot menu\r\n\
+                    DEFAULT linux\r\n\
+                    \r\n\
+                    LABEL rescue\r\n\
+                    \tKERNEL vmlinuz\r\n\
+                    \r\n\
+                    # Default kernel\r\n\
+                    LABEL linux\r\n\
+                    \tKERNEL vmlinuz\r\n\
+                    \tAPPEND root=/dev/sda1\r\n");
+    }
+
+    #[test]
+    fn renames_label_and_references() {
+        let mut document = document(CONF);
+
+        document.rename_label("linux", "default").unwrap();
+        let text = document.to_string();
+        assert!(text.contains("DEFAULT default\r\n"));
+        assert!(text.contains("LABEL default\r\n"));
+        assert!(document.remove_label("default").is_err());
+        document.remove_label("rescue").unwrap();
+        assert!(!document.to_string().contains("rescue"));
+    }
+
+    #[test]
+    fn rejects_edit_which_can_not_be_parsed() {
+        let mut options = ParserOptions::default();
+        options.strict = true;
+        let mut document = Document::parse(CONF, &options).unwrap();
+
+        match document.set_kernel_field("linux", KernelField::KernelFile,
+                                        Some("")) {
+            Err(EditError::Reparse(Some(6))) => (),
+            result => panic!("Unexpected result {:?}", result),
+        };
+        match document.set_kernel_field("linux", KernelField::FDT,
+                                        Some("board.dtb")) {
+            Err(EditError::Reparse(Some(8))) => (),
+            result => panic!("Unexpected result {:?}", result),
+        };
+        assert_eq!(document.to_string(), CONF);
+    }
+
+    #[test]
+    fn keeps_problems_of_original_text() {
+        let mut options = ParserOptions::default();
+        options.strict = true;
+        let text = format!("{}\tFOO bar\r\n", CONF);
+        let mut document = Document::parse(&text, &options).unwrap();
+
+        document.set_default(Some("rescue")).unwrap();
+        assert!(document.to_string().ends_with("\tFOO bar\r\n"));
+    }
+
+    #[test]
+    fn writes_file_atomically() {
+        let dir = TempDir::new();
+        let path = dir.write("syslinux.cfg", CONF.as_bytes());
+        let mut document = document(CONF);
+        document.set_default(Some("rescue")).unwrap();
+
+        document.write_to(&path).unwrap();
+        let mut names: Vec<std::ffi::OsString> =
+            std::fs::read_dir(dir.path()).unwrap()
+                .map(|entry| entry.unwrap().file_name())
+                .collect();
+        names.sort();
+        assert_eq!(names, vec![std::ffi::OsString::from("syslinux.cfg")]);
+
+        let mut text = String::new();
+        {
+            use std::io::Read;
+            std::fs::File::open(&path).unwrap()
+                .read_to_string(&mut text).unwrap();
+        }
+        assert_eq!(text, document.to_string());
+    }
+
+    #[test]
+    fn skips_stale_temp_files() {
+        let dir = TempDir::new();
+        let path = dir.write("syslinux.cfg", CONF.as_bytes());
+        let stale_name = format!(".syslinux.cfg.{}.0.tmp",
+                                 std::process::id());
+        let stale = dir.write(&stale_name, b"stale");
+        let old_stale = dir.write(".syslinux.cfg.tmp", b"stale");
+
+        document(CONF).write_to(&path).unwrap();
+        assert!(stale.exists());
+        assert!(old_stale.exists());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 3);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new();
+        let path = dir.write("syslinux.cfg", CONF.as_bytes());
+        std::fs::set_permissions(
+            &path, std::fs::Permissions::from_mode(0o600)).unwrap();
+
+        document(CONF).write_to(&path).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn writes_in_original_encoding() {
+        let dir = TempDir::new();
+        let path = dir.write("syslinux.cfg", b"");
+        let mut options = ParserOptions::default();
+        options.encoding = Encoding::Latin1;
+        let document = Document::parse("MENU TITLE Men\u{fc}\n", &options)
+            .unwrap();
+
+        document.write_to(&path).unwrap();
+        let mut bytes = Vec::new();
+        {
+            use std::io::Read;
+            std::fs::File::open(&path).unwrap()
+                .read_to_end(&mut bytes).unwrap();
+        }
+        assert_eq!(bytes, b"MENU TITLE Men\xfc\n".to_vec());
+    }
+
+    #[test]
+    fn refuses_lossy_decoding() {
+        let bytes = b"DEFAULT linux\nMENU TITLE Men\xfc\n";
+        let mut options = ParserOptions::default();
+
+        match Document::decode(bytes, &options, false) {
+            Err(EditError::InvalidEncoding(2)) => (),
+            result => panic!("Unexpected result {:?}", result),
+        };
+        let document = Document::decode(bytes, &options, true).unwrap();
+        assert!(document.to_string().ends_with("Men\u{fffd}\n"));
+
+        options.encoding = Encoding::Latin1;
+        let document = Document::decode(bytes, &options, false).unwrap();
+        assert_eq!(document.conf().global.menu_title,
+                   Some(String::from("Men\u{fc}")));
+    }
+
+    #[test]
+    fn fails_without_original_file() {
+        let dir = TempDir::new();
+        let path = dir.path().join("syslinux.cfg");
+
+        assert!(document(CONF).write_to(&path).is_err());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn writes_new_file() {
+        let dir = TempDir::new();
+        let path = dir.path().join("syslinux.cfg");
+
+        write_file(&path, CONF.as_bytes()).unwrap();
+        write_file(&path, b"DEFAULT rescue\n").unwrap();
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        let mut text = String::new();
+        {
+            use std::io::Read;
+            std::fs::File::open(&path).unwrap()
+                .read_to_string(&mut text).unwrap();
+        }
+        assert_eq!(text, "DEFAULT rescue\n");
+    }
+}
@@ -0,0 +1,292 @@
+use std;
+
+use lexer;
+use parser::{LabelAction, LabelNames, ParserOptions, parse_section};
+use types::{Diagnostic, Global, Label};
+
+// Parts of configuration in order they are read.
+#[derive(Debug, Clone)]
+pub enum StreamEvent {
+    // Comes once, before labels.
+    Global(Global),
+    Label(String, Label),
+    // Duplicate label which takes place of the previous label of the same
+    // name, if the last of duplicates wins.
+    ReplacedLabel(String, Label),
+    Diagnostic(Diagnostic),
+}
+
+// Incremental parser for configurations too large to be kept in memory,
+// e.g. menus generated for thousands of hosts. Input is split into sections
+// at LABEL lines and every section is parsed as soon as the next one starts,
+// so only lines of the current label and names of the previous labels are
+// kept. Lines end with "\n", "\r\n" or bare "\r" like in the batch parser,
+// so events give the same configuration and diagnostics as
+// SyslinuxConf::parse_with_diagnostics().
+pub struct StreamParser {
+    options: ParserOptions,
+    names: LabelNames,
+    // Complete lines of the current section as they were read.
+    section: Vec<u8>,
+    section_line: usize,
+    // Line which has not ended yet, or which ends with "\r" that may be
+    // followed by "\n" in the next chunk.
+    partial: Vec<u8>,
+    next_line: usize,
+    // LABEL inside of TEXT HELP block does not start a section.
+    in_text: bool,
+    global_done: bool,
+    stopped_at: Option<usize>,
+}
+
+impl StreamParser {
+    pub fn new(options: &ParserOptions) -> StreamParser {
+        StreamParser{
+            options: options.clone(),
+            names: LabelNames::new(),
+            section: Vec::new(),
+            section_line: 1,
+            partial: Vec::new(),
+            next_line: 1,
+            in_text: false,
+            global_done: false,
+            stopped_at: None,
+        }
+    }
+
+    // Events of sections completed by chunk.
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<StreamEvent> {
+        let mut events = Vec::new();
+        self.partial.extend_from_slice(chunk);
+        // Line may have started in the previous chunk.
+        let mut start = 0;
+        while let Some(length) = lexer::line_length(&self.partial[start..],
+                                                    false) {
+            let line = self.partial[start..start + length].to_vec();
+            start += length;
+            self.push_line(line, &mut events);
+        }
+        self.partial.drain(..start);
+        events
+    }
+
+    // Input ended, events of the last section.
+    pub fn finish(&mut self) -> Vec<StreamEvent> {
+        let mut events = Vec::new();
+        // Bare "\r" at the end of input ends the last line.
+        let partial = std::mem::replace(&mut self.partial, Vec::new());
+        for line in lexer::split_lines(&partial) {
+            self.push_line(line.to_vec(), &mut events);
+        }
+        if self.stopped_at.is_none() {
+            self.flush_section(&mut events);
+        }
+        events
+    }
+
+    // Line of duplicate label parsing stopped at, if duplicates are errors.
+    // Input after it is ignored, like rest of input of the batch parser.
+    pub fn stopped_at(&self) -> Option<usize> {
+        self.stopped_at
+    }
+
+    fn push_line(&mut self, line: Vec<u8>, events: &mut Vec<StreamEvent>) {
+        if self.stopped_at.is_some() {
+            return;
+        }
+
+        let decoded = lexer::decode(&line, self.options.encoding);
+        if !self.in_text && lexer::starts_label(&decoded) {
+            self.flush_section(events);
+        }
+        self.in_text = match self.in_text {
+            true => !lexer::ends_text(&decoded),
+            false => lexer::starts_text(&decoded),
+        };
+
+        self.section.extend(line);
+        self.next_line += 1;
+    }
+
+    fn flush_section(&mut self, events: &mut Vec<StreamEvent>) {
+        let section = std::mem::replace(&mut self.section, Vec::new());
+        let (rest, parsed) = parse_section(&section, self.section_line,
+                                           &self.options, &mut self.names);
+        if !rest.is_empty() {
+            self.stopped_at = Some(
+                self.section_line + lexer::line_at(&section, rest) - 1);
+        }
+
+        events.extend(parsed.diagnostics.into_iter()
+            .map(StreamEvent::Diagnostic));
+        if !self.global_done {
+            self.global_done = true;
+            events.push(StreamEvent::Global(parsed.global));
+        }
+        for (label_name, label, action) in parsed.labels {
+            match action {
+                LabelAction::Add => {
+                    events.push(StreamEvent::Label(label_name, label))
+                },
+                LabelAction::Replace => {
+                    events.push(StreamEvent::ReplacedLabel(label_name, label))
+                },
+                LabelAction::Ignore => {},
+            };
+        }
+
+        self.section_line = self.next_line;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate nom;
+    extern crate serde_json;
+
+    use super::{StreamEvent, StreamParser};
+    use lexer;
+    use parser::{DuplicateLabels, ParserOptions};
+    use types::{Diagnostic, Global, Labels, Location, SyslinuxConf};
+
+    // Every kind of line ending, help text with LABEL and duplicate label.
+    const CONF: &'static [u8] = b"# Boot menu\r\n\
+                                  DEFAULT linux\r\
+                                  SERIAL 0\n\
+                                  TIMEOUT soon\r\n\
+                                  LABEL linux\r\
+                                  \x20 KERNEL vmlinuz\r\n\
+                                  \x20 TEXT HELP\n\
+                                  LABEL is not a label here\r\
+                                  \x20 ENDTEXT\r\n\
+                                  \x20 DEFAULT rescue\n\
+                                  LABEL rescue\r\
+                                  \x20 KERNEL vmlinuz\r\
+                                  LABEL linux\n\
+                                  \x20 KERNEL vmlinuz.new\r";
+
+    const CR_ONLY: &'static [u8] = b"DEFAULT linux\r\
+                                     LABEL linux\r\
+                                     \x20 KERNEL vmlinuz\r\
+                                     LABEL rescue\r\
+                                     \x20 KERNEL vmlinuz\r";
+
+    // Configuration, diagnostics and line parsing stopped at.
+    type Parsed = (SyslinuxConf, Vec<Diagnostic>, Option<usize>);
+
+    // Configuration and diagnostics as JSON, to be compared.
+    fn to_json(parsed: &Parsed) -> (String, String, Option<usize>) {
+        (serde_json::to_string(&parsed.0).unwrap(),
+         serde_json::to_string(&parsed.1).unwrap(),
+         parsed.2)
+    }
+
+    fn parse_batch(input: &[u8], options: &ParserOptions) -> Parsed {
+        match SyslinuxConf::parse_with_diagnostics(input, options) {
+            nom::IResult::Done(rest, (conf, diagnostics)) => {
+                let stopped_at = match rest.is_empty() {
+                    true => None,
+                    false => Some(lexer::line_at(input, rest)),
+                };
+                (conf, diagnostics, stopped_at)
+            },
+            _ => panic!("Unable to parse configuration"),
+        }
+    }
+
+    fn parse_stream(input: &[u8], chunk_size: usize, options: &ParserOptions)
+                    -> Parsed {
+        let mut parser = StreamParser::new(options);
+        let mut events = Vec::new();
+        for chunk in input.chunks(chunk_size) {
+            events.extend(parser.feed(chunk));
+        }
+        events.extend(parser.finish());
+
+        let mut conf = SyslinuxConf{
+            global: Global::default(),
+            labels: Labels::new(),
+        };
+        let mut diagnostics = Vec::new();
+        for event in events {
+            match event {
+                StreamEvent::Global(global) => conf.global = global,
+                StreamEvent::Label(label_name, label) => {
+                    conf.labels.insert(label_name, label);
+                },
+                StreamEvent::ReplacedLabel(label_name, label) => {
+                    if let Some(previous) = conf.labels.get_mut(&label_name) {
+                        *previous = label;
+                    }
+                },
+                StreamEvent::Diagnostic(diagnostic) => {
+                    diagnostics.push(diagnostic)
+                },
+            };
+        }
+        (conf, diagnostics, parser.stopped_at())
+    }
+
+    fn parser_options(policy: DuplicateLabels) -> ParserOptions {
+        let mut options = ParserOptions::default();
+        options.duplicate_labels = policy;
+        options
+    }
+
+    #[test]
+    fn gives_the_same_result_as_batch_parser() {
+        let policies = [DuplicateLabels::FirstWins, DuplicateLabels::LastWins,
+                        DuplicateLabels::Error];
+        for input in &[CONF, CR_ONLY] {
+            for policy in &policies {
+                let options = parser_options(*policy);
+                let expected = to_json(&parse_batch(input, &options));
+                for chunk_size in 1..input.len() + 1 {
+                    let result = parse_stream(input, chunk_size, &options);
+                    assert_eq!(to_json(&result), expected,
+                               "{:?}, chunk size {}", policy, chunk_size);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn counts_lines_with_any_line_ending() {
+        let options = parser_options(DuplicateLabels::LastWins);
+        for parsed in &[parse_batch(CONF, &options),
+                        parse_stream(CONF, 5, &options)] {
+            let (ref conf, ref diagnostics, _) = *parsed;
+            let found: Vec<(&str, Location)> = diagnostics.iter()
+                .map(|diagnostic| (diagnostic.code.as_str(),
+                                   diagnostic.location.clone().unwrap()))
+                .collect();
+            assert_eq!(found,
+                       vec![("unknown-tag", Location::line(3)),
+                            ("invalid-number", Location::line(4)),
+                            ("wrong-scope", Location::line(10)),
+                            ("duplicate-label", Location::lines(13, 14))]);
+            assert_eq!(diagnostics[3].related_locations,
+                       vec![Location::line(5)]);
+            // Invalid TIMEOUT is not kept.
+            assert_eq!(conf.global.unknown_directives.len(), 1);
+            assert_eq!(conf.global.unknown_directives[0].line, 3);
+        }
+
+        let options = parser_options(DuplicateLabels::Error);
+        assert_eq!(parse_batch(CONF, &options).2, Some(13));
+        assert_eq!(parse_stream(CONF, 5, &options).2, Some(13));
+    }
+
+    #[test]
+    fn ends_lines_with_bare_carriage_return() {
+        for chunk_size in &[1, 2, 7, CR_ONLY.len()] {
+            let (conf, _, _) = parse_stream(CR_ONLY, *chunk_size,
+                                            &ParserOptions::default());
+            let label_names: Vec<&str> = conf.labels.keys()
+                .map(|name| name.as_str())
+                .collect();
+            assert_eq!(label_names, vec!["linux", "rescue"]);
+            assert_eq!(conf.global.default, Some(String::from("linux")));
+        }
+    }
+}
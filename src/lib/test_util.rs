@@ -0,0 +1,57 @@
+use std;
+
+use nom;
+use types::SyslinuxConf;
+
+use std::sync::atomic::{ATOMIC_USIZE_INIT, AtomicUsize, Ordering};
+
+static NEXT_ID: AtomicUsize = ATOMIC_USIZE_INIT;
+
+// Directory for files of single test, removed with everything inside when
+// test finishes.
+pub struct TempDir {
+    path: std::path::PathBuf,
+}
+
+impl TempDir {
+    pub fn new() -> TempDir {
+        let path = std::env::temp_dir().join(format!(
+            "syslinux-conf-test-{}-{}",
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::SeqCst)));
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir{
+            path: path,
+        }
+    }
+
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+
+    pub fn write(&self, name: &str, data: &[u8]) -> std::path::PathBuf {
+        use std::io::Write;
+
+        let path = self.path.join(name);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).unwrap();
+        }
+        let mut file = std::fs::File::create(&path).unwrap();
+        file.write_all(data).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+// Configuration read with default parser options.
+pub fn parse(text: &str) -> SyslinuxConf {
+    match SyslinuxConf::parse(text.as_bytes()) {
+        nom::IResult::Done(_, conf) => conf,
+        _ => panic!("Unable to parse configuration:\n{}", text),
+    }
+}
@@ -0,0 +1,168 @@
+extern crate serde;
+extern crate serde_json;
+extern crate toml;
+
+use self::serde::Serialize;
+use self::serde_json::Value;
+
+// TOML has no null: None fields are omitted, but there is no way to write
+// null inside of array.
+fn remove_nulls(value: Value) -> Result<Value, String> {
+    match value {
+        Value::Object(map) => {
+            let mut table = serde_json::Map::new();
+            for (key, value) in map.into_iter() {
+                if !value.is_null() {
+                    table.insert(key, try!(remove_nulls(value)));
+                }
+            }
+            Ok(Value::Object(table))
+        },
+        Value::Array(items) => {
+            let mut array = Vec::new();
+            for item in items.into_iter() {
+                match item {
+                    Value::Null => return Err(String::from(
+                        "TOML can not represent null inside of array")),
+                    item => array.push(try!(remove_nulls(item))),
+                };
+            }
+            Ok(Value::Array(array))
+        },
+        value => Ok(value),
+    }
+}
+
+// Keys of TOML tables are sorted, but order of labels is order of boot menu,
+// so labels are written as array of tables with names.
+fn labels_to_array(value: Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(map.into_iter()
+            .map(|(key, value)| {
+                let value = match (key.as_str(), value) {
+                    ("labels", Value::Object(labels)) => {
+                        Value::Array(labels.into_iter()
+                            .map(|(name, label)| named_table(name, label))
+                            .collect())
+                    },
+                    (_, value) => labels_to_array(value),
+                };
+                (key, value)
+            })
+            .collect()),
+        Value::Array(items) => {
+            Value::Array(items.into_iter().map(labels_to_array).collect())
+        },
+        value => value,
+    }
+}
+
+fn named_table(name: String, value: Value) -> Value {
+    let mut table = serde_json::Map::new();
+    table.insert(String::from("name"), Value::String(name));
+    if let Value::Object(fields) = labels_to_array(value) {
+        table.extend(fields);
+    }
+    Value::Object(table)
+}
+
+// Error if value is not serialized as a map, TOML document is always a
+// table.
+pub fn to_toml<T: Serialize>(value: &T) -> Result<String, String> {
+    let value = match serde_json::value::to_value(value) {
+        value @ Value::Object(_) => {
+            labels_to_array(try!(remove_nulls(value)))
+        },
+        _ => return Err(String::from("TOML document must be a table")),
+    };
+
+    let mut encoder = toml::Encoder::new();
+    try!(value.serialize(&mut encoder).map_err(|err| format!("{}", err)));
+    Ok(format!("{}", toml::Value::Table(encoder.toml)))
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate toml;
+
+    use std;
+
+    use super::to_toml;
+    use test_util::parse;
+
+    const CONF: &'static str = "DEFAULT linux\n\
+                                TIMEOUT 50\n\
+                                LABEL linux\n\
+                                \x20 KERNEL vmlinuz\n\
+                                \x20 APPEND root=/dev/sda1 quiet\n\
+                                LABEL rescue\n\
+                                \x20 LINUX vmlinuz\n\
+                                \x20 APPEND \"single\"\n";
+
+    fn read(text: &str) -> toml::Value {
+        let mut parser = toml::Parser::new(text);
+        match parser.parse() {
+            Some(table) => toml::Value::Table(table),
+            None => panic!("Invalid TOML {:?}:\n{}", parser.errors, text),
+        }
+    }
+
+    #[test]
+    fn writes_configuration() {
+        let toml = read(&to_toml(&parse(CONF)).unwrap());
+
+        assert_eq!(toml.lookup("global.default").and_then(|v| v.as_str()),
+                   Some("linux"));
+        assert_eq!(toml.lookup("global.timeout").and_then(|v| v.as_float()),
+                   Some(5.0));
+        assert_eq!(toml.lookup("labels.0.kernel_or_config.Kernel.append")
+                       .and_then(|v| v.as_str()),
+                   Some("root=/dev/sda1 quiet"));
+        assert_eq!(toml.lookup("labels.1.kernel_or_config.Kernel.append")
+                       .and_then(|v| v.as_str()),
+                   Some("\"single\""));
+    }
+
+    #[test]
+    fn omits_null_fields() {
+        let toml = read(&to_toml(&parse(CONF)).unwrap());
+
+        assert!(toml.lookup("global.ontimeout").is_none());
+        assert!(toml.lookup("labels.0.say").is_none());
+    }
+
+    #[test]
+    fn keeps_order_of_labels() {
+        let conf = parse("LABEL zeta\n\
+                          \x20 KERNEL vmlinuz\n\
+                          LABEL alpha\n\
+                          \x20 KERNEL vmlinuz\n\
+                          LABEL mid\n\
+                          \x20 KERNEL vmlinuz\n");
+        let toml = read(&to_toml(&conf).unwrap());
+
+        let names: Vec<&str> = toml.lookup("labels")
+            .and_then(|labels| labels.as_slice())
+            .unwrap()
+            .iter()
+            .map(|label| label.lookup("name").unwrap().as_str().unwrap())
+            .collect();
+        assert_eq!(names, vec!["zeta", "alpha", "mid"]);
+    }
+
+    #[test]
+    fn rejects_null_in_array() {
+        let value: Vec<(&str, Vec<Option<u32>>)> =
+            vec![("items", vec![Some(1), None])];
+        let map: std::collections::BTreeMap<_, _> =
+            value.into_iter().collect();
+
+        assert!(to_toml(&map).is_err());
+    }
+
+    #[test]
+    fn rejects_non_table() {
+        assert!(to_toml(&vec![1, 2]).is_err());
+        assert!(to_toml(&"text").is_err());
+    }
+}
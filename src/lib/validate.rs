@@ -0,0 +1,421 @@
+use std;
+
+use edit::command_label;
+use types::{Diagnostic, Global, KernelFile, KernelFileSource, KernelOrConfig,
+            Label, Severity, SyslinuxConf, UnknownDirective};
+
+// Timeouts are stored in units of 1/10s as u32.
+const MAX_TIMEOUT: f64 = std::u32::MAX as f64 / 10.0;
+
+fn has_line_break(value: &str) -> bool {
+    value.contains('\n') || value.contains('\r')
+}
+
+struct Validator {
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Validator {
+    fn diagnostic(&mut self, severity: Severity, label_name: Option<&str>,
+                  code: &str, message: String) {
+        let diagnostic = Diagnostic::new(severity, code, message);
+        self.diagnostics.push(match label_name {
+            Some(label_name) => diagnostic.for_label(label_name),
+            None => diagnostic,
+        });
+    }
+
+    // Syslinux configuration is line-based and values are trimmed.
+    fn value(&mut self, label_name: Option<&str>, tag: &str, value: &str) {
+        if has_line_break(value) {
+            self.diagnostic(Severity::Error, label_name, "value-line-break",
+                            format!("{} contains line break", tag));
+        } else if value.trim() != value {
+            self.diagnostic(Severity::Warning, label_name, "value-whitespace",
+                            format!("Leading and trailing whitespace of {} \
+                                     will be lost", tag));
+        }
+    }
+
+    fn path(&mut self, label_name: Option<&str>, tag: &str,
+            path: &std::path::Path) {
+        match path.to_str() {
+            Some(path) => self.value(label_name, tag, path),
+            None => self.diagnostic(
+                Severity::Error, label_name, "path-encoding",
+                format!("{} is not valid UTF-8", tag)),
+        }
+    }
+
+    fn timeout(&mut self, tag: &str, timeout: Option<f64>) {
+        let timeout = match timeout {
+            Some(timeout) => timeout,
+            None => return,
+        };
+
+        if !timeout.is_finite() || timeout < 0.0 || timeout > MAX_TIMEOUT {
+            self.diagnostic(Severity::Error, None, "timeout-range",
+                            format!("{} {} is out of range", tag, timeout));
+        } else if timeout == 0.0 {
+            self.diagnostic(Severity::Warning, None, "timeout-zero",
+                            format!("{} 0 disables timeout in syslinux, but \
+                                     boots immediately in U-Boot", tag));
+        } else if (timeout * 10.0).round() != timeout * 10.0 {
+            self.diagnostic(Severity::Warning, None, "timeout-precision",
+                            format!("{} {} will be rounded to 1/10s", tag,
+                                    timeout));
+        }
+    }
+
+    // Keyword is the first word of line, the rest is argument.
+    fn unknown_directives(&mut self, label_name: Option<&str>,
+                          directives: &[UnknownDirective]) {
+        for directive in directives {
+            let keyword = &directive.keyword;
+            if keyword.is_empty() || keyword.contains(char::is_whitespace) ||
+                    keyword.starts_with('#') {
+                self.diagnostic(Severity::Error, label_name,
+                                "directive-keyword", format!(
+                                    "\"{}\" is not a keyword", keyword));
+            }
+            self.value(label_name, keyword, &directive.argument);
+        }
+    }
+
+    fn label(&mut self, label_name: Option<&str>, label: &Label) {
+        match label.kernel_or_config {
+            KernelOrConfig::Kernel(ref kernel) => {
+                if let Some(ref kernel_file) = kernel.kernel_file {
+                    self.path(label_name, "kernel file", kernel_file.path());
+
+                    // Type of file after KERNEL tag is guessed again when
+                    // configuration is read back.
+                    let guessed = KernelFile::guess(kernel_file.path().clone());
+                    if kernel.kernel_file_source ==
+                            Some(KernelFileSource::Extension) &&
+                            guessed != *kernel_file {
+                        self.diagnostic(
+                            Severity::Error, label_name, "kernel-extension",
+                            format!("Kernel file is {:?}, but its extension \
+                                     means {:?}", kernel_file, guessed));
+                    }
+                }
+                if let Some(ref initrd) = kernel.initrd {
+                    self.path(label_name, "INITRD", initrd);
+                }
+                if let Some(ref fdt) = kernel.fdt {
+                    self.path(label_name, "FDT", fdt);
+                }
+                if let Some(ref fdt_dir) = kernel.fdt_dir {
+                    self.path(label_name, "FDTDIR", fdt_dir);
+                }
+                for fdt_overlay in &kernel.fdt_overlays {
+                    self.path(label_name, "FDTOVERLAYS", fdt_overlay);
+                    if fdt_overlay.to_string_lossy().contains(' ') {
+                        self.diagnostic(
+                            Severity::Error, label_name, "fdt-overlay-space",
+                            String::from("FDTOVERLAYS is space-separated, \
+                                          paths can not contain spaces"));
+                    }
+                }
+                if let Some(ref append) = kernel.append {
+                    self.value(label_name, "APPEND", append);
+                }
+                if kernel.linux_info.is_some() || kernel.initrd_info.is_some() {
+                    self.diagnostic(
+                        Severity::Info, label_name, "inspection-ignored",
+                        String::from("Inspection results are not part of \
+                                      configuration"));
+                }
+            },
+        };
+
+        if let Some(ref say) = label.say {
+            self.value(label_name, "SAY", say);
+        }
+        if let Some(ref display) = label.display {
+            self.path(label_name, "DISPLAY", display);
+        }
+        self.unknown_directives(label_name, &label.unknown_directives);
+        if let Some(ref text_help) = label.text_help {
+            if has_line_break(text_help) {
+                self.diagnostic(Severity::Warning, label_name,
+                                "text-help-line-break", String::from(
+                                    "Line breaks of TEXT HELP will be \
+                                     replaced with spaces"));
+            }
+        }
+    }
+
+    // Each of them can be a command line, whose first word is a label or
+    // else a kernel file. Whether the file exists is up to check_commands().
+    fn label_reference(&mut self, conf: &SyslinuxConf, tag: &str,
+                       command: &Option<String>) {
+        if let Some(ref command) = *command {
+            self.value(None, tag, command);
+        }
+        match command_label(command) {
+            Some(label_name) => if !conf.labels.contains_key(label_name) {
+                self.diagnostic(Severity::Warning, None, "unknown-label",
+                                format!("{} \"{}\" is not a label, so it is \
+                                         booted as kernel", tag, label_name));
+            },
+            None => if command.is_some() {
+                self.diagnostic(Severity::Error, None, "empty-command",
+                                format!("{} is empty", tag));
+            },
+        }
+    }
+
+    fn global(&mut self, conf: &SyslinuxConf) {
+        let global: &Global = &conf.global;
+
+        self.label_reference(conf, "DEFAULT", &global.default);
+        self.label_reference(conf, "ONTIMEOUT", &global.ontimeout);
+        self.label_reference(conf, "ONERROR", &global.onerror);
+
+        self.timeout("TIMEOUT", global.timeout);
+        self.timeout("TOTALTIMEOUT", global.total_timeout);
+
+        if let Some(ref menu_title) = global.menu_title {
+            self.value(None, "MENU TITLE", menu_title);
+        }
+        if let Some(ref ui) = global.ui {
+            self.value(None, "UI", ui);
+        }
+        self.unknown_directives(None, &global.unknown_directives);
+
+        self.label(None, &global.label_defaults);
+    }
+}
+
+// Checks that configuration can be written in syslinux syntax and read back
+// without changing its meaning. Meant for configurations which were not
+// produced by the parser, e.g. deserialized from JSON.
+pub fn validate(conf: &SyslinuxConf) -> Vec<Diagnostic> {
+    let mut validator = Validator{
+        diagnostics: Vec::new(),
+    };
+
+    validator.global(conf);
+    for (label_name, label) in conf.labels.iter() {
+        let label_name: &str = label_name;
+        if label_name.is_empty() {
+            validator.diagnostic(Severity::Error, Some(label_name),
+                                 "label-empty", String::from(
+                                     "Label name is empty"));
+        }
+        validator.value(Some(label_name), "LABEL", label_name);
+        validator.label(Some(label_name), label);
+    }
+
+    validator.diagnostics
+}
+
+struct FileChecker {
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl FileChecker {
+    fn file(&mut self, label_name: Option<&str>, tag: &str,
+            path: &std::path::Path) {
+        // Lists of initrds can not be checked after resolution.
+        if path.to_string_lossy().contains(',') {
+            return;
+        }
+        if !path.exists() {
+            let diagnostic = Diagnostic::new(
+                Severity::Error, "missing-file",
+                format!("{} {:?} does not exist", tag, path));
+            self.diagnostics.push(match label_name {
+                Some(label_name) => diagnostic.for_label(label_name),
+                None => diagnostic,
+            });
+        }
+    }
+
+    fn label(&mut self, label_name: Option<&str>, label: &Label) {
+        match label.kernel_or_config {
+            KernelOrConfig::Kernel(ref kernel) => {
+                if let Some(ref kernel_file) = kernel.kernel_file {
+                    self.file(label_name, "Kernel file", kernel_file.path());
+                }
+                if let Some(ref initrd) = kernel.initrd {
+                    self.file(label_name, "INITRD", initrd);
+                }
+                if let Some(ref fdt) = kernel.fdt {
+                    self.file(label_name, "FDT", fdt);
+                }
+                if let Some(ref fdt_dir) = kernel.fdt_dir {
+                    self.file(label_name, "FDTDIR", fdt_dir);
+                }
+                for fdt_overlay in &kernel.fdt_overlays {
+                    self.file(label_name, "FDTOVERLAYS", fdt_overlay);
+                }
+            },
+        };
+
+        if let Some(ref display) = label.display {
+            self.file(label_name, "DISPLAY", display);
+        }
+    }
+}
+
+// Checks that all files referenced by configuration exist. Paths in
+// configuration should be already resolved.
+pub fn check_files(conf: &SyslinuxConf) -> Vec<Diagnostic> {
+    let mut checker = FileChecker{
+        diagnostics: Vec::new(),
+    };
+
+    checker.label(None, &conf.global.label_defaults);
+    for (label_name, label) in conf.labels.iter() {
+        let label_name: &str = label_name;
+        checker.label(Some(label_name), label);
+    }
+
+    checker.diagnostics
+}
+
+// Checks that DEFAULT, ONTIMEOUT and ONERROR command lines which do not
+// start with a label name start with an existing kernel file. Path of the
+// kernel file is resolved in the same way as paths in configuration.
+pub fn check_commands<F>(conf: &SyslinuxConf, resolve: F) -> Vec<Diagnostic>
+        where F: Fn(&std::path::Path) -> std::path::PathBuf {
+    let global: &Global = &conf.global;
+    let commands = [("DEFAULT", &global.default),
+                    ("ONTIMEOUT", &global.ontimeout),
+                    ("ONERROR", &global.onerror)];
+
+    commands.iter()
+        .filter_map(|&(tag, command)| {
+            command_label(command).map(|label_name| (tag, label_name))
+        })
+        .filter(|&(_, label_name)| !conf.labels.contains_key(label_name))
+        .filter(|&(_, kernel)| !resolve(std::path::Path::new(kernel)).exists())
+        .map(|(tag, kernel)| Diagnostic::new(
+            Severity::Error, "unresolved-command",
+            format!("{} \"{}\" is neither a label nor an existing kernel \
+                     file", tag, kernel)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std;
+
+    use super::{check_commands, check_files, validate};
+    use reader::resolve_paths;
+    use test_util::{TempDir, parse};
+    use types::Severity;
+
+    const CONF: &'static str = "DEFAULT linux\n\
+                                LABEL linux\n\
+                                \x20 KERNEL vmlinuz\n\
+                                \x20 INITRD initrd.img\n";
+
+    fn diagnostics(text: &str) -> Vec<(Severity, String)> {
+        validate(&parse(text)).into_iter()
+            .map(|diagnostic| (diagnostic.severity, diagnostic.code))
+            .collect()
+    }
+
+    #[test]
+    fn accepts_parsed_configuration() {
+        assert!(diagnostics(CONF).is_empty());
+    }
+
+    #[test]
+    fn accepts_default_label_with_arguments() {
+        assert!(diagnostics("DEFAULT linux single\n\
+                             LABEL linux\n\
+                             \x20 KERNEL vmlinuz\n").is_empty());
+    }
+
+    #[test]
+    fn warns_about_default_command_line() {
+        assert_eq!(diagnostics("DEFAULT vmlinuz root=/dev/sda1\n\
+                                ONTIMEOUT rescue\n\
+                                LABEL linux\n\
+                                \x20 KERNEL vmlinuz\n"),
+                   vec![(Severity::Warning, String::from("unknown-label")),
+                        (Severity::Warning, String::from("unknown-label"))]);
+    }
+
+    #[test]
+    fn rejects_empty_default() {
+        let mut conf = parse(CONF);
+        conf.global.default = Some(String::new());
+
+        let codes: Vec<String> = validate(&conf).into_iter()
+            .filter(|diagnostic| diagnostic.severity == Severity::Error)
+            .map(|diagnostic| diagnostic.code)
+            .collect();
+        assert_eq!(codes, vec!["empty-command"]);
+    }
+
+    #[test]
+    fn rejects_line_breaks() {
+        let mut conf = parse(CONF);
+        conf.global.menu_title = Some(String::from("Boot\nmenu"));
+
+        let codes: Vec<String> = validate(&conf).into_iter()
+            .map(|diagnostic| diagnostic.code)
+            .collect();
+        assert_eq!(codes, vec!["value-line-break"]);
+    }
+
+    #[test]
+    fn checks_timeout_range() {
+        let mut conf = parse(CONF);
+        conf.global.timeout = Some(-1.0);
+        conf.global.total_timeout = Some(0.25);
+
+        let codes: Vec<String> = validate(&conf).into_iter()
+            .map(|diagnostic| diagnostic.code)
+            .collect();
+        assert_eq!(codes, vec!["timeout-range", "timeout-precision"]);
+    }
+
+    #[test]
+    fn checks_files() {
+        let dir = TempDir::new();
+        dir.write("vmlinuz", b"");
+        let conf = resolve_paths(parse(CONF), dir.path());
+
+        let diagnostics = check_files(&conf);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, "missing-file");
+        assert_eq!(diagnostics[0].label, Some(String::from("linux")));
+    }
+
+    #[test]
+    fn checks_command_kernel_files() {
+        let dir = TempDir::new();
+        dir.write("vmlinuz", b"");
+        let conf = parse("DEFAULT vmlinuz root=/dev/sda1\n\
+                          ONTIMEOUT linux\n\
+                          ONERROR rescue\n\
+                          LABEL linux\n\
+                          \x20 KERNEL vmlinuz\n");
+
+        let diagnostics = check_commands(&conf, |path| dir.path().join(path));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(diagnostics[0].code, "unresolved-command");
+        assert!(diagnostics[0].message.contains("ONERROR"));
+    }
+
+    #[test]
+    fn resolves_command_paths() {
+        let conf = parse("DEFAULT /boot/vmlinuz\n");
+        let resolved = std::cell::RefCell::new(Vec::new());
+
+        check_commands(&conf, |path| {
+            resolved.borrow_mut().push(path.to_path_buf());
+            path.to_path_buf()
+        });
+        assert_eq!(*resolved.borrow(),
+                   vec![std::path::PathBuf::from("/boot/vmlinuz")]);
+    }
+}
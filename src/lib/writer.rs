@@ -0,0 +1,275 @@
+use std;
+
+use types::{Global, KernelFile, KernelFileSource, KernelOrConfig, Label,
+            SyslinuxConf, UnknownDirective};
+
+const INDENT: &'static str = "    ";
+
+// Syslinux configuration is line-based, line breaks in values would change
+// the meaning of configuration.
+fn single_line(value: &str) -> String {
+    value.replace("\r", " ").replace("\n", " ")
+}
+
+fn path_to_string(path: &std::path::Path) -> String {
+    single_line(&path.to_string_lossy())
+}
+
+impl KernelFile {
+    // Tag which explicitly specifies type of kernel file.
+    pub fn tag(&self) -> &'static str {
+        match *self {
+            KernelFile::Linux(_)   => "LINUX",
+            KernelFile::Boot(_)    => "BOOT",
+            KernelFile::BSS(_)     => "BSS",
+            KernelFile::PXE(_)     => "PXE",
+            KernelFile::FDImage(_) => "FDIMAGE",
+            KernelFile::ComBoot(_) => "COMBOOT",
+            KernelFile::Com32(_)   => "COM32",
+        }
+    }
+}
+
+// Timeouts are written in units of 1/10s. Zero means no timeout at all in
+// syslinux, so shorter non-zero timeouts are rounded up.
+fn conv_timeout(timeout: f64) -> u32 {
+    match (timeout * 10.0).round() as u32 {
+        0 if timeout > 0.0 => 1,
+        timeout => timeout,
+    }
+}
+
+struct CfgWriter {
+    output: String,
+}
+
+impl CfgWriter {
+    fn line(&mut self, indent: &str, tag: &str, value: &str) {
+        self.output.push_str(indent);
+        self.output.push_str(tag);
+        if !value.is_empty() {
+            self.output.push(' ');
+            self.output.push_str(value);
+        }
+        self.output.push('\n');
+    }
+
+    fn unknown_directives(&mut self, indent: &str,
+                          directives: &[UnknownDirective]) {
+        for directive in directives {
+            self.line(indent, &single_line(&directive.keyword),
+                      &single_line(&directive.argument));
+        }
+    }
+
+    fn label(&mut self, indent: &str, label: &Label) {
+        match label.kernel_or_config {
+            KernelOrConfig::Kernel(ref kernel) => {
+                if let Some(ref kernel_file) = kernel.kernel_file {
+                    let tag = match kernel.kernel_file_source {
+                        Some(KernelFileSource::Extension) => "KERNEL",
+                        _ => kernel_file.tag(),
+                    };
+                    self.line(indent, tag, &path_to_string(kernel_file.path()));
+                }
+                if let Some(ref initrd) = kernel.initrd {
+                    self.line(indent, "INITRD", &path_to_string(initrd));
+                }
+                if let Some(ref fdt) = kernel.fdt {
+                    self.line(indent, "FDT", &path_to_string(fdt));
+                }
+                if let Some(ref fdt_dir) = kernel.fdt_dir {
+                    self.line(indent, "FDTDIR", &path_to_string(fdt_dir));
+                }
+                if !kernel.fdt_overlays.is_empty() {
+                    let fdt_overlays: Vec<String> = kernel.fdt_overlays.iter()
+                        .map(|path| path_to_string(path))
+                        .collect();
+                    self.line(indent, "FDTOVERLAYS", &fdt_overlays.join(" "));
+                }
+                if let Some(ref append) = kernel.append {
+                    self.line(indent, "APPEND", &single_line(append));
+                }
+            },
+        };
+
+        if let Some(ref say) = label.say {
+            self.line(indent, "SAY", &single_line(say));
+        }
+        if let Some(ref display) = label.display {
+            self.line(indent, "DISPLAY", &path_to_string(display));
+        }
+
+        // TEXT HELP block should start at the beginning of line.
+        if let Some(ref text_help) = label.text_help {
+            self.line("", "TEXT HELP", "");
+            for text_line in text_help.lines() {
+                self.line("", text_line, "");
+            }
+            self.line("", "ENDTEXT", "");
+        }
+
+        // Known fields do not keep their lines, so unknown directives are
+        // written after all of them in the order they were read. MENU BEGIN
+        // or MENU END end the label in syslinux anyway, so the label stays
+        // in its submenu.
+        self.unknown_directives(indent, &label.unknown_directives);
+    }
+
+    fn global(&mut self, global: &Global) {
+        if let Some(ref default) = global.default {
+            self.line("", "DEFAULT", &single_line(default));
+        }
+        if let Some(ref ontimeout) = global.ontimeout {
+            self.line("", "ONTIMEOUT", &single_line(ontimeout));
+        }
+        if let Some(ref onerror) = global.onerror {
+            self.line("", "ONERROR", &single_line(onerror));
+        }
+        if let Some(timeout) = global.timeout {
+            self.line("", "TIMEOUT", &format!("{}", conv_timeout(timeout)));
+        }
+        if let Some(total_timeout) = global.total_timeout {
+            self.line("", "TOTALTIMEOUT",
+                      &format!("{}", conv_timeout(total_timeout)));
+        }
+        if let Some(prompt) = global.prompt {
+            self.line("", "PROMPT", if prompt { "1" } else { "0" });
+        }
+        if let Some(ref ui) = global.ui {
+            self.line("", "UI", &single_line(ui));
+        }
+        if let Some(ref menu_title) = global.menu_title {
+            self.line("", "MENU TITLE", &single_line(menu_title));
+        }
+        self.label("", &global.label_defaults);
+        self.unknown_directives("", &global.unknown_directives);
+    }
+}
+
+// Paths are written as they are, so configuration should not be resolved.
+pub fn to_syslinux_cfg(conf: &SyslinuxConf) -> String {
+    let mut writer = CfgWriter{
+        output: String::new(),
+    };
+
+    writer.global(&conf.global);
+    for (label_name, label) in conf.labels.iter() {
+        writer.line("", "", "");
+        writer.line("", "LABEL", &single_line(label_name));
+        writer.label(INDENT, label);
+    }
+
+    writer.output
+}
+
+// Single LABEL block, e.g. to be appended to existing configuration.
+pub fn label_to_syslinux_cfg(label_name: &str, label: &Label) -> String {
+    let mut writer = CfgWriter{
+        output: String::new(),
+    };
+
+    writer.line("", "LABEL", &single_line(label_name));
+    writer.label(INDENT, label);
+
+    writer.output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::to_syslinux_cfg;
+    use test_util::parse;
+    use types::{Label, SyslinuxConf};
+
+    fn label<'a>(conf: &'a SyslinuxConf, label_name: &str) -> &'a Label {
+        conf.labels.get(label_name).unwrap()
+    }
+
+    #[test]
+    fn writes_configuration_back() {
+        let text = "DEFAULT linux\n\
+                    TIMEOUT 50\n\
+                    UI menu.c32\n\
+                    MENU TITLE Boot menu\n\
+                    \n\
+                    LABEL linux\n\
+                    \x20   KERNEL /vmlinuz\n\
+                    \x20   INITRD /initrd.img\n\
+                    \x20   APPEND root=/dev/sda1 ro\n\
+                    \n\
+                    LABEL memtest\n\
+                    \x20   COM32 /memtest.c32\n";
+        assert_eq!(to_syslinux_cfg(&parse(text)), text);
+    }
+
+    #[test]
+    fn keeps_short_timeout() {
+        let mut conf = parse("LABEL linux\n  LINUX /vmlinuz\n");
+        conf.global.timeout = Some(0.01);
+        conf.global.total_timeout = Some(0.0);
+
+        let text = to_syslinux_cfg(&conf);
+        assert!(text.starts_with("TIMEOUT 1\nTOTALTIMEOUT 0\n"), "{}", text);
+    }
+
+    #[test]
+    fn writes_lines_of_help_text() {
+        let mut conf = parse("LABEL linux\n  LINUX /vmlinuz\n");
+        conf.labels.get_mut("linux").unwrap().text_help =
+            Some(String::from("First line.\nSecond line."));
+
+        let text = to_syslinux_cfg(&conf);
+        assert!(text.ends_with("TEXT HELP\n\
+                                First line.\n\
+                                Second line.\n\
+                                ENDTEXT\n"), "{}", text);
+        // Lines of help text are joined by parser.
+        assert_eq!(label(&parse(&text), "linux").text_help,
+                   Some(String::from("First line. Second line.")));
+    }
+
+    #[test]
+    fn does_not_break_lines() {
+        let mut conf = parse("LABEL linux\n  LINUX /vmlinuz\n");
+        conf.labels.get_mut("linux").unwrap().say =
+            Some(String::from("Booting\nLABEL evil"));
+
+        let conf = parse(&to_syslinux_cfg(&conf));
+        assert_eq!(conf.labels.len(), 1);
+        assert_eq!(label(&conf, "linux").say,
+                   Some(String::from("Booting LABEL evil")));
+    }
+
+    #[test]
+    fn writes_unknown_directives_back() {
+        let text = "DEFAULT linux\n\
+                    SERIAL 0 115200\n\
+                    \n\
+                    LABEL linux\n\
+                    \x20   KERNEL /vmlinuz\n\
+                    \x20   IPAPPEND 2\n\
+                    \x20   SYSAPPEND\n";
+        assert_eq!(to_syslinux_cfg(&parse(text)), text);
+    }
+
+    #[test]
+    fn keeps_labels_in_submenus() {
+        let text = "DEFAULT linux\n\
+                    \n\
+                    LABEL linux\n\
+                    \x20   KERNEL /vmlinuz\n\
+                    \x20   MENU BEGIN tools\n\
+                    \n\
+                    LABEL memtest\n\
+                    \x20   COM32 /memtest.c32\n\
+                    TEXT HELP\n\
+                    Memory test.\n\
+                    ENDTEXT\n\
+                    \x20   MENU END\n\
+                    \n\
+                    LABEL rescue\n\
+                    \x20   KERNEL /vmlinuz\n\
+                    \x20   APPEND single\n";
+        assert_eq!(to_syslinux_cfg(&parse(text)), text);
+    }
+}